@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use crate::{color, config};
+
+const AUDIT_FILE: &str = "audit.jsonl";
+
+/// What happened to a suggestion
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Run as suggested
+    Executed,
+    /// Edited, then run
+    Edited,
+    /// Copied to the clipboard
+    Copied,
+    /// Printed with `--print` or `--output json`
+    Printed,
+    /// Declined
+    Cancelled,
+    /// The model couldn't suggest a command
+    Refused,
+    /// Not run, because it matches the blocklist or isn't in the allowlist
+    Blocked,
+}
+
+impl Action {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Action::Executed => "executed",
+            Action::Edited => "edited",
+            Action::Copied => "copied",
+            Action::Printed => "printed",
+            Action::Cancelled => "cancelled",
+            Action::Refused => "refused",
+            Action::Blocked => "blocked",
+        }
+    }
+}
+
+/// One line of the audit log
+#[derive(Serialize, Deserialize)]
+pub struct Entry {
+    pub timestamp: String,
+    pub cwd: String,
+    pub prompt: String,
+    pub model: String,
+    /// The model's final suggestion
+    pub suggestion: String,
+    pub action: Action,
+    /// The command that was run, if it was edited first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+}
+
+impl Entry {
+    pub fn new(prompt: &str, model: &str, suggestion: &str, action: Action) -> Self {
+        Entry {
+            timestamp: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+            cwd: std::env::current_dir().map(|cwd| cwd.display().to_string()).unwrap_or_default(),
+            prompt: prompt.to_string(),
+            model: model.to_string(),
+            suggestion: suggestion.to_string(),
+            action,
+            command: None,
+            exit_code: None,
+        }
+    }
+}
+
+fn audit_file() -> Option<PathBuf> {
+    Some(config::state_dir()?.join(AUDIT_FILE))
+}
+
+/// Append an entry to the audit log, warning (but carrying on) if it can't be written
+pub fn append(entry: &Entry) {
+    if let Err(e) = try_append(entry) {
+        eprintln!("Warning: Could not write to the audit log: {}", e);
+    }
+}
+
+fn try_append(entry: &Entry) -> Result<(), Box<dyn std::error::Error>> {
+    let path = audit_file().ok_or("Could not determine the state directory")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let line = format!("{}\n", serde_json::to_string(entry)?);
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&path)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Which entries `llm-exec log` shows
+pub struct Filter {
+    pub search: Option<String>,
+    pub action: Option<Action>,
+    pub failed: bool,
+    pub limit: usize,
+}
+
+impl Filter {
+    fn matches(&self, entry: &Entry) -> bool {
+        let search = self.search.as_deref().is_none_or(|text| {
+            entry.prompt.contains(text) || entry.suggestion.contains(text) || entry.command.as_deref().is_some_and(|c| c.contains(text))
+        });
+        let action = self.action.is_none_or(|action| entry.action == action);
+        let failed = !self.failed || entry.exit_code.is_some_and(|code| code != 0);
+        search && action && failed
+    }
+}
+
+/// Every entry in the audit log with the line it was read from, oldest first
+fn read_entries() -> Result<Vec<(String, Entry)>, Box<dyn std::error::Error>> {
+    let path = audit_file().ok_or("Could not determine the state directory")?;
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Could not read {}: {}", path.display(), e).into()),
+    };
+
+    let mut entries = Vec::new();
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line?;
+        // Skip lines that were cut short or written by a newer version
+        if let Ok(entry) = serde_json::from_str::<Entry>(&line) {
+            entries.push((line, entry));
+        }
+    }
+    Ok(entries)
+}
+
+/// The most recent entry with a command in it, for `--last`
+pub fn last_suggestion() -> Result<Option<Entry>, Box<dyn std::error::Error>> {
+    let entries = read_entries()?;
+    Ok(entries.into_iter().map(|(_, entry)| entry).rfind(|entry| entry.action != Action::Refused && !entry.suggestion.is_empty()))
+}
+
+/// `llm-exec history`: print the most recent distinct prompts and the commands suggested for them,
+/// oldest first, optionally only those containing `search`
+pub fn show_history(search: Option<&str>, limit: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    for (_, entry) in read_entries()? {
+        if entry.action == Action::Refused || entry.suggestion.is_empty() {
+            continue;
+        }
+        let command = entry.command.unwrap_or(entry.suggestion);
+        if search.is_some_and(|text| !entry.prompt.contains(text) && !command.contains(text)) {
+            continue;
+        }
+        // Keep only the latest of repeated pairs
+        pairs.retain(|(prompt, previous)| *prompt != entry.prompt || *previous != command);
+        pairs.push((entry.prompt, command));
+    }
+
+    let skip = pairs.len().saturating_sub(limit);
+    for (prompt, command) in pairs.into_iter().skip(skip) {
+        println!("{}", color::paint(color::DIM, prompt));
+        println!("  {}", color::paint(color::COMMAND, command));
+    }
+    Ok(())
+}
+
+/// `llm-exec log`: print the most recent matching entries, oldest first
+pub fn show(filter: &Filter, json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let entries: Vec<(String, Entry)> = read_entries()?.into_iter().filter(|(_, entry)| filter.matches(entry)).collect();
+
+    let skip = entries.len().saturating_sub(filter.limit);
+    for (line, entry) in entries.into_iter().skip(skip) {
+        if json {
+            println!("{}", line);
+            continue;
+        }
+
+        let status = match entry.exit_code {
+            Some(0) => format!(" {}", color::paint(color::GREEN, "exit 0")),
+            Some(code) => format!(" {}", color::paint(color::RED, format!("exit {}", code))),
+            None => String::new(),
+        };
+        let time = entry.timestamp.get(..19).unwrap_or(&entry.timestamp).replace('T', " ");
+        println!("{}  {}{}", color::paint(color::DIM, time), entry.action.as_str(), status);
+        println!("  {}", color::paint(color::COMMAND, entry.command.as_deref().unwrap_or(&entry.suggestion)));
+        println!("  {}", color::paint(color::DIM, format!("{} ({}, in {})", entry.prompt, entry.model, entry.cwd)));
+        println!();
+    }
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+/// Section headings, like "Suggested command:"
+pub const HEADING: &str = "1;36";
+/// Commands
+pub const COMMAND: &str = "1;33";
+/// Notes and other secondary text
+pub const DIM: &str = "2";
+pub const ERROR: &str = "1;31";
+pub const WARNING: &str = "1;33";
+pub const SUCCESS: &str = "1;32";
+pub const RED: &str = "31";
+pub const GREEN: &str = "32";
+pub const CYAN: &str = "36";
+pub const BOLD: &str = "1";
+
+/// When to color output
+#[derive(Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum ColorChoice {
+    /// When writing to a terminal, unless `NO_COLOR` is set
+    #[default]
+    Auto,
+    /// Even when not writing to a terminal
+    Always,
+    /// Never
+    Never,
+}
+
+/// Whether stdout and stderr are colored
+struct Enabled {
+    stdout: bool,
+    stderr: bool,
+}
+
+static ENABLED: OnceLock<Enabled> = OnceLock::new();
+
+/// Set the color policy. Only the first call has any effect; before it, `Auto` is assumed.
+pub fn init(choice: ColorChoice) {
+    let _ = ENABLED.set(detect(choice));
+}
+
+fn detect(choice: ColorChoice) -> Enabled {
+    let auto = |terminal: bool| terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && std::env::var("TERM").as_deref() != Ok("dumb");
+    match choice {
+        ColorChoice::Auto => Enabled { stdout: auto(std::io::stdout().is_terminal()), stderr: auto(std::io::stderr().is_terminal()) },
+        ColorChoice::Always => Enabled { stdout: true, stderr: true },
+        ColorChoice::Never => Enabled { stdout: false, stderr: false },
+    }
+}
+
+fn enabled() -> &'static Enabled {
+    ENABLED.get_or_init(|| detect(ColorChoice::Auto))
+}
+
+/// The text in the given color, for printing to stdout
+pub fn paint(color: &str, text: impl std::fmt::Display) -> String {
+    wrap(enabled().stdout, color, text)
+}
+
+/// The text in the given color, for printing to stderr
+pub fn epaint(color: &str, text: impl std::fmt::Display) -> String {
+    wrap(enabled().stderr, color, text)
+}
+
+/// The escape that starts the color on stdout, for text printed a piece at a time; end it with `reset()`
+pub fn start(color: &str) -> String {
+    if enabled().stdout {
+        format!("\x1b[{}m", color)
+    } else {
+        String::new()
+    }
+}
+
+/// The escape that ends a color started with `start()`
+pub fn reset() -> &'static str {
+    if enabled().stdout {
+        "\x1b[0m"
+    } else {
+        ""
+    }
+}
+
+fn wrap(enabled: bool, color: &str, text: impl std::fmt::Display) -> String {
+    if enabled {
+        format!("\x1b[{}m{}\x1b[0m", color, text)
+    } else {
+        text.to_string()
+    }
+}
@@ -0,0 +1,882 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Name of llm-exec's directory inside the config directory
+const APP_DIR: &str = "llm-exec";
+/// Where the config used to live on every platform, still read if nothing is in the new location
+const LEGACY_CONFIG_DIR: &str = ".config/llm-exec";
+/// Project-local config, found by walking up from the current directory
+const PROJECT_CONFIG_FILE: &str = ".llm-exec.toml";
+/// Environment variable selecting a profile when `--profile` isn't given
+const PROFILE_ENV: &str = "LLM_EXEC_PROFILE";
+/// Prefix of the environment variables that override config keys, e.g. `LLM_EXEC_MODEL`
+const ENV_PREFIX: &str = "LLM_EXEC_";
+
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct Config {
+    /// LLM provider to use: "anthropic" (default), "openai", "ollama", "gemini", "bedrock", or "custom"
+    pub provider: Option<String>,
+    /// Base URL of the provider's API (used by openai, ollama, and custom)
+    pub base_url: Option<String>,
+    /// Environment variable holding the API key (used by anthropic, openai, gemini, and custom)
+    pub api_key_env: Option<String>,
+    /// Command that prints the API key, e.g. `op read op://...` (used when the environment variable isn't set)
+    pub api_key_cmd: Option<String>,
+    /// Extra HTTP headers to send with each request (used by openai and custom)
+    pub headers: Option<HashMap<String, String>>,
+    /// Proxy for all requests, e.g. `http://proxy.corp:3128` (default: HTTPS_PROXY/HTTP_PROXY, minus NO_PROXY)
+    pub proxy: Option<String>,
+    /// PEM file of extra root certificates to trust, e.g. for a TLS-intercepting proxy
+    pub ca_bundle: Option<PathBuf>,
+    /// AWS region for the bedrock provider (default: AWS_REGION, ~/.aws/config, then us-east-1)
+    pub aws_region: Option<String>,
+    /// AWS profile for the bedrock provider (default: AWS_PROFILE, then "default")
+    pub aws_profile: Option<String>,
+    /// Model to use, or an alias for one
+    pub model: Option<String>,
+    /// Names for models, e.g. `fast = "claude-haiku-4-5-20251001"`, in addition to the provider's
+    /// built-in ones (`haiku`, `sonnet`, and `opus` for Anthropic and Bedrock)
+    pub model_aliases: Option<HashMap<String, String>>,
+    /// Providers to try in order, as `provider` or `provider:model`, when a request fails with an
+    /// authentication, rate limit, or availability error, e.g. `["openai:gpt-4o-mini", "ollama"]`
+    pub fallbacks: Option<Vec<String>>,
+    /// Max tokens for response
+    pub max_tokens: Option<u32>,
+    /// Sampling temperature; lower is more deterministic (default: the provider's)
+    pub temperature: Option<f64>,
+    /// Nucleus sampling: only consider the most likely tokens making up this probability (default: the provider's)
+    pub top_p: Option<f64>,
+    /// Only consider this many of the most likely tokens; not supported by OpenAI (default: the provider's)
+    pub top_k: Option<u32>,
+    /// Tokens Anthropic and Bedrock models may spend thinking before they answer; 0 turns extended thinking off (default: 0)
+    pub thinking_budget: Option<u32>,
+    /// Attempts per request, retrying rate limits and server errors (default: 4)
+    pub max_attempts: Option<u32>,
+    /// Seconds to wait for each request, including retries; 0 waits indefinitely (default: 60)
+    pub timeout_secs: Option<u64>,
+    /// Number of history lines to include
+    pub history_lines: Option<usize>,
+    /// Of those, send only this many that are most relevant to the prompt (plus the few most recent); 0 sends them all (default: 0)
+    pub relevant_history_lines: Option<usize>,
+    /// Only include history from the current directory; needs atuin, which records where commands ran (default: false)
+    pub directory_history: Option<bool>,
+    /// Where to read history from and add commands to: "bash", "zsh", "fish", "powershell", "atuin", or "auto" to detect it (default: "auto")
+    pub history_backend: Option<String>,
+    /// Take repeated commands, runs of `cd`s, and bare `ls`s and the like out of the history when it's long (default: true)
+    pub compress_history: Option<bool>,
+    /// Model (or `provider:model`) that summarizes the history when it's long, to send instead of it
+    pub history_summary_model: Option<String>,
+    /// Estimated tokens of history and other context to send at most, trimming the oldest lines to fit; 0 disables (default: 8000)
+    pub max_context_tokens: Option<usize>,
+    /// Additional instructions to append to the system prompt
+    pub system_prompt_suffix: Option<String>,
+    /// Complete override of the system prompt (replaces default)
+    pub system_prompt: Option<String>,
+    /// Have the model reply by calling a `suggest_command` tool with the command, an explanation,
+    /// and its risk, instead of replying with just the command (default: false)
+    pub structured_output: Option<bool>,
+    /// Context files to look for in cwd and parent directories (default: ["CLAUDE.md", "AGENTS.md"])
+    pub context_files: Option<Vec<String>>,
+    /// Shell to generate commands for and run them with (default: $SHELL, or pwsh on Windows)
+    pub shell: Option<String>,
+    /// Ask the provider to cache the system prompt between requests, where supported (default: true)
+    pub prompt_cache: Option<bool>,
+    /// Also cache the shell history block, for repeated requests in the same session (default: false)
+    pub prompt_cache_history: Option<bool>,
+    /// Reuse the response to an identical request made within this many seconds; 0 disables (default: 300)
+    pub cache_ttl_secs: Option<u64>,
+    /// Regex patterns for commands that are never run without `--force`
+    pub blocklist: Option<Vec<String>>,
+    /// Replace secrets in shell history, context, and command output before sending them (default: true)
+    pub redact: Option<bool>,
+    /// Regex patterns for secrets to redact, in addition to the built-in ones
+    pub redact_patterns: Option<Vec<String>>,
+    /// Record every suggestion and what happened to it in the audit log (default: true)
+    pub audit_log: Option<bool>,
+    /// File to append a detailed trace of each request to
+    pub log_file: Option<PathBuf>,
+    /// If set, only commands starting with one of these words (e.g. "git", "kubectl get") can be run
+    pub allowlist: Option<Vec<String>>,
+    /// Times to offer asking the model to fix a command that failed; 0 disables (default: 3)
+    pub max_fix_attempts: Option<u32>,
+    /// What pressing Enter does at the confirmation menu: "no", "yes", "edit", "copy", or "explain" (default: "no")
+    pub default_action: Option<String>,
+    /// Prompts with `{placeholder}`s to fill in, by name, used with `--template`
+    pub templates: Option<HashMap<String, String>>,
+    /// Built-in tools the model can call while generating commands
+    #[serde(default)]
+    pub tools: ToolsConfig,
+    /// MCP servers whose tools and resources the model can use while generating commands, by name
+    pub mcp_servers: Option<HashMap<String, McpServerConfig>>,
+    /// Which kinds of extra context to include in the request
+    #[serde(default)]
+    pub context: ContextConfig,
+    /// How `--sandbox` runs commands in a container
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    /// How commands of each risk level are confirmed before they run
+    #[serde(default)]
+    pub confirm: ConfirmConfig,
+    /// Limits for `--agent` mode
+    #[serde(default)]
+    pub agent: AgentConfig,
+    /// Named sets of overrides, selected with `--profile` or `LLM_EXEC_PROFILE`
+    #[serde(default)]
+    pub profiles: HashMap<String, Config>,
+}
+
+impl Config {
+    /// Overlay `other` on top of this config: every key set in `other` wins,
+    /// except `blocklist` and `redact_patterns`, whose patterns are added to the ones already set,
+    /// and `templates` and `model_aliases`, which are added to the ones already defined.
+    pub fn merge(self, other: Config) -> Config {
+        let mut profiles = self.profiles;
+        profiles.extend(other.profiles);
+
+        let templates = match (self.templates, other.templates) {
+            (Some(mut templates), Some(more)) => {
+                templates.extend(more);
+                Some(templates)
+            }
+            (templates, more) => more.or(templates),
+        };
+
+        let model_aliases = match (self.model_aliases, other.model_aliases) {
+            (Some(mut aliases), Some(more)) => {
+                aliases.extend(more);
+                Some(aliases)
+            }
+            (aliases, more) => more.or(aliases),
+        };
+
+        let blocklist = match (self.blocklist, other.blocklist) {
+            (Some(mut patterns), Some(more)) => {
+                patterns.extend(more);
+                Some(patterns)
+            }
+            (patterns, more) => more.or(patterns),
+        };
+
+        let redact_patterns = match (self.redact_patterns, other.redact_patterns) {
+            (Some(mut patterns), Some(more)) => {
+                patterns.extend(more);
+                Some(patterns)
+            }
+            (patterns, more) => more.or(patterns),
+        };
+
+        Config {
+            provider: other.provider.or(self.provider),
+            base_url: other.base_url.or(self.base_url),
+            api_key_env: other.api_key_env.or(self.api_key_env),
+            api_key_cmd: other.api_key_cmd.or(self.api_key_cmd),
+            headers: other.headers.or(self.headers),
+            proxy: other.proxy.or(self.proxy),
+            ca_bundle: other.ca_bundle.or(self.ca_bundle),
+            aws_region: other.aws_region.or(self.aws_region),
+            aws_profile: other.aws_profile.or(self.aws_profile),
+            model: other.model.or(self.model),
+            model_aliases,
+            fallbacks: other.fallbacks.or(self.fallbacks),
+            max_tokens: other.max_tokens.or(self.max_tokens),
+            temperature: other.temperature.or(self.temperature),
+            top_p: other.top_p.or(self.top_p),
+            top_k: other.top_k.or(self.top_k),
+            thinking_budget: other.thinking_budget.or(self.thinking_budget),
+            max_attempts: other.max_attempts.or(self.max_attempts),
+            timeout_secs: other.timeout_secs.or(self.timeout_secs),
+            history_lines: other.history_lines.or(self.history_lines),
+            relevant_history_lines: other.relevant_history_lines.or(self.relevant_history_lines),
+            directory_history: other.directory_history.or(self.directory_history),
+            history_backend: other.history_backend.or(self.history_backend),
+            compress_history: other.compress_history.or(self.compress_history),
+            history_summary_model: other.history_summary_model.or(self.history_summary_model),
+            max_context_tokens: other.max_context_tokens.or(self.max_context_tokens),
+            system_prompt_suffix: other.system_prompt_suffix.or(self.system_prompt_suffix),
+            system_prompt: other.system_prompt.or(self.system_prompt),
+            structured_output: other.structured_output.or(self.structured_output),
+            context_files: other.context_files.or(self.context_files),
+            shell: other.shell.or(self.shell),
+            prompt_cache: other.prompt_cache.or(self.prompt_cache),
+            prompt_cache_history: other.prompt_cache_history.or(self.prompt_cache_history),
+            cache_ttl_secs: other.cache_ttl_secs.or(self.cache_ttl_secs),
+            blocklist,
+            redact: other.redact.or(self.redact),
+            redact_patterns,
+            audit_log: other.audit_log.or(self.audit_log),
+            log_file: other.log_file.or(self.log_file),
+            max_fix_attempts: other.max_fix_attempts.or(self.max_fix_attempts),
+            default_action: other.default_action.or(self.default_action),
+            templates,
+            allowlist: other.allowlist.or(self.allowlist),
+            tools: self.tools.merge(other.tools),
+            mcp_servers: other.mcp_servers.or(self.mcp_servers),
+            context: self.context.merge(other.context),
+            sandbox: self.sandbox.merge(other.sandbox),
+            confirm: self.confirm.merge(other.confirm),
+            agent: self.agent.merge(other.agent),
+            profiles,
+        }
+    }
+
+    /// Drop keys a project config may not set, since it may come from an untrusted repository:
+    /// anything that could send the API key elsewhere or change what runs the commands.
+    fn restrict_to_project_keys(mut self, path: &Path) -> Config {
+        let mut ignored = Vec::new();
+        if self.base_url.take().is_some() {
+            ignored.push("base_url");
+        }
+        if self.api_key_env.take().is_some() {
+            ignored.push("api_key_env");
+        }
+        if self.api_key_cmd.take().is_some() {
+            ignored.push("api_key_cmd");
+        }
+        if self.headers.take().is_some() {
+            ignored.push("headers");
+        }
+        if self.proxy.take().is_some() {
+            ignored.push("proxy");
+        }
+        if self.ca_bundle.take().is_some() {
+            ignored.push("ca_bundle");
+        }
+        if self.aws_profile.take().is_some() {
+            ignored.push("aws_profile");
+        }
+        if self.shell.take().is_some() {
+            ignored.push("shell");
+        }
+        if self.allowlist.take().is_some() {
+            ignored.push("allowlist");
+        }
+        if self.audit_log.take().is_some() {
+            ignored.push("audit_log");
+        }
+        if self.log_file.take().is_some() {
+            ignored.push("log_file");
+        }
+        if self.redact.take().is_some() {
+            ignored.push("redact");
+        }
+        if self.default_action.take().is_some() {
+            ignored.push("default_action");
+        }
+        if self.mcp_servers.take().is_some() {
+            ignored.push("mcp_servers");
+        }
+        if self.context.commands.take().is_some() {
+            ignored.push("context.commands");
+        }
+        if self.context.files.take().is_some() {
+            ignored.push("context.files");
+        }
+        let sandbox = std::mem::take(&mut self.sandbox);
+        if sandbox.runtime.is_some() || sandbox.image.is_some() || sandbox.writable.is_some() {
+            ignored.push("sandbox");
+        }
+        let confirm = std::mem::take(&mut self.confirm);
+        if confirm.low.is_some() || confirm.medium.is_some() || confirm.high.is_some() {
+            ignored.push("confirm");
+        }
+        if self.agent.auto_approve_low_risk.take().is_some() {
+            ignored.push("agent.auto_approve_low_risk");
+        }
+        if !self.profiles.is_empty() {
+            self.profiles.clear();
+            ignored.push("profiles");
+        }
+
+        if !ignored.is_empty() {
+            eprintln!(
+                "Warning: Ignoring {} in {} (only allowed in your own config)",
+                ignored.join(", "),
+                path.display()
+            );
+        }
+        self
+    }
+
+    /// Apply the named profile's overrides on top of this config.
+    pub fn with_profile(mut self, name: &str) -> Result<Config, Box<dyn std::error::Error>> {
+        let Some(profile) = self.profiles.remove(name) else {
+            let mut available: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            available.sort();
+            return Err(if available.is_empty() {
+                format!("Unknown profile '{}' (no profiles are configured)", name)
+            } else {
+                format!("Unknown profile '{}' (available: {})", name, available.join(", "))
+            }
+            .into());
+        };
+
+        Ok(self.merge(profile))
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct ContextConfig {
+    /// Include OS, distribution, kernel, and architecture details (default: false)
+    pub os: Option<bool>,
+    /// Include a listing of the current directory (default: false)
+    pub cwd: Option<bool>,
+    /// Include branch, remotes, status, and recent commits when inside a git repository (default: false)
+    pub git: Option<bool>,
+    /// Include the current tmux pane's recent scrollback when running inside tmux (default: false)
+    pub tmux: Option<bool>,
+    /// Include the previous command and exit status recorded by the shell integration (default: true)
+    pub last_command: Option<bool>,
+    /// Include recent shell history (default: true)
+    pub history: Option<bool>,
+    /// Include the tldr page or man page synopsis of installed tools the prompt mentions (default: false)
+    pub tool_docs: Option<bool>,
+    /// Include which common tools (rg, fd, jq, docker, ...) are installed (default: false)
+    pub installed_tools: Option<bool>,
+    /// Include the aliases defined by the user's shell (default: false)
+    pub aliases: Option<bool>,
+    /// Include the current kubectl context, namespace, and pods (default: false)
+    pub kubernetes: Option<bool>,
+    /// Include running containers and the services in the nearest Compose file (default: false)
+    pub docker: Option<bool>,
+    /// Names of context providers to include first, in this order; the rest follow in the default order
+    pub order: Option<Vec<String>>,
+    /// Extra context providers: name -> shell command whose output is included
+    pub commands: Option<HashMap<String, String>>,
+    /// Files whose contents are included, cut down to their start and end if large
+    pub files: Option<Vec<String>>,
+}
+
+impl ContextConfig {
+    fn merge(self, other: ContextConfig) -> ContextConfig {
+        ContextConfig {
+            os: other.os.or(self.os),
+            cwd: other.cwd.or(self.cwd),
+            git: other.git.or(self.git),
+            tmux: other.tmux.or(self.tmux),
+            last_command: other.last_command.or(self.last_command),
+            history: other.history.or(self.history),
+            tool_docs: other.tool_docs.or(self.tool_docs),
+            installed_tools: other.installed_tools.or(self.installed_tools),
+            aliases: other.aliases.or(self.aliases),
+            kubernetes: other.kubernetes.or(self.kubernetes),
+            docker: other.docker.or(self.docker),
+            order: other.order.or(self.order),
+            commands: other.commands.or(self.commands),
+            files: other.files.or(self.files),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct ToolsConfig {
+    /// Let the model list directories within the current one (default: false)
+    pub list_dir: Option<bool>,
+    /// Let the model read the first lines of files within the current directory (default: false)
+    pub read_file_head: Option<bool>,
+    /// Ask before the model reads each file (default: true)
+    pub confirm: Option<bool>,
+}
+
+impl ToolsConfig {
+    fn merge(self, other: ToolsConfig) -> ToolsConfig {
+        ToolsConfig {
+            list_dir: other.list_dir.or(self.list_dir),
+            read_file_head: other.read_file_head.or(self.read_file_head),
+            confirm: other.confirm.or(self.confirm),
+        }
+    }
+}
+
+/// An MCP server that llm-exec starts and talks to over its stdin and stdout
+#[derive(Deserialize, Serialize, Clone)]
+pub struct McpServerConfig {
+    /// Program that runs the server
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Extra environment variables for the server
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Only offer the model these tools (default: all of the server's tools)
+    pub tools: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct SandboxConfig {
+    /// Container runtime to use (default: docker, or podman if docker isn't installed)
+    pub runtime: Option<String>,
+    /// Image to run commands in (default: ubuntu)
+    pub image: Option<String>,
+    /// Mount the current directory read-write instead of read-only (default: false)
+    pub writable: Option<bool>,
+}
+
+impl SandboxConfig {
+    fn merge(self, other: SandboxConfig) -> SandboxConfig {
+        SandboxConfig {
+            runtime: other.runtime.or(self.runtime),
+            image: other.image.or(self.image),
+            writable: other.writable.or(self.writable),
+        }
+    }
+}
+
+/// Each risk level's confirmation policy: "auto", "confirm", or "type-to-confirm"
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct ConfirmConfig {
+    /// Policy for LOW risk commands (default: "auto")
+    pub low: Option<String>,
+    /// Policy for MEDIUM risk commands (default: "auto")
+    pub medium: Option<String>,
+    /// Policy for HIGH risk commands (default: "type-to-confirm")
+    pub high: Option<String>,
+}
+
+impl ConfirmConfig {
+    fn merge(self, other: ConfirmConfig) -> ConfirmConfig {
+        ConfirmConfig {
+            low: other.low.or(self.low),
+            medium: other.medium.or(self.medium),
+            high: other.high.or(self.high),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct AgentConfig {
+    /// Most commands `--agent` runs for one task (default: 10)
+    pub max_steps: Option<u32>,
+    /// Run LOW risk commands without asking (default: false)
+    pub auto_approve_low_risk: Option<bool>,
+}
+
+impl AgentConfig {
+    fn merge(self, other: AgentConfig) -> AgentConfig {
+        AgentConfig {
+            max_steps: other.max_steps.or(self.max_steps),
+            auto_approve_low_risk: other.auto_approve_low_risk.or(self.auto_approve_low_risk),
+        }
+    }
+}
+
+/// Directory holding the user's config file: `$XDG_CONFIG_HOME/llm-exec` if set, otherwise the
+/// platform's config directory (`~/.config` on Linux, `~/Library/Application Support` on macOS,
+/// `%APPDATA%` on Windows).
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(xdg) = std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(xdg).join(APP_DIR));
+    }
+    Some(dirs::config_dir()?.join(APP_DIR))
+}
+
+/// Directory for llm-exec's logs and other state: `$XDG_STATE_HOME/llm-exec` if set, otherwise
+/// `~/.local/state` on Linux, or the platform's local data directory elsewhere.
+pub fn state_dir() -> Option<PathBuf> {
+    if let Some(xdg) = std::env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(xdg).join(APP_DIR));
+    }
+    Some(dirs::state_dir().or_else(dirs::data_local_dir)?.join(APP_DIR))
+}
+
+/// Directory for data that can be thrown away, like cached responses: `$XDG_CACHE_HOME/llm-exec`
+/// if set, otherwise the platform's cache directory (`~/.cache` on Linux).
+pub fn cache_dir() -> Option<PathBuf> {
+    if let Some(xdg) = std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(xdg).join(APP_DIR));
+    }
+    Some(dirs::cache_dir()?.join(APP_DIR))
+}
+
+/// Build the effective config from every layer, each overriding the ones before it:
+/// the user's config file, the nearest project config, the selected profile,
+/// `LLM_EXEC_*` environment variables, and finally `cli` (from command-line flags).
+///
+/// Problems in the config files are reported as warnings, or as an error if `strict`.
+pub fn load(profile: Option<&str>, cli: Config, strict: bool) -> Result<Config, Box<dyn std::error::Error>> {
+    let mut config = match user_config_file() {
+        Some(path) if path.exists() => load_file(&path, strict)?,
+        _ => Config::default(),
+    };
+
+    if let Some(path) = find_project_config() {
+        let project = load_file(&path, strict)?.restrict_to_project_keys(&path);
+        config = config.merge(project);
+    }
+
+    let profile = profile.map(str::to_string).or_else(|| std::env::var(PROFILE_ENV).ok());
+    if let Some(profile) = profile {
+        config = config.with_profile(&profile)?;
+    }
+
+    let mut config = config.merge(from_env()?).merge(cli);
+    config.model = config.model.take().map(|model| resolve_model(&config, model));
+    Ok(config)
+}
+
+/// The model ID `model` stands for: a `model_aliases` entry, then the provider's built-in aliases
+pub(crate) fn resolve_model(config: &Config, model: String) -> String {
+    let model = config.model_aliases.as_ref().and_then(|aliases| aliases.get(&model)).cloned().unwrap_or(model);
+    let provider = config.provider.as_deref().unwrap_or(crate::providers::DEFAULT_PROVIDER);
+    crate::providers::builtin_model_alias(provider, &model).map_or(model, str::to_string)
+}
+
+/// Config keys set through `LLM_EXEC_*` environment variables.
+/// Nested keys use underscores (`LLM_EXEC_CONTEXT_GIT`), lists are comma-separated,
+/// and headers are comma-separated `Name=value` pairs.
+fn from_env() -> Result<Config, Box<dyn std::error::Error>> {
+    Ok(Config {
+        provider: env_value("PROVIDER")?,
+        base_url: env_value("BASE_URL")?,
+        api_key_env: env_value("API_KEY_ENV")?,
+        api_key_cmd: env_value("API_KEY_CMD")?,
+        headers: env_value::<String>("HEADERS")?.map(|headers| parse_headers(&headers)).transpose()?,
+        proxy: env_value("PROXY")?,
+        ca_bundle: env_value("CA_BUNDLE")?,
+        aws_region: env_value("AWS_REGION")?,
+        aws_profile: env_value("AWS_PROFILE")?,
+        model: env_value("MODEL")?,
+        model_aliases: None,
+        fallbacks: env_value::<String>("FALLBACKS")?
+            .map(|entries| entries.split(',').map(|e| e.trim().to_string()).filter(|e| !e.is_empty()).collect()),
+        max_tokens: env_value("MAX_TOKENS")?,
+        temperature: env_value("TEMPERATURE")?,
+        top_p: env_value("TOP_P")?,
+        top_k: env_value("TOP_K")?,
+        thinking_budget: env_value("THINKING_BUDGET")?,
+        max_attempts: env_value("MAX_ATTEMPTS")?,
+        timeout_secs: env_value("TIMEOUT_SECS")?,
+        history_lines: env_value("HISTORY_LINES")?,
+        relevant_history_lines: env_value("RELEVANT_HISTORY_LINES")?,
+        directory_history: env_value("DIRECTORY_HISTORY")?,
+        history_backend: env_value("HISTORY_BACKEND")?,
+        compress_history: env_value("COMPRESS_HISTORY")?,
+        history_summary_model: env_value("HISTORY_SUMMARY_MODEL")?,
+        max_context_tokens: env_value("MAX_CONTEXT_TOKENS")?,
+        system_prompt_suffix: env_value("SYSTEM_PROMPT_SUFFIX")?,
+        system_prompt: env_value("SYSTEM_PROMPT")?,
+        structured_output: env_value("STRUCTURED_OUTPUT")?,
+        context_files: env_value::<String>("CONTEXT_FILES")?
+            .map(|files| files.split(',').map(|f| f.trim().to_string()).filter(|f| !f.is_empty()).collect()),
+        shell: env_value("SHELL")?,
+        prompt_cache: env_value("PROMPT_CACHE")?,
+        prompt_cache_history: env_value("PROMPT_CACHE_HISTORY")?,
+        cache_ttl_secs: env_value("CACHE_TTL_SECS")?,
+        blocklist: env_value::<String>("BLOCKLIST")?
+            .map(|patterns| patterns.split(',').map(|p| p.trim().to_string()).filter(|p| !p.is_empty()).collect()),
+        redact: env_value("REDACT")?,
+        redact_patterns: None,
+        audit_log: env_value("AUDIT_LOG")?,
+        max_fix_attempts: env_value("MAX_FIX_ATTEMPTS")?,
+        default_action: env_value("DEFAULT_ACTION")?,
+        templates: None,
+        log_file: env_value("LOG_FILE")?,
+        allowlist: env_value::<String>("ALLOWLIST")?
+            .map(|commands| commands.split(',').map(|c| c.trim().to_string()).filter(|c| !c.is_empty()).collect()),
+        tools: ToolsConfig {
+            list_dir: env_value("TOOLS_LIST_DIR")?,
+            read_file_head: env_value("TOOLS_READ_FILE_HEAD")?,
+            confirm: env_value("TOOLS_CONFIRM")?,
+        },
+        mcp_servers: None,
+        context: ContextConfig {
+            os: env_value("CONTEXT_OS")?,
+            cwd: env_value("CONTEXT_CWD")?,
+            git: env_value("CONTEXT_GIT")?,
+            tmux: env_value("CONTEXT_TMUX")?,
+            last_command: env_value("CONTEXT_LAST_COMMAND")?,
+            history: env_value("CONTEXT_HISTORY")?,
+            tool_docs: env_value("CONTEXT_TOOL_DOCS")?,
+            installed_tools: env_value("CONTEXT_INSTALLED_TOOLS")?,
+            aliases: env_value("CONTEXT_ALIASES")?,
+            kubernetes: env_value("CONTEXT_KUBERNETES")?,
+            docker: env_value("CONTEXT_DOCKER")?,
+            order: env_value::<String>("CONTEXT_ORDER")?
+                .map(|names| names.split(',').map(|n| n.trim().to_string()).filter(|n| !n.is_empty()).collect()),
+            commands: None,
+            files: None,
+        },
+        sandbox: SandboxConfig {
+            runtime: env_value("SANDBOX_RUNTIME")?,
+            image: env_value("SANDBOX_IMAGE")?,
+            writable: env_value("SANDBOX_WRITABLE")?,
+        },
+        confirm: ConfirmConfig {
+            low: env_value("CONFIRM_LOW")?,
+            medium: env_value("CONFIRM_MEDIUM")?,
+            high: env_value("CONFIRM_HIGH")?,
+        },
+        agent: AgentConfig {
+            max_steps: env_value("AGENT_MAX_STEPS")?,
+            auto_approve_low_risk: env_value("AGENT_AUTO_APPROVE_LOW_RISK")?,
+        },
+        profiles: HashMap::new(),
+    })
+}
+
+/// Parse `LLM_EXEC_<key>`, treating an unset or empty variable as not set
+fn env_value<T: FromStr>(key: &str) -> Result<Option<T>, Box<dyn std::error::Error>>
+where
+    T::Err: std::fmt::Display,
+{
+    let name = format!("{}{}", ENV_PREFIX, key);
+    match std::env::var(&name) {
+        Ok(value) if !value.is_empty() => value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|e| format!("Invalid {}: {}", name, e).into()),
+        _ => Ok(None),
+    }
+}
+
+/// Compile a regex from the config key `key`, such as a `blocklist` entry. Regex errors span
+/// several lines pointing at the problem; only the last one, which says what it is, is kept.
+pub(crate) fn pattern(key: &str, pattern: &str) -> Result<regex::Regex, String> {
+    regex::Regex::new(pattern).map_err(|e| {
+        let message = e.to_string();
+        let reason = message.lines().last().unwrap_or_default().trim_start_matches("error: ").to_string();
+        format!("Invalid {} pattern '{}': {}", key, pattern, reason)
+    })
+}
+
+fn parse_headers(headers: &str) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    headers
+        .split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            let (name, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid {}HEADERS entry '{}' (expected Name=value)", ENV_PREFIX, pair))?;
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// The nearest `.llm-exec.toml` in the current directory or its parents
+fn find_project_config() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// The user's config file: `config.toml` in the config directory, falling back to
+/// `config.json`. If neither exists, the path where `config.toml` would go.
+/// Falls back to `~/.config/llm-exec` for configs created before the platform directory was used.
+pub fn user_config_file() -> Option<PathBuf> {
+    let dir = config_dir()?;
+    if let Some(path) = existing_config_file(&dir) {
+        return Some(path);
+    }
+
+    let legacy = dirs::home_dir().map(|home| home.join(LEGACY_CONFIG_DIR));
+    if let Some(path) = legacy.filter(|legacy| *legacy != dir).and_then(|legacy| existing_config_file(&legacy)) {
+        return Some(path);
+    }
+
+    Some(dir.join("config.toml"))
+}
+
+/// `config.toml` or `config.json` in `dir`, preferring TOML
+fn existing_config_file(dir: &Path) -> Option<PathBuf> {
+    let toml_path = dir.join("config.toml");
+    let json_path = dir.join("config.json");
+
+    if toml_path.exists() {
+        if json_path.exists() {
+            eprintln!("Warning: Ignoring {} because {} exists", json_path.display(), toml_path.display());
+        }
+        return Some(toml_path);
+    }
+    json_path.exists().then_some(json_path)
+}
+
+/// Load a config file. Unknown keys are skipped and a file that can't be read or parsed
+/// counts as empty, with a warning for each; in `strict` mode any problem is an error.
+fn load_file(path: &Path, strict: bool) -> Result<Config, Box<dyn std::error::Error>> {
+    let (config, problems) = match std::fs::read_to_string(path) {
+        Ok(content) => match parse_lenient(path, &content) {
+            Ok((config, unknown)) => (config, unknown.iter().map(|key| unknown_key_message(key)).collect()),
+            Err(e) => (Config::default(), vec![format!("could not be parsed: {}", e)]),
+        },
+        Err(e) => (Config::default(), vec![format!("could not be read: {}", e)]),
+    };
+
+    if strict && !problems.is_empty() {
+        return Err(format!("Config file {} {}", path.display(), problems.join("; ")).into());
+    }
+    for problem in problems {
+        eprintln!("Warning: Config file {} {}", path.display(), problem);
+    }
+    Ok(config)
+}
+
+/// Parse a config file as TOML or JSON, depending on its extension, rejecting unknown keys
+pub fn parse(path: &Path, content: &str) -> Result<Config, Box<dyn std::error::Error>> {
+    let (config, unknown) = parse_lenient(path, content)?;
+    if !unknown.is_empty() {
+        let problems: Vec<String> = unknown.iter().map(|key| unknown_key_message(key)).collect();
+        return Err(problems.join("; ").into());
+    }
+    Ok(config)
+}
+
+/// Parse a config file, returning the dotted paths of any keys that aren't config keys
+fn parse_lenient(path: &Path, content: &str) -> Result<(Config, Vec<String>), Box<dyn std::error::Error>> {
+    let mut unknown = Vec::new();
+    let config = if path.extension().is_some_and(|ext| ext == "toml") {
+        let deserializer = toml::Deserializer::parse(content)?;
+        serde_ignored::deserialize(deserializer, |key| unknown.push(key.to_string()))?
+    } else {
+        let mut deserializer = serde_json::Deserializer::from_str(content);
+        let config = serde_ignored::deserialize(&mut deserializer, |key| unknown.push(key.to_string()))?;
+        deserializer.end()?;
+        config
+    };
+    Ok((config, unknown))
+}
+
+fn unknown_key_message(key: &str) -> String {
+    match suggest_key(key) {
+        Some(suggestion) => format!("has an unknown key \"{}\" (did you mean \"{}\"?)", key, suggestion),
+        None => format!("has an unknown key \"{}\"", key),
+    }
+}
+
+/// The known key closest to a misspelled one, if any is close enough
+fn suggest_key(key: &str) -> Option<String> {
+    // Suggest within the same profile, e.g. profiles.work.modle -> profiles.work.model
+    let (prefix, key) = match key.strip_prefix("profiles.").and_then(|rest| rest.split_once('.')) {
+        Some((profile, rest)) => (format!("profiles.{}.", profile), rest),
+        None => (String::new(), key),
+    };
+
+    KEYS.iter()
+        .map(|(known, _)| (*known, edit_distance(key, known)))
+        .filter(|(_, distance)| *distance <= 2)
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(known, _)| format!("{}{}", prefix, known))
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// The type of value a config key holds, for `config set`
+#[derive(Clone, Copy)]
+pub enum KeyKind {
+    String,
+    Integer,
+    /// May have a fractional part
+    Number,
+    Bool,
+    /// Comma-separated on the command line
+    List,
+}
+
+/// Every config key (dotted for nested keys) and its type; `headers.<name>`, `templates.<name>`, `model_aliases.<name>`,
+/// `context.commands.<name>`, `mcp_servers.<name>.<key>`, and `profiles.<name>.<key>` are handled separately.
+const KEYS: &[(&str, KeyKind)] = &[
+    ("provider", KeyKind::String),
+    ("base_url", KeyKind::String),
+    ("api_key_env", KeyKind::String),
+    ("api_key_cmd", KeyKind::String),
+    ("proxy", KeyKind::String),
+    ("ca_bundle", KeyKind::String),
+    ("aws_region", KeyKind::String),
+    ("aws_profile", KeyKind::String),
+    ("model", KeyKind::String),
+    ("fallbacks", KeyKind::List),
+    ("max_tokens", KeyKind::Integer),
+    ("temperature", KeyKind::Number),
+    ("top_p", KeyKind::Number),
+    ("top_k", KeyKind::Integer),
+    ("thinking_budget", KeyKind::Integer),
+    ("max_attempts", KeyKind::Integer),
+    ("timeout_secs", KeyKind::Integer),
+    ("history_lines", KeyKind::Integer),
+    ("relevant_history_lines", KeyKind::Integer),
+    ("directory_history", KeyKind::Bool),
+    ("history_backend", KeyKind::String),
+    ("compress_history", KeyKind::Bool),
+    ("history_summary_model", KeyKind::String),
+    ("max_context_tokens", KeyKind::Integer),
+    ("system_prompt_suffix", KeyKind::String),
+    ("system_prompt", KeyKind::String),
+    ("structured_output", KeyKind::Bool),
+    ("context_files", KeyKind::List),
+    ("shell", KeyKind::String),
+    ("prompt_cache", KeyKind::Bool),
+    ("prompt_cache_history", KeyKind::Bool),
+    ("cache_ttl_secs", KeyKind::Integer),
+    ("blocklist", KeyKind::List),
+    ("redact", KeyKind::Bool),
+    ("redact_patterns", KeyKind::List),
+    ("allowlist", KeyKind::List),
+    ("audit_log", KeyKind::Bool),
+    ("max_fix_attempts", KeyKind::Integer),
+    ("default_action", KeyKind::String),
+    ("log_file", KeyKind::String),
+    ("tools.list_dir", KeyKind::Bool),
+    ("tools.read_file_head", KeyKind::Bool),
+    ("tools.confirm", KeyKind::Bool),
+    ("context.os", KeyKind::Bool),
+    ("context.cwd", KeyKind::Bool),
+    ("context.git", KeyKind::Bool),
+    ("context.tmux", KeyKind::Bool),
+    ("context.last_command", KeyKind::Bool),
+    ("context.history", KeyKind::Bool),
+    ("context.tool_docs", KeyKind::Bool),
+    ("context.installed_tools", KeyKind::Bool),
+    ("context.aliases", KeyKind::Bool),
+    ("context.kubernetes", KeyKind::Bool),
+    ("context.docker", KeyKind::Bool),
+    ("context.order", KeyKind::List),
+    ("context.files", KeyKind::List),
+    ("sandbox.runtime", KeyKind::String),
+    ("sandbox.image", KeyKind::String),
+    ("sandbox.writable", KeyKind::Bool),
+    ("confirm.low", KeyKind::String),
+    ("confirm.medium", KeyKind::String),
+    ("confirm.high", KeyKind::String),
+    ("agent.max_steps", KeyKind::Integer),
+    ("agent.auto_approve_low_risk", KeyKind::Bool),
+];
+
+/// What kind of value `key` (dotted, e.g. `context.git`) holds, or `None` if it isn't a config key
+pub fn key_kind(key: &str) -> Option<KeyKind> {
+    // Profiles hold the same keys as the top level
+    let key = match key.strip_prefix("profiles.") {
+        Some(rest) => rest.split_once('.')?.1,
+        None => key,
+    };
+
+    if ["headers.", "templates.", "model_aliases.", "context.commands."].iter().any(|prefix| key.strip_prefix(prefix).is_some_and(|name| !name.is_empty())) {
+        return Some(KeyKind::String);
+    }
+    if let Some((name, field)) = key.strip_prefix("mcp_servers.").and_then(|rest| rest.split_once('.')) {
+        return match field {
+            _ if name.is_empty() => None,
+            "command" => Some(KeyKind::String),
+            "args" | "tools" => Some(KeyKind::List),
+            _ if field.strip_prefix("env.").is_some_and(|var| !var.is_empty()) => Some(KeyKind::String),
+            _ => None,
+        };
+    }
+    KEYS.iter().find(|(known, _)| *known == key).map(|(_, kind)| *kind)
+}
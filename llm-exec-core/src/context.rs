@@ -0,0 +1,770 @@
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use crate::config::Config;
+use crate::prompt::shell_name;
+use crate::{history, shell_integration};
+
+/// Estimated tokens of context sent with each request, unless `max_context_tokens` is set
+pub const DEFAULT_MAX_CONTEXT_TOKENS: usize = 8000;
+/// Maximum number of directory entries included in the cwd listing
+const MAX_CWD_ENTRIES: usize = 100;
+/// Maximum number of `git status` lines included in the git context
+const MAX_GIT_STATUS_LINES: usize = 30;
+/// Number of lines of tmux scrollback included as context
+const TMUX_SCROLLBACK_LINES: usize = 200;
+/// Number of lines of tmux scrollback included as the previous command's output
+const LAST_OUTPUT_LINES: usize = 50;
+/// Maximum size of piped stdin included as context
+const MAX_PIPED_INPUT_BYTES: usize = 64 * 1024;
+/// Maximum size of a `context.commands` command's output included as context
+const MAX_COMMAND_OUTPUT_BYTES: usize = 16 * 1024;
+/// Maximum size of each `context.files` file included as context, split between its start and end
+const MAX_FILE_BYTES: usize = 16 * 1024;
+/// Names of the built-in providers that can be listed in `context.order`
+const BUILTIN_PROVIDERS: &[&str] = &["os", "cwd", "git", "last_command", "tmux", "tool_docs", "installed_tools", "aliases", "kubernetes", "docker", "files"];
+/// Maximum number of running containers included in the Docker context
+const MAX_CONTAINERS: usize = 30;
+/// Compose file names, in the order `docker compose` looks for them
+const COMPOSE_FILES: &[&str] = &["compose.yaml", "compose.yml", "docker-compose.yaml", "docker-compose.yml"];
+/// Maximum number of `kubectl get pods` lines included in the Kubernetes context
+const MAX_POD_LINES: usize = 20;
+/// Maximum number of shell aliases included as context
+const MAX_ALIASES: usize = 100;
+/// How long to wait for an interactive shell to list its aliases, in case an rc file blocks
+const ALIAS_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+/// Programs whose presence changes what a good command looks like: modern replacements
+/// for the classics, GNU tools on macOS, package managers, and common CLIs
+const PROBED_TOOLS: &[&str] = &[
+    "fd", "fdfind", "rg", "ag", "jq", "yq", "fzf", "bat", "eza", "exa", "gsed", "gawk", "ggrep", "gfind", "gxargs", "gdate", "parallel",
+    "curl", "wget", "http", "rsync", "7z", "zstd", "ffmpeg", "magick", "convert", "docker", "podman", "kubectl", "helm", "terraform",
+    "aws", "gcloud", "az", "gh", "git", "python3", "node", "deno", "bun", "go", "cargo", "brew", "apt", "dnf", "yum", "pacman", "apk",
+    "systemctl", "journalctl", "launchctl", "tmux", "xclip", "wl-copy", "pbcopy",
+];
+/// Maximum number of tools mentioned in the prompt to include documentation for
+const MAX_TOOL_DOCS: usize = 3;
+/// Maximum lines of documentation included per tool
+const MAX_TOOL_DOC_LINES: usize = 40;
+/// Commands that are also everyday words, so a prompt using them rarely means the tool
+const COMMON_WORDS: &[&str] = &[
+    "all", "and", "at", "base", "cat", "chat", "col", "cut", "date", "echo", "env", "expand", "false", "file", "find", "free", "groups",
+    "head", "id", "install", "join", "kill", "last", "less", "link", "look", "make", "more", "names", "paste", "print", "read", "script",
+    "sort", "split", "tail", "tee", "test", "time", "top", "touch", "true", "users", "wait", "watch", "which", "who", "write", "yes",
+];
+/// ANSI escape sequences and the backspace overstrikes man uses for bold and underline
+static FORMATTING: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\x1b\[[0-9;]*[A-Za-z]|.\x08").expect("invalid formatting pattern"));
+
+/// A titled block of extra context included in the system prompt
+#[derive(Clone)]
+pub struct Section {
+    pub title: String,
+    pub body: String,
+    /// Name of the context provider it came from, or "piped input", once gathered
+    pub source: String,
+}
+
+impl Section {
+    pub fn new(title: impl Into<String>, body: impl Into<String>) -> Self {
+        Section {
+            title: title.into(),
+            body: body.into(),
+            source: String::new(),
+        }
+    }
+}
+
+/// A source of extra context for the system prompt
+pub trait ContextProvider {
+    /// Name of the provider in `context.order`
+    fn name(&self) -> &str;
+
+    /// The section to include, or `None` if there's nothing to add
+    fn collect(&self) -> Option<Section>;
+}
+
+/// Recent shell history
+pub struct History<'a> {
+    pub backend: &'a dyn history::HistoryBackend,
+    pub lines: usize,
+    /// Only commands run in the current directory, where the history records that
+    pub here: bool,
+}
+
+impl ContextProvider for History<'_> {
+    fn name(&self) -> &str {
+        "history"
+    }
+
+    fn collect(&self) -> Option<Section> {
+        let history = if self.here { self.backend.recent_here(self.lines) } else { self.backend.recent(self.lines) };
+        match history {
+            Ok(history) => Some(Section::new("The user's recent shell history", history.join("\n"))),
+            Err(e) => {
+                eprintln!("Warning: Could not read shell history: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// See [`os_info`]
+pub struct Os;
+
+impl ContextProvider for Os {
+    fn name(&self) -> &str {
+        "os"
+    }
+
+    fn collect(&self) -> Option<Section> {
+        Some(os_info())
+    }
+}
+
+/// See [`cwd_listing`]
+pub struct Cwd;
+
+impl ContextProvider for Cwd {
+    fn name(&self) -> &str {
+        "cwd"
+    }
+
+    fn collect(&self) -> Option<Section> {
+        cwd_listing()
+    }
+}
+
+/// See [`git_info`]
+pub struct Git;
+
+impl ContextProvider for Git {
+    fn name(&self) -> &str {
+        "git"
+    }
+
+    fn collect(&self) -> Option<Section> {
+        git_info()
+    }
+}
+
+/// See [`tmux_scrollback`]
+pub struct Tmux;
+
+impl ContextProvider for Tmux {
+    fn name(&self) -> &str {
+        "tmux"
+    }
+
+    fn collect(&self) -> Option<Section> {
+        tmux_scrollback()
+    }
+}
+
+/// See [`kubernetes_info`]
+pub struct Kubernetes;
+
+impl ContextProvider for Kubernetes {
+    fn name(&self) -> &str {
+        "kubernetes"
+    }
+
+    fn collect(&self) -> Option<Section> {
+        kubernetes_info()
+    }
+}
+
+/// See [`docker_info`]
+pub struct Docker;
+
+impl ContextProvider for Docker {
+    fn name(&self) -> &str {
+        "docker"
+    }
+
+    fn collect(&self) -> Option<Section> {
+        docker_info()
+    }
+}
+
+/// See [`last_command`]
+pub struct LastCommand {
+    pub with_output: bool,
+}
+
+impl ContextProvider for LastCommand {
+    fn name(&self) -> &str {
+        "last_command"
+    }
+
+    fn collect(&self) -> Option<Section> {
+        last_command(self.with_output)
+    }
+}
+
+/// The `tldr` page, or failing that the man page synopsis, of each installed tool the prompt mentions
+pub struct ToolDocs {
+    pub prompt: String,
+}
+
+impl ContextProvider for ToolDocs {
+    fn name(&self) -> &str {
+        "tool_docs"
+    }
+
+    fn collect(&self) -> Option<Section> {
+        let docs: Vec<String> = mentioned_tools(&self.prompt)
+            .into_iter()
+            .take(MAX_TOOL_DOCS)
+            .filter_map(|tool| tool_doc(&tool))
+            .collect();
+        (!docs.is_empty()).then(|| Section::new("Documentation for tools in the request, as installed on the user's system", docs.join("\n\n")))
+    }
+}
+
+/// Words in the prompt that name programs on `PATH`, in order and without common words
+fn mentioned_tools(prompt: &str) -> Vec<String> {
+    let mut tools: Vec<String> = Vec::new();
+    for word in prompt.split(|c: char| !(c.is_alphanumeric() || "-_.+".contains(c))) {
+        let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+        if word.len() < 2 || COMMON_WORDS.contains(&word) || tools.iter().any(|tool| tool == word) {
+            continue;
+        }
+        if on_path(word) {
+            tools.push(word.to_string());
+        }
+    }
+    tools
+}
+
+fn on_path(program: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+    std::env::split_paths(&path).any(|dir| {
+        let candidate = dir.join(program);
+        candidate.is_file() || (cfg!(windows) && candidate.with_extension("exe").is_file())
+    })
+}
+
+/// The tool's `tldr` page, or the SYNOPSIS section of its man page
+fn tool_doc(tool: &str) -> Option<String> {
+    if let Some(page) = command_output("tldr", &[tool]) {
+        return Some(format!("{} (tldr page):\n{}", tool, first_lines(&FORMATTING.replace_all(&page, ""))));
+    }
+
+    let output = Command::new("man").arg(tool).env("MANPAGER", "cat").env("MANWIDTH", "100").stdin(Stdio::null()).output().ok()?;
+    let page = FORMATTING.replace_all(&String::from_utf8_lossy(&output.stdout), "").into_owned();
+    let mut lines = page.lines().skip_while(|line| line.trim() != "SYNOPSIS").skip(1);
+    // The section ends at the next heading, which isn't indented
+    let synopsis: Vec<&str> = lines.by_ref().take_while(|line| line.is_empty() || line.starts_with(char::is_whitespace)).collect();
+    let indent = synopsis.iter().filter(|line| !line.trim().is_empty()).map(|line| line.len() - line.trim_start().len()).min().unwrap_or(0);
+    let synopsis = synopsis.iter().map(|line| line.get(indent..).unwrap_or("")).collect::<Vec<_>>().join("\n");
+    (!synopsis.trim().is_empty()).then(|| format!("{} (man page synopsis):\n{}", tool, first_lines(synopsis.trim_end())))
+}
+
+/// The first `MAX_TOOL_DOC_LINES` lines of `text`
+fn first_lines(text: &str) -> String {
+    text.trim().lines().take(MAX_TOOL_DOC_LINES).collect::<Vec<_>>().join("\n")
+}
+
+/// Which of `PROBED_TOOLS` are on `PATH`, so the model uses the tools the user has
+pub struct InstalledTools;
+
+impl ContextProvider for InstalledTools {
+    fn name(&self) -> &str {
+        "installed_tools"
+    }
+
+    fn collect(&self) -> Option<Section> {
+        let (installed, missing): (Vec<&str>, Vec<&str>) = PROBED_TOOLS.iter().partition(|tool| on_path(tool));
+        Some(Section::new(
+            "Tools on the user's PATH",
+            format!("Installed: {}\nNot installed: {}", installed.join(", "), missing.join(", ")),
+        ))
+    }
+}
+
+/// The aliases defined by the user's shell rc files, which are also defined when commands run
+pub struct Aliases {
+    pub shell: String,
+}
+
+impl ContextProvider for Aliases {
+    fn name(&self) -> &str {
+        "aliases"
+    }
+
+    fn collect(&self) -> Option<Section> {
+        if !matches!(shell_name(&self.shell).as_str(), "sh" | "bash" | "zsh" | "dash" | "ksh" | "fish") {
+            return None;
+        }
+
+        let mut child = Command::new(&self.shell)
+            .args(["-i", "-c", "alias"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+        let mut stdout = child.stdout.take()?;
+        let reader = std::thread::spawn(move || {
+            let mut output = String::new();
+            stdout.read_to_string(&mut output).map(|_| output)
+        });
+
+        let started = std::time::Instant::now();
+        while child.try_wait().ok()?.is_none() {
+            if started.elapsed() > ALIAS_TIMEOUT {
+                let _ = child.kill();
+                let _ = child.wait();
+                eprintln!("Warning: Gave up listing shell aliases after {}s", ALIAS_TIMEOUT.as_secs());
+                return None;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+
+        let output = reader.join().ok()?.ok()?;
+        // Skip anything else the rc files print
+        let aliases: Vec<&str> = output.lines().filter(|line| line.starts_with("alias ") || line.contains('=')).take(MAX_ALIASES).collect();
+        (!aliases.is_empty()).then(|| Section::new("The user's shell aliases, which are also defined when commands run", aliases.join("\n")))
+    }
+}
+
+/// The output of a command from `context.commands`, run with the user's shell
+pub struct UserCommand {
+    pub name: String,
+    pub command: String,
+    pub shell: String,
+}
+
+impl ContextProvider for UserCommand {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn collect(&self) -> Option<Section> {
+        let flags: &[&str] = if cfg!(windows) { &["-NoLogo", "-Command"] } else { &["-c"] };
+        let output = Command::new(&self.shell)
+            .args(flags)
+            .arg(&self.command)
+            .stdin(Stdio::null())
+            .output();
+
+        let output = match output {
+            Ok(output) if output.status.success() => output,
+            Ok(output) => {
+                eprintln!("Warning: Context command '{}' failed ({})", self.name, output.status);
+                return None;
+            }
+            Err(e) => {
+                eprintln!("Warning: Could not run context command '{}': {}", self.name, e);
+                return None;
+            }
+        };
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stdout = stdout.trim();
+        let title = if self.name == self.command { format!("Output of `{}`", self.command) } else { format!("{} (output of `{}`)", self.name, self.command) };
+        (!stdout.is_empty()).then(|| Section::new(title, truncate_start(stdout, MAX_COMMAND_OUTPUT_BYTES)))
+    }
+}
+
+/// The contents of the files from `context.files` (or `--file`)
+pub struct Files {
+    pub paths: Vec<String>,
+}
+
+impl ContextProvider for Files {
+    fn name(&self) -> &str {
+        "files"
+    }
+
+    fn collect(&self) -> Option<Section> {
+        let mut files = Vec::new();
+        for path in &self.paths {
+            let contents = match std::fs::read(path) {
+                Ok(contents) => contents,
+                Err(e) => {
+                    eprintln!("Warning: Could not read {}: {}", path, e);
+                    continue;
+                }
+            };
+            if contents.contains(&0) {
+                eprintln!("Warning: Not including {}, since it isn't a text file", path);
+                continue;
+            }
+            let text = String::from_utf8_lossy(&contents);
+            files.push(format!("==> {} <==\n{}", path, truncate_middle(text.trim_end(), MAX_FILE_BYTES)));
+        }
+        (!files.is_empty()).then(|| Section::new("Files attached by the user", files.join("\n\n")))
+    }
+}
+
+/// Run a command and return its trimmed stdout, or `None` if it fails or prints nothing.
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!stdout.is_empty()).then_some(stdout)
+}
+
+/// Kernel, distribution/OS version, and architecture of this machine.
+pub fn os_info() -> Section {
+    let mut lines = vec![format!("Architecture: {}", std::env::consts::ARCH)];
+
+    if let Some(uname) = command_output("uname", &["-srm"]) {
+        lines.push(format!("Kernel: {}", uname));
+    }
+
+    if let Some(version) = command_output("sw_vers", &["-productVersion"]) {
+        lines.push(format!("OS: macOS {}", version));
+    } else if let Some(distro) = linux_distribution() {
+        lines.push(format!("OS: {}", distro));
+    } else {
+        lines.push(format!("OS: {}", std::env::consts::OS));
+    }
+
+    Section::new("The user's system", lines.join("\n"))
+}
+
+/// `PRETTY_NAME` from /etc/os-release, e.g. "Ubuntu 22.04.4 LTS"
+fn linux_distribution() -> Option<String> {
+    let content = std::fs::read_to_string("/etc/os-release").ok()?;
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+        .map(|name| name.trim_matches('"').to_string())
+}
+
+/// An `ls -p`-style listing of the current directory, truncated to `MAX_CWD_ENTRIES`.
+pub fn cwd_listing() -> Option<Section> {
+    let cwd = std::env::current_dir().ok()?;
+    Some(Section::new(
+        format!("Contents of the current directory ({})", cwd.display()),
+        listing(&cwd).ok()?,
+    ))
+}
+
+/// An `ls -p`-style listing of `dir`, with sizes and truncated to `MAX_CWD_ENTRIES`
+pub(crate) fn listing(dir: &Path) -> std::io::Result<String> {
+    let mut entries: Vec<String> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            match entry.metadata() {
+                Ok(meta) if meta.is_dir() => format!("{}/", name),
+                Ok(meta) => format!("{} ({} bytes)", name, meta.len()),
+                Err(_) => name,
+            }
+        })
+        .collect();
+    entries.sort();
+
+    let total = entries.len();
+    if total > MAX_CWD_ENTRIES {
+        entries.truncate(MAX_CWD_ENTRIES);
+        entries.push(format!("... and {} more entries", total - MAX_CWD_ENTRIES));
+    }
+    Ok(entries.join("\n"))
+}
+
+/// Branch, remotes, short status, and recent commits, if the cwd is inside a git repository.
+pub fn git_info() -> Option<Section> {
+    let branch = command_output("git", &["rev-parse", "--abbrev-ref", "HEAD"])?;
+    let mut lines = vec![format!("Current branch: {}", branch)];
+
+    if let Some(remotes) = command_output("git", &["remote", "-v"]) {
+        let fetch_remotes: Vec<&str> = remotes.lines().filter(|l| l.ends_with("(fetch)")).collect();
+        lines.push(format!("Remotes:\n{}", fetch_remotes.join("\n")));
+    }
+
+    if let Some(status) = command_output("git", &["status", "--short", "--branch"]) {
+        let mut status_lines: Vec<&str> = status.lines().collect();
+        let total = status_lines.len();
+        status_lines.truncate(MAX_GIT_STATUS_LINES);
+        let mut status = status_lines.join("\n");
+        if total > MAX_GIT_STATUS_LINES {
+            status.push_str(&format!("\n... and {} more changed files", total - MAX_GIT_STATUS_LINES));
+        }
+        lines.push(format!("Status:\n{}", status));
+    }
+
+    if let Some(log) = command_output("git", &["log", "--oneline", "-n", "5"]) {
+        lines.push(format!("Recent commits:\n{}", log));
+    }
+
+    Some(Section::new("Git repository", lines.join("\n\n")))
+}
+
+/// The current kubectl context and namespace, and the pods in it, if kubectl is installed and configured.
+pub fn kubernetes_info() -> Option<Section> {
+    if !on_path("kubectl") {
+        return None;
+    }
+    let context = command_output("kubectl", &["config", "current-context"])?;
+    let namespace = command_output("kubectl", &["config", "view", "--minify", "-o", "jsonpath={..namespace}"]).unwrap_or_else(|| "default".to_string());
+    let mut lines = vec![format!("Context: {}", context), format!("Namespace: {}", namespace)];
+
+    // Don't hold up the request for long if the cluster is unreachable
+    if let Some(pods) = command_output("kubectl", &["get", "pods", "--request-timeout=3s"]) {
+        let mut pod_lines: Vec<&str> = pods.lines().collect();
+        let total = pod_lines.len();
+        pod_lines.truncate(MAX_POD_LINES + 1);
+        let mut pods = pod_lines.join("\n");
+        if total > MAX_POD_LINES + 1 {
+            pods.push_str(&format!("\n... and {} more pods", total - MAX_POD_LINES - 1));
+        }
+        lines.push(format!("Pods in the namespace:\n{}", pods));
+    }
+
+    Some(Section::new("Kubernetes", lines.join("\n")))
+}
+
+/// Running containers, and the services in the nearest Compose file, if there are any.
+pub fn docker_info() -> Option<Section> {
+    let mut parts = Vec::new();
+
+    if on_path("docker") {
+        if let Some(containers) = command_output("docker", &["ps", "--format", "{{.Names}}\t{{.Image}}\t{{.Status}}"]) {
+            let mut lines: Vec<&str> = containers.lines().collect();
+            let total = lines.len();
+            lines.truncate(MAX_CONTAINERS);
+            let mut containers = lines.join("\n");
+            if total > MAX_CONTAINERS {
+                containers.push_str(&format!("\n... and {} more containers", total - MAX_CONTAINERS));
+            }
+            parts.push(format!("Running containers (name, image, status):\n{}", containers));
+        }
+    }
+
+    let cwd = std::env::current_dir().ok()?;
+    let compose_file = cwd.ancestors().flat_map(|dir| COMPOSE_FILES.iter().map(move |name| dir.join(name))).find(|path| path.is_file());
+    if let Some(path) = compose_file {
+        let services = compose_services(&std::fs::read_to_string(&path).ok()?);
+        if !services.is_empty() {
+            parts.push(format!("Services in {}: {}", path.display(), services.join(", ")));
+        }
+    }
+
+    (!parts.is_empty()).then(|| Section::new("Docker", parts.join("\n\n")))
+}
+
+/// The keys of the top-level `services:` mapping of a Compose file. Enough YAML for the
+/// usual block style; anything fancier yields nothing.
+fn compose_services(yaml: &str) -> Vec<String> {
+    let mut lines = yaml.lines().skip_while(|line| line.trim_end() != "services:").skip(1);
+    let mut services = Vec::new();
+    let mut indent = None;
+    for line in lines.by_ref() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let depth = line.len() - trimmed.len();
+        if depth == 0 {
+            break;
+        }
+        if *indent.get_or_insert(depth) == depth {
+            if let Some(name) = trimmed.strip_suffix(':').or_else(|| trimmed.split_once(": ").map(|(name, _)| name)) {
+                services.push(name.trim_matches(|c| c == '"' || c == '\'').to_string());
+            }
+        }
+    }
+    services
+}
+
+/// Recent scrollback of the tmux pane llm-exec is running in, if any.
+pub fn tmux_scrollback() -> Option<Section> {
+    let scrollback = tmux_capture(TMUX_SCROLLBACK_LINES)?;
+    Some(Section::new("Recent output in the user's terminal (tmux pane)", scrollback))
+}
+
+/// The last `lines` lines of the current tmux pane, or `None` outside tmux.
+fn tmux_capture(lines: usize) -> Option<String> {
+    std::env::var_os("TMUX")?;
+
+    let start = format!("-{}", lines);
+    let mut args = vec!["capture-pane", "-p", "-J", "-S", start.as_str()];
+    // Target our own pane rather than whichever one is active
+    let pane = std::env::var("TMUX_PANE").ok();
+    if let Some(pane) = &pane {
+        args.extend(["-t", pane.as_str()]);
+    }
+
+    command_output("tmux", &args)
+}
+
+/// The previous command and its exit status, recorded by the `--shell-init` hooks.
+/// Inside tmux, the tail of the pane is included as its output unless `with_output` is false.
+pub fn last_command(with_output: bool) -> Option<Section> {
+    let (status, command) = shell_integration::last_command()?;
+    let mut body = format!("Command: {}\nExit status: {}", command, status);
+
+    if let Some(output) = tmux_capture(LAST_OUTPUT_LINES).filter(|_| with_output) {
+        body.push_str(&format!("\nOutput (tail of the terminal):\n{}", output));
+    }
+
+    Some(Section::new("The user's previous command", body))
+}
+
+/// Text piped to llm-exec on stdin, keeping only the last `MAX_PIPED_INPUT_BYTES`
+/// since errors usually appear at the end of output.
+pub fn piped_input() -> Option<Section> {
+    let text = read_stdin()?;
+    Some(Section::new("Input piped in by the user", truncate_start(&text, MAX_PIPED_INPUT_BYTES)))
+}
+
+/// Everything on stdin, trimmed, or `None` if there's nothing
+pub fn read_stdin() -> Option<String> {
+    let mut input = Vec::new();
+    std::io::stdin().read_to_end(&mut input).ok()?;
+
+    let text = String::from_utf8_lossy(&input).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// Rough number of tokens `text` takes up, at about four characters per token
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Drop lines from the start of `text`, the oldest in history and logs, until it's estimated
+/// at no more than `max_tokens`. Returns how many lines were dropped.
+pub fn trim_to_tokens(text: &mut String, max_tokens: usize) -> usize {
+    let mut tokens = estimate_tokens(text);
+    let mut dropped = 0;
+    let mut start = 0;
+    while tokens > max_tokens && start < text.len() {
+        let end = text[start..].find('\n').map_or(text.len(), |i| start + i + 1);
+        tokens = tokens.saturating_sub(estimate_tokens(&text[start..end]));
+        start = end;
+        dropped += 1;
+    }
+    text.drain(..start);
+    dropped
+}
+
+/// The last `max_bytes` of `text`, noting how much was cut
+fn truncate_start(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+
+    let mut start = text.len() - max_bytes;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("[... {} earlier bytes truncated ...]\n{}", start, &text[start..])
+}
+
+/// The first and last `max_bytes / 2` of `text`, cut at line breaks where possible, noting how much was cut
+fn truncate_middle(text: &str, max_bytes: usize) -> String {
+    if text.len() <= max_bytes {
+        return text.to_string();
+    }
+
+    let mut head = max_bytes / 2;
+    while !text.is_char_boundary(head) {
+        head -= 1;
+    }
+    let head = text[..head].rfind('\n').map_or(head, |newline| newline + 1);
+    let mut tail = text.len() - max_bytes / 2;
+    while !text.is_char_boundary(tail) {
+        tail += 1;
+    }
+    let tail = text[tail..].find('\n').map_or(tail, |newline| tail + newline + 1);
+    format!("{}[... {} bytes truncated ...]\n{}", &text[..head], tail - head, &text[tail..])
+}
+
+/// The context providers enabled by config (including flags) for `prompt`, in the configured order.
+/// Shell history is left out since it goes in its own part of the system prompt.
+pub fn providers(config: &Config, prompt: &str) -> Vec<Box<dyn ContextProvider>> {
+    let context = &config.context;
+    let mut providers: Vec<Box<dyn ContextProvider>> = Vec::new();
+
+    if context.os.unwrap_or(false) {
+        providers.push(Box::new(Os));
+    }
+    if context.cwd.unwrap_or(false) {
+        providers.push(Box::new(Cwd));
+    }
+    if context.git.unwrap_or(false) {
+        providers.push(Box::new(Git));
+    }
+    let include_tmux = context.tmux.unwrap_or(false);
+    if context.last_command.unwrap_or(true) {
+        // The full scrollback already covers the last command's output
+        providers.push(Box::new(LastCommand { with_output: !include_tmux }));
+    }
+    if include_tmux {
+        providers.push(Box::new(Tmux));
+    }
+    if context.tool_docs.unwrap_or(false) {
+        providers.push(Box::new(ToolDocs { prompt: prompt.to_string() }));
+    }
+    if context.installed_tools.unwrap_or(false) {
+        providers.push(Box::new(InstalledTools));
+    }
+    if context.aliases.unwrap_or(false) {
+        providers.push(Box::new(Aliases {
+            shell: crate::prompt::user_shell(config),
+        }));
+    }
+    if context.kubernetes.unwrap_or(false) {
+        providers.push(Box::new(Kubernetes));
+    }
+    if context.docker.unwrap_or(false) {
+        providers.push(Box::new(Docker));
+    }
+    if let Some(paths) = context.files.clone().filter(|paths| !paths.is_empty()) {
+        providers.push(Box::new(Files { paths }));
+    }
+
+    let mut commands: Vec<(&String, &String)> = context.commands.iter().flatten().collect();
+    commands.sort();
+    for (name, command) in commands {
+        providers.push(Box::new(UserCommand {
+            name: name.clone(),
+            command: command.clone(),
+            shell: crate::prompt::user_shell(config),
+        }));
+    }
+
+    if let Some(order) = &context.order {
+        for name in order {
+            let known = BUILTIN_PROVIDERS.contains(&name.as_str()) || context.commands.as_ref().is_some_and(|c| c.contains_key(name));
+            if !known {
+                eprintln!("Warning: Unknown context provider '{}' in context.order", name);
+            }
+        }
+        // Stable, so providers that aren't listed keep their default order
+        providers.sort_by_key(|provider| order.iter().position(|name| name == provider.name()).unwrap_or(order.len()));
+    }
+
+    providers
+}
+
+/// Collect the extra context sections enabled by config (including flags) for `prompt`, with piped input last
+pub fn gather(config: &Config, prompt: &str, piped_input: Option<Section>) -> Vec<Section> {
+    let mut sections: Vec<Section> = providers(config, prompt)
+        .iter()
+        .filter_map(|provider| {
+            let section = provider.collect()?;
+            Some(Section {
+                source: provider.name().to_string(),
+                ..section
+            })
+        })
+        .collect();
+    sections.extend(piped_input.map(|section| Section {
+        source: "piped input".to_string(),
+        ..section
+    }));
+    sections
+}
@@ -0,0 +1,172 @@
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Who said a turn of the conversation
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+/// A single message in the conversation
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Turn {
+    pub role: Role,
+    pub content: String,
+    /// Images sent along with the text, for models that can see them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<Image>,
+    /// Tools the model called in this turn, for providers with native tool use; `content` then
+    /// has the calls as `# tool:` lines for those without
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    /// Results of the tool calls in the turn before, for providers with native tool use;
+    /// `content` then has them as text for those without
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_results: Vec<ToolResult>,
+}
+
+/// A call the model made to one of the tools it was offered
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ToolCall {
+    /// The provider's ID for the call, which its result refers to
+    pub id: String,
+    /// The name the tool was offered to the model by
+    pub name: String,
+    pub arguments: serde_json::Value,
+}
+
+/// What a tool call returned, or why it failed
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ToolResult {
+    /// The `id` of the call
+    pub id: String,
+    pub content: String,
+    pub is_error: bool,
+}
+
+/// An image attached to a turn
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Image {
+    /// Such as `image/png`
+    pub media_type: String,
+    /// The image file, base64-encoded
+    pub data: String,
+}
+
+impl Image {
+    /// Read a PNG, JPEG, GIF, or WebP file
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default().to_lowercase();
+        let media_type = match extension.as_str() {
+            "png" => "image/png",
+            "jpg" | "jpeg" => "image/jpeg",
+            "gif" => "image/gif",
+            "webp" => "image/webp",
+            _ => return Err(format!("{} isn't a PNG, JPEG, GIF, or WebP image", path.display()).into()),
+        };
+        let bytes = std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        Ok(Image {
+            media_type: media_type.to_string(),
+            data: base64::engine::general_purpose::STANDARD.encode(bytes),
+        })
+    }
+
+    /// The image as a `data:` URL
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.media_type, self.data)
+    }
+}
+
+/// The running exchange with the model: the user's prompts and feedback,
+/// interleaved with the commands the model suggested.
+#[derive(Default)]
+pub struct Conversation {
+    turns: Vec<Turn>,
+}
+
+impl Conversation {
+    pub fn new() -> Self {
+        Conversation::default()
+    }
+
+    /// Continue a conversation saved earlier
+    pub fn from_turns(turns: Vec<Turn>) -> Self {
+        Conversation { turns }
+    }
+
+    pub fn push(&mut self, role: Role, content: impl Into<String>) {
+        self.push_with_images(role, content, Vec::new());
+    }
+
+    pub fn push_with_images(&mut self, role: Role, content: impl Into<String>, images: Vec<Image>) {
+        self.turns.push(Turn {
+            role,
+            content: content.into(),
+            images,
+            tool_calls: Vec::new(),
+            tool_results: Vec::new(),
+        });
+    }
+
+    pub fn pop(&mut self) -> Option<Turn> {
+        self.turns.pop()
+    }
+
+    pub fn last_role(&self) -> Option<Role> {
+        self.turns.last().map(|turn| turn.role)
+    }
+
+    pub fn turns(&self) -> &[Turn] {
+        &self.turns
+    }
+}
+
+/// A piece of the system prompt
+#[derive(Clone, Debug)]
+pub struct PromptBlock {
+    pub text: String,
+    /// Whether providers that cache prompt prefixes should cache up to the end of this block
+    pub cache: bool,
+}
+
+/// The system prompt, in blocks ordered from least to most likely to change between requests
+/// so that providers with prompt caching can reuse the stable prefix.
+#[derive(Clone, Default, Debug)]
+pub struct SystemPrompt {
+    blocks: Vec<PromptBlock>,
+}
+
+impl SystemPrompt {
+    pub fn push(&mut self, text: impl Into<String>, cache: bool) {
+        self.blocks.push(PromptBlock { text: text.into(), cache });
+    }
+
+    pub fn blocks(&self) -> &[PromptBlock] {
+        &self.blocks
+    }
+
+    /// The whole prompt as one string, for providers without caching
+    pub fn text(&self) -> String {
+        self.blocks.iter().map(|block| block.text.as_str()).collect::<Vec<_>>().join("\n\n")
+    }
+}
+
+impl From<&str> for SystemPrompt {
+    fn from(text: &str) -> Self {
+        let mut prompt = SystemPrompt::default();
+        prompt.push(text, false);
+        prompt
+    }
+}
@@ -0,0 +1,45 @@
+/// Service name API keys are stored under in the OS keychain
+const SERVICE: &str = "llm-exec";
+
+/// Providers that authenticate with an API key
+const KEYED_PROVIDERS: &[&str] = &["anthropic", "openai", "gemini", "custom"];
+
+/// The API key `llm-exec auth login` stored for `provider`, if any
+pub fn stored_key(provider: &str) -> Option<String> {
+    keyring::Entry::new(SERVICE, provider).ok()?.get_password().ok()
+}
+
+/// Store an API key for `provider` in the macOS Keychain, Secret Service, or Windows Credential Manager
+pub fn store_key(provider: &str, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+    check_provider(provider)?;
+    keyring::Entry::new(SERVICE, provider)
+        .and_then(|entry| entry.set_password(key))
+        .map_err(keychain_error)
+}
+
+/// Remove the stored API key for `provider`, returning whether there was one
+pub fn delete_key(provider: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    check_provider(provider)?;
+    match keyring::Entry::new(SERVICE, provider).and_then(|entry| entry.delete_credential()) {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(e) => Err(keychain_error(e)),
+    }
+}
+
+fn keychain_error(e: keyring::Error) -> Box<dyn std::error::Error> {
+    format!("Could not access the system keychain: {}", e).into()
+}
+
+/// An error unless `provider` authenticates with an API key
+pub fn check_provider(provider: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if KEYED_PROVIDERS.contains(&provider) {
+        return Ok(());
+    }
+    Err(format!(
+        "The {} provider doesn't use an API key (expected one of: {})",
+        provider,
+        KEYED_PROVIDERS.join(", ")
+    )
+    .into())
+}
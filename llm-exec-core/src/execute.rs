@@ -0,0 +1,198 @@
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use std::process::{Child, Command, ExitStatus, Stdio};
+
+/// Parse the command with the shell's no-exec mode, returning its complaint if the syntax is invalid.
+/// Shells without such a mode are assumed to accept anything.
+pub fn check_syntax(command: &str, shell: &str) -> Option<String> {
+    if !matches!(crate::prompt::shell_name(shell).as_str(), "sh" | "bash" | "zsh" | "dash" | "ksh" | "fish") {
+        return None;
+    }
+
+    let output = Command::new(shell).arg("-n").arg("-c").arg(command).output().ok()?;
+    if output.status.success() {
+        return None;
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Some(stderr.lines().next().unwrap_or("syntax error").to_string())
+}
+
+/// Most bytes of each of a command's stdout and stderr kept by `execute_capturing`
+const MAX_CAPTURED_BYTES: usize = 8 * 1024;
+/// How long to wait for the rest of a command's output after it exits
+const OUTPUT_GRACE: Duration = Duration::from_millis(200);
+
+#[cfg(windows)]
+fn shell_command(command: &str, shell: &str) -> Command {
+    let mut cmd = Command::new(shell);
+    cmd.arg("-NoLogo").arg("-Command").arg(command);
+    cmd
+}
+
+#[cfg(not(windows))]
+fn shell_command(command: &str, shell: &str) -> Command {
+    let mut cmd = Command::new(shell);
+    cmd.arg("-i").arg("-c").arg(command);
+    cmd
+}
+
+/// Start the command with the shell, taking input from `stdin`
+fn spawn(command: &str, shell: &str, stdin: impl Fn() -> Stdio, stdout: impl Fn() -> Stdio, stderr: impl Fn() -> Stdio) -> std::io::Result<Child> {
+    let spawn = |shell: &str| shell_command(command, shell).stdin(stdin()).stdout(stdout()).stderr(stderr()).spawn();
+
+    // Prefer PowerShell 7 (pwsh), falling back to the built-in Windows PowerShell
+    match spawn(shell) {
+        Err(e) if cfg!(windows) && e.kind() == std::io::ErrorKind::NotFound && shell == "pwsh" => spawn("powershell"),
+        result => result,
+    }
+}
+
+/// Run the command with the shell, taking input from `stdin` and inheriting stdout and stderr
+pub fn execute(command: &str, shell: &str, stdin: impl Fn() -> Stdio) -> Result<ExitStatus, Box<dyn std::error::Error>> {
+    Ok(spawn(command, shell, stdin, Stdio::inherit, Stdio::inherit)?.wait()?)
+}
+
+/// Run a script file, taking input from `stdin` and inheriting stdout and stderr. It's run directly
+/// if it starts with a shebang line, or else with the shell.
+#[cfg(not(windows))]
+pub fn execute_script(path: &Path, shell: &str, stdin: impl Fn() -> Stdio) -> Result<ExitStatus, Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut script = if std::fs::read(path)?.starts_with(b"#!") {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o700))?;
+        Command::new(path)
+    } else {
+        let mut script = Command::new(shell);
+        script.arg(path);
+        script
+    };
+    Ok(script.stdin(stdin()).stdout(Stdio::inherit()).stderr(Stdio::inherit()).status()?)
+}
+
+/// Run a script file with PowerShell, taking input from `stdin` and inheriting stdout and stderr
+#[cfg(windows)]
+pub fn execute_script(path: &Path, shell: &str, stdin: impl Fn() -> Stdio) -> Result<ExitStatus, Box<dyn std::error::Error>> {
+    let run = |shell: &str| {
+        Command::new(shell)
+            .arg("-NoLogo")
+            .arg("-File")
+            .arg(path)
+            .stdin(stdin())
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()
+    };
+
+    // Prefer PowerShell 7 (pwsh), falling back to the built-in Windows PowerShell
+    let status = match run(shell) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && shell == "pwsh" => run("powershell")?,
+        result => result?,
+    };
+    Ok(status)
+}
+
+/// The end of what a command wrote, from `execute_capturing`
+#[derive(Debug, Default)]
+pub struct Captured {
+    /// Empty unless stdout was captured
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Like `execute`, but also returns the end of what the command wrote to stderr, and to stdout
+/// if `stdout` is set. Both are still passed through to ours as they're written.
+pub fn execute_capturing(command: &str, shell: &str, stdin: impl Fn() -> Stdio, stdout: bool) -> Result<(ExitStatus, Captured), Box<dyn std::error::Error>> {
+    let foreground = is_foreground();
+    let child_stdout = || if stdout { Stdio::piped() } else { Stdio::inherit() };
+    let mut child = spawn(command, shell, stdin, child_stdout, Stdio::piped)?;
+    let stdout_tee = child.stdout.take().map(|source| Tee::start(source, std::io::stdout()));
+    let stderr_tee = Tee::start(child.stderr.take().ok_or("no stderr")?, std::io::stderr());
+
+    let status = child.wait()?;
+    if foreground {
+        take_foreground();
+    }
+    let captured = Captured {
+        stdout: stdout_tee.map(Tee::finish).unwrap_or_default(),
+        stderr: stderr_tee.finish(),
+    };
+    Ok((status, captured))
+}
+
+/// Copies a child's output to ours on a thread, keeping the last `MAX_CAPTURED_BYTES`
+struct Tee {
+    tail: Arc<Mutex<Vec<u8>>>,
+    done: mpsc::Receiver<()>,
+}
+
+impl Tee {
+    fn start(mut source: impl Read + Send + 'static, mut sink: impl Write + Send + 'static) -> Self {
+        let tail = Arc::new(Mutex::new(Vec::new()));
+        let (done_tx, done) = mpsc::channel();
+        let thread_tail = tail.clone();
+        std::thread::spawn(move || {
+            let mut buf = [0; 4096];
+            while let Ok(n) = source.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                let _ = sink.write_all(&buf[..n]).and_then(|_| sink.flush());
+                let mut tail = thread_tail.lock().unwrap();
+                tail.extend_from_slice(&buf[..n]);
+                if tail.len() > MAX_CAPTURED_BYTES {
+                    let excess = tail.len() - MAX_CAPTURED_BYTES;
+                    tail.drain(..excess);
+                }
+            }
+            let _ = done_tx.send(());
+        });
+        Tee { tail, done }
+    }
+
+    /// What was kept once the output ends. Background processes the command started may keep
+    /// it open, so this doesn't wait long after the command exits.
+    fn finish(self) -> String {
+        let _ = self.done.recv_timeout(OUTPUT_GRACE);
+        let tail = self.tail.lock().unwrap();
+        String::from_utf8_lossy(&tail).into_owned()
+    }
+}
+
+/// Whether we're the terminal's foreground process group
+#[cfg(unix)]
+fn is_foreground() -> bool {
+    use std::os::fd::AsRawFd;
+    let Ok(tty) = crate::terminal::open() else {
+        return false;
+    };
+    // SAFETY: tcgetpgrp and getpgrp only read process state, and tty is an open descriptor
+    unsafe { libc::tcgetpgrp(tty.as_raw_fd()) == libc::getpgrp() }
+}
+
+/// Become the terminal's foreground process group again. An interactive shell whose stderr
+/// isn't the terminal takes the foreground and doesn't hand it back when it exits.
+#[cfg(unix)]
+fn take_foreground() {
+    use std::os::fd::AsRawFd;
+    let Ok(tty) = crate::terminal::open() else {
+        return;
+    };
+    // SAFETY: tty is an open descriptor; SIGTTOU is ignored while a background process group
+    // takes the foreground, which would otherwise stop us, then restored
+    unsafe {
+        let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(tty.as_raw_fd(), libc::getpgrp());
+        libc::signal(libc::SIGTTOU, previous);
+    }
+}
+
+#[cfg(not(unix))]
+fn is_foreground() -> bool {
+    false
+}
+
+#[cfg(not(unix))]
+fn take_foreground() {}
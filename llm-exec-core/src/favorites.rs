@@ -0,0 +1,64 @@
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use crate::{color, config};
+
+const FAVORITES_FILE: &str = "favorites.jsonl";
+
+/// A command saved from the confirmation menu, with the prompt it was suggested for
+#[derive(Serialize, Deserialize)]
+pub struct Favorite {
+    pub prompt: String,
+    pub command: String,
+}
+
+fn favorites_file() -> Option<PathBuf> {
+    Some(config::state_dir()?.join(FAVORITES_FILE))
+}
+
+/// Save a command to the favorites, unless it's already there
+pub fn add(favorite: &Favorite) -> Result<(), Box<dyn std::error::Error>> {
+    if read()?.iter().any(|saved| saved.command == favorite.command) {
+        return Ok(());
+    }
+
+    let path = favorites_file().ok_or("Could not determine the state directory")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let line = format!("{}\n", serde_json::to_string(favorite)?);
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&path)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Every saved favorite, oldest first
+pub fn read() -> Result<Vec<Favorite>, Box<dyn std::error::Error>> {
+    let path = favorites_file().ok_or("Could not determine the state directory")?;
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Could not read {}: {}", path.display(), e).into()),
+    };
+
+    let mut favorites = Vec::new();
+    for line in std::io::BufReader::new(file).lines() {
+        if let Ok(favorite) = serde_json::from_str(&line?) {
+            favorites.push(favorite);
+        }
+    }
+    Ok(favorites)
+}
+
+/// `llm-exec favorites`: print the saved commands, optionally only those containing `search`
+pub fn show(search: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    for favorite in read()? {
+        if search.is_some_and(|text| !favorite.prompt.contains(text) && !favorite.command.contains(text)) {
+            continue;
+        }
+        println!("{}", color::paint(color::DIM, &favorite.prompt));
+        println!("  {}", color::paint(color::COMMAND, &favorite.command));
+    }
+    Ok(())
+}
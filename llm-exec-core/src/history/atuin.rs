@@ -0,0 +1,59 @@
+use std::process::{Command, Stdio};
+
+use super::HistoryBackend;
+
+/// Set by atuin's shell integration, in shells whose history it records
+pub(super) const SESSION_ENV: &str = "ATUIN_SESSION";
+
+/// atuin's history database, through the `atuin` command. It records where each command ran
+/// and how it ended, and keeps the shell's own history file from being up to date.
+pub(super) struct Atuin;
+
+impl Atuin {
+    /// The last `lines` commands, oldest first, only those run in the current directory if `here`
+    fn list(&self, lines: usize, here: bool) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut command = Command::new("atuin");
+        command.args(["history", "list", "--cmd-only", "--print0"]);
+        if here {
+            command.arg("--cwd");
+        }
+        let stdout = run(&mut command, "history list")?;
+        let mut commands: Vec<String> = stdout.split('\0').filter(|command| !command.trim().is_empty()).map(str::to_string).collect();
+        let start = commands.len().saturating_sub(lines);
+        Ok(commands.split_off(start))
+    }
+}
+
+impl HistoryBackend for Atuin {
+    fn name(&self) -> &str {
+        "atuin"
+    }
+
+    fn recent(&self, lines: usize) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        self.list(lines, false)
+    }
+
+    fn recent_here(&self, lines: usize) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        self.list(lines, true)
+    }
+
+    fn append(&self, command: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let id = run(Command::new("atuin").args(["history", "start", "--", command]), "history start")?;
+        Ok(Some(id.trim().to_string()))
+    }
+
+    fn finish(&self, id: &str, exit_code: Option<i32>) -> Result<(), Box<dyn std::error::Error>> {
+        let exit = exit_code.unwrap_or(-1).to_string();
+        run(Command::new("atuin").args(["history", "end", "--exit", &exit, id]), "history end")?;
+        Ok(())
+    }
+}
+
+/// The output of an `atuin` subcommand, or its error output if it fails
+fn run(command: &mut Command, subcommand: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let output = command.stdin(Stdio::null()).output().map_err(|e| format!("Could not run atuin: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("atuin {} failed: {}", subcommand, String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
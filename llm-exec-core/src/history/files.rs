@@ -0,0 +1,93 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::HistoryBackend;
+
+/// bash's history file (`~/.bash_history`), one command per line
+pub(super) struct Bash {
+    pub(super) path: PathBuf,
+}
+
+impl HistoryBackend for Bash {
+    fn name(&self) -> &str {
+        "bash"
+    }
+
+    fn recent(&self, lines: usize) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        read_lines(&self.path, lines)
+    }
+
+    fn append(&self, command: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        append_line(&self.path, command)
+    }
+}
+
+/// zsh's history file (`~/.zsh_history`), in its extended format with a timestamp on each command
+pub(super) struct Zsh {
+    pub(super) path: PathBuf,
+}
+
+impl HistoryBackend for Zsh {
+    fn name(&self) -> &str {
+        "zsh"
+    }
+
+    fn recent(&self, lines: usize) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        read_lines(&self.path, lines)
+    }
+
+    fn append(&self, command: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        // `: timestamp:0;command`
+        append_line(&self.path, &format!(": {}:0;{}", super::unix_time(), command))
+    }
+}
+
+/// PSReadLine's history file, one command per line
+pub(super) struct PowerShell {
+    pub(super) path: PathBuf,
+}
+
+impl PowerShell {
+    /// `%APPDATA%\Microsoft\Windows\PowerShell\PSReadLine\ConsoleHost_history.txt`
+    pub(super) fn default_path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("Microsoft/Windows/PowerShell/PSReadLine/ConsoleHost_history.txt"))
+    }
+}
+
+impl HistoryBackend for PowerShell {
+    fn name(&self) -> &str {
+        "powershell"
+    }
+
+    fn recent(&self, lines: usize) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        read_lines(&self.path, lines)
+    }
+
+    fn append(&self, command: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        append_line(&self.path, command)
+    }
+}
+
+/// The last `lines` lines of a history file, without zsh's timestamps (like `: 1234567890:0;`)
+fn read_lines(path: &Path, lines: usize) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let history_lines: Vec<&str> = content.lines().collect();
+    let start = history_lines.len().saturating_sub(lines);
+
+    Ok(history_lines[start..]
+        .iter()
+        .map(|line| {
+            if line.starts_with(": ") && line.contains(';') {
+                line.split_once(';').map(|(_, cmd)| cmd).unwrap_or(line).to_string()
+            } else {
+                line.to_string()
+            }
+        })
+        .collect())
+}
+
+fn append_line(path: &Path, line: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let mut file = std::fs::OpenOptions::new().append(true).open(path)?;
+    file.write_all(format!("{}\n", line).as_bytes())?;
+    Ok(None)
+}
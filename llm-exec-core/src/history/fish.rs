@@ -0,0 +1,107 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use super::HistoryBackend;
+
+/// fish's history file, in its YAML-like format
+pub(super) struct Fish {
+    pub(super) path: PathBuf,
+}
+
+impl Fish {
+    /// `$XDG_DATA_HOME/fish/fish_history`, or `~/.local/share/fish/fish_history`
+    pub(super) fn default_path() -> Option<PathBuf> {
+        let data_dir = std::env::var_os("XDG_DATA_HOME").map(PathBuf::from).or_else(|| Some(dirs::home_dir()?.join(".local/share")))?;
+        Some(data_dir.join("fish/fish_history"))
+    }
+}
+
+impl HistoryBackend for Fish {
+    fn name(&self) -> &str {
+        "fish"
+    }
+
+    fn recent(&self, lines: usize) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let content = std::fs::read_to_string(&self.path).map_err(|e| format!("Could not read {}: {}", self.path.display(), e))?;
+        let mut commands = parse_fish_history(&content);
+        let start = commands.len().saturating_sub(lines);
+        Ok(commands.split_off(start))
+    }
+
+    fn append(&self, command: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let mut file = std::fs::OpenOptions::new().append(true).open(&self.path)?;
+        // `- cmd: command` followed by `  when: timestamp`
+        file.write_all(format!("- cmd: {}\n  when: {}\n", escape_fish(command), super::unix_time()).as_bytes())?;
+        Ok(None)
+    }
+}
+
+/// Extract commands from fish's YAML-like history format (`- cmd: ...` entries)
+fn parse_fish_history(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| line.strip_prefix("- cmd: "))
+        .map(unescape_fish)
+        .collect()
+}
+
+/// Undo fish's history escaping of backslashes and newlines
+fn unescape_fish(cmd: &str) -> String {
+    let mut unescaped = String::with_capacity(cmd.len());
+    let mut chars = cmd.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('\\') => unescaped.push('\\'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+/// Escape backslashes and newlines the way fish stores commands in its history
+fn escape_fish(cmd: &str) -> String {
+    cmd.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cmd_entries_and_skips_metadata() {
+        let content = "- cmd: git status\n  when: 1700000000\n- cmd: vim README.md\n  when: 1700000005\n  paths:\n    - README.md\n";
+        assert_eq!(parse_fish_history(content), ["git status", "vim README.md"]);
+    }
+
+    #[test]
+    fn parses_multi_line_commands() {
+        let content = "- cmd: for f in *.rs\\n  echo $f\\nend\n  when: 1700000000\n";
+        assert_eq!(parse_fish_history(content), ["for f in *.rs\n  echo $f\nend"]);
+    }
+
+    #[test]
+    fn unescapes_backslashes() {
+        assert_eq!(unescape_fish(r"printf 'a\\nb'"), r"printf 'a\nb'");
+        assert_eq!(unescape_fish(r"echo C:\\Users\\me"), r"echo C:\Users\me");
+        assert_eq!(unescape_fish(r"grep \d trailing\"), r"grep \d trailing\");
+    }
+
+    #[test]
+    fn escaping_round_trips() {
+        for command in [r"echo C:\Users\me", "for f in *\n  echo $f\nend", r"printf 'a\nb\n'", r"printf a\\nb", r"trailing\"] {
+            assert_eq!(unescape_fish(&escape_fish(command)), command);
+        }
+        assert_eq!(escape_fish("echo a\nb\\nc"), r"echo a\nb\\nc");
+    }
+}
@@ -0,0 +1,360 @@
+mod atuin;
+mod files;
+mod fish;
+
+use std::path::PathBuf;
+
+use crate::config::Config;
+
+use atuin::Atuin;
+use files::{Bash, PowerShell, Zsh};
+use fish::Fish;
+
+pub const DEFAULT_HISTORY_LINES: usize = 1000;
+/// Newest commands sent along with the ones relevant to the prompt, with `relevant_history_lines`
+pub const RECENT_LINES: usize = 5;
+
+/// Names accepted by the `history_backend` config key, besides "auto"
+pub const BACKENDS: &[&str] = &["bash", "zsh", "fish", "powershell", "atuin"];
+
+/// Where the user's shell history is read from, and commands run through llm-exec are added to
+/// so they can be recalled like any other.
+pub trait HistoryBackend {
+    /// The name `history_backend` selects this backend by
+    fn name(&self) -> &str;
+
+    /// The last `lines` commands, oldest first
+    fn recent(&self, lines: usize) -> Result<Vec<String>, Box<dyn std::error::Error>>;
+
+    /// Like `recent`, but only the commands run in the current directory. Backends that don't
+    /// record where commands ran return the whole history.
+    fn recent_here(&self, lines: usize) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        self.recent(lines)
+    }
+
+    /// Add a command before it runs. Returns an ID to pass to `finish` if the backend records
+    /// how commands ended.
+    fn append(&self, command: &str) -> Result<Option<String>, Box<dyn std::error::Error>>;
+
+    /// Record how the command `append` returned `id` for ended: its exit code, or `None` if it
+    /// was killed by a signal
+    fn finish(&self, _id: &str, _exit_code: Option<i32>) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+}
+
+/// The backend the `history_backend` config key names, or else the one `detect` finds
+pub fn from_config(config: &Config) -> Result<Box<dyn HistoryBackend>, Box<dyn std::error::Error>> {
+    let home = || dirs::home_dir().ok_or("Could not determine the home directory");
+    let backend: Box<dyn HistoryBackend> = match config.history_backend.as_deref().unwrap_or("auto") {
+        "auto" => detect()?,
+        "bash" => Box::new(Bash { path: home()?.join(".bash_history") }),
+        "zsh" => Box::new(Zsh { path: home()?.join(".zsh_history") }),
+        "fish" => Box::new(Fish { path: Fish::default_path().ok_or("Could not determine the fish history file")? }),
+        "powershell" => Box::new(PowerShell { path: PowerShell::default_path().ok_or("Could not determine the PSReadLine history file")? }),
+        "atuin" => Box::new(Atuin),
+        other => return Err(format!("Unknown history_backend \"{}\" (expected auto or one of: {})", other, BACKENDS.join(", ")).into()),
+    };
+    tracing::debug!(backend = backend.name(), "history backend");
+    Ok(backend)
+}
+
+/// atuin if its shell integration is active, PSReadLine on Windows, and otherwise the first
+/// history file that exists (bash's, if none do). fish users may also have stale bash/zsh history lying around,
+/// so if fish is the login shell its history is checked first.
+fn detect() -> Result<Box<dyn HistoryBackend>, Box<dyn std::error::Error>> {
+    if std::env::var_os(atuin::SESSION_ENV).is_some() {
+        return Ok(Box::new(Atuin));
+    }
+    if cfg!(windows) {
+        let path = PowerShell::default_path().ok_or("Could not determine the PSReadLine history file")?;
+        return Ok(Box::new(PowerShell { path }));
+    }
+
+    let home = dirs::home_dir().ok_or("Could not determine the home directory")?;
+    let zsh = home.join(".zsh_history");
+    let bash = home.join(".bash_history");
+    let other = home.join(".history");
+    let fish = Fish::default_path();
+    let fish_is_login_shell = std::env::var("SHELL").is_ok_and(|shell| shell.ends_with("/fish"));
+
+    let exists = |path: &PathBuf| path.exists();
+    if let Some(path) = fish.clone().filter(|path| fish_is_login_shell && exists(path)) {
+        return Ok(Box::new(Fish { path }));
+    }
+    if exists(&zsh) {
+        return Ok(Box::new(Zsh { path: zsh }));
+    }
+    if exists(&bash) {
+        return Ok(Box::new(Bash { path: bash }));
+    }
+    if exists(&other) {
+        return Ok(Box::new(Bash { path: other }));
+    }
+    if let Some(path) = fish.filter(exists) {
+        return Ok(Box::new(Fish { path }));
+    }
+    // Reading it will say it doesn't exist
+    Ok(Box::new(Bash { path: bash }))
+}
+
+/// Seconds since the Unix epoch, for history formats that timestamp each command
+fn unix_time() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// The history with repeated commands removed, keeping the most recent of each
+pub fn dedupe(history: &str) -> String {
+    let mut seen = std::collections::HashSet::new();
+    let mut commands: Vec<&str> = history.lines().rev().filter(|command| seen.insert(*command)).collect();
+    commands.reverse();
+    commands.join("\n")
+}
+
+/// History estimated at no more than this many tokens is sent as it is, without being compressed
+/// or deduped
+pub const COMPACT_MIN_TOKENS: usize = 1000;
+
+/// The history shortened for sending if it's longer than `COMPACT_MIN_TOKENS`: deduped, and with
+/// `compress` set, compressed first
+pub fn compact(history: &str, compress: bool) -> String {
+    if crate::context::estimate_tokens(history) <= COMPACT_MIN_TOKENS {
+        return history.to_string();
+    }
+    let compressed = if compress { self::compress(history) } else { history.to_string() };
+    let compacted = dedupe(&compressed);
+    tracing::info!(raw_bytes = history.len(), compressed_bytes = compacted.len(), "compressed shell history");
+    compacted
+}
+
+/// Commands that only look around, which say little about what the user is working on
+const NOISE_COMMANDS: &[&str] = &["ls", "ll", "la", "l", "pwd", "clear", "cls", "exit", "history"];
+
+/// The history with the noise taken out: repeats of the previous command, commands like `ls`
+/// and `pwd` run without arguments, and runs of `cd`s, which are collapsed to the last one
+pub fn compress(history: &str) -> String {
+    let mut commands: Vec<&str> = Vec::new();
+    let mut last_cd = None;
+    for command in history.lines().map(str::trim).filter(|command| !command.is_empty()) {
+        let mut words = command.split_whitespace();
+        let program = words.next().unwrap_or_default();
+        if program == "cd" {
+            last_cd = Some(command);
+            continue;
+        }
+        if NOISE_COMMANDS.contains(&program) && words.all(|word| word.starts_with('-')) {
+            continue;
+        }
+        if let Some(cd) = last_cd.take() {
+            commands.push(cd);
+        }
+        if commands.last() != Some(&command) {
+            commands.push(command);
+        }
+    }
+    commands.extend(last_cd);
+    commands.join("\n")
+}
+
+/// Words too common in prompts to help find a command
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "the", "to", "of", "in", "on", "at", "for", "and", "or", "with", "from", "by", "into", "that", "this", "these", "those",
+    "i", "me", "my", "it", "is", "was", "all", "some", "how", "what", "which", "do", "did", "used", "use", "using", "again", "last",
+    "ago", "day", "week", "month", "year", "time", "command", "flag", "flags", "option", "one",
+];
+
+/// Up to `limit` commands from `history` (oldest first, one per line) that share words with
+/// `query`, best first. A word counts most as a whole word of the command, less inside one,
+/// and least when only its first few letters match; ties go to the most recent command.
+pub fn search(history: &str, query: &str, limit: usize) -> Vec<String> {
+    let words = query_words(query);
+    if words.is_empty() {
+        return Vec::new();
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    let mut matches: Vec<(usize, &str)> = Vec::new();
+    // Newest first, so a stable sort by score keeps recent commands ahead
+    for command in history.lines().rev().map(str::trim) {
+        if command.is_empty() || !seen.insert(command) {
+            continue;
+        }
+        let lower = command.to_lowercase();
+        let tokens = command_words(&lower);
+        let score: usize = words.iter().map(|word| word_score(word, &lower, &tokens)).sum();
+        if score > 0 {
+            matches.push((score, command));
+        }
+    }
+    matches.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+    matches.into_iter().take(limit).map(|(_, command)| command.to_string()).collect()
+}
+
+/// The words of a prompt worth looking for in commands, lowercased
+fn query_words(query: &str) -> Vec<String> {
+    query
+        .split(|c: char| !(c.is_alphanumeric() || "-_.".contains(c)))
+        .map(|word| word.trim_matches('.').to_lowercase())
+        .filter(|word| word.chars().count() > 1 && !STOP_WORDS.contains(&word.as_str()))
+        .collect()
+}
+
+/// The words of an already lowercased command, split on whitespace and shell punctuation
+fn command_words(command: &str) -> Vec<&str> {
+    command.split(|c: char| c.is_whitespace() || "/.=|;&'\"()".contains(c)).filter(|word| !word.is_empty()).collect()
+}
+
+fn word_score(word: &str, command: &str, tokens: &[&str]) -> usize {
+    const STEM_CHARS: usize = 4;
+    if tokens.contains(&word) {
+        3
+    } else if command.contains(word) {
+        2
+    } else if word.chars().count() > STEM_CHARS && command.contains(&word.chars().take(STEM_CHARS).collect::<String>()) {
+        1
+    } else {
+        0
+    }
+}
+
+/// How much a term appearing again in a command counts for less (BM25's k1)
+const BM25_SATURATION: f64 = 1.2;
+/// How much longer commands are penalized (BM25's b)
+const BM25_LENGTH_WEIGHT: f64 = 0.75;
+
+/// The `count` commands in `history` most relevant to `query`, ranked by BM25 over the words of
+/// the prompt, plus the `recent` newest commands, which often matter whatever was asked. They're
+/// returned in their original order, one per line; commands sharing no words with the prompt
+/// are left out.
+pub fn most_relevant(history: &str, query: &str, count: usize, recent: usize) -> String {
+    let lines: Vec<&str> = history.lines().collect();
+    let commands: Vec<String> = lines.iter().map(|line| line.to_lowercase()).collect();
+    let documents: Vec<Vec<&str>> = commands.iter().map(|command| command_words(command)).collect();
+    let average_len = documents.iter().map(Vec::len).sum::<usize>() as f64 / documents.len().max(1) as f64;
+
+    let mut words = query_words(query);
+    words.sort();
+    words.dedup();
+    let idf: Vec<f64> = words
+        .iter()
+        .map(|word| {
+            let containing = documents.iter().filter(|document| document.contains(&word.as_str())).count() as f64;
+            ((documents.len() as f64 - containing + 0.5) / (containing + 0.5) + 1.0).ln()
+        })
+        .collect();
+
+    let mut scored: Vec<(f64, usize)> = documents
+        .iter()
+        .enumerate()
+        .map(|(index, document)| {
+            let length_norm = 1.0 - BM25_LENGTH_WEIGHT + BM25_LENGTH_WEIGHT * document.len() as f64 / average_len.max(1.0);
+            let score = words
+                .iter()
+                .zip(&idf)
+                .map(|(word, idf)| {
+                    let frequency = document.iter().filter(|token| **token == word.as_str()).count() as f64;
+                    idf * frequency * (BM25_SATURATION + 1.0) / (frequency + BM25_SATURATION * length_norm)
+                })
+                .sum();
+            (score, index)
+        })
+        .filter(|(score, _)| *score > 0.0)
+        .collect();
+    // Best first, and the newest of equally relevant commands
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0).then(b.1.cmp(&a.1)));
+
+    let mut keep = vec![false; lines.len()];
+    for (_, index) in scored.into_iter().take(count) {
+        keep[index] = true;
+    }
+    let newest = lines.len().saturating_sub(recent);
+    keep[newest..].fill(true);
+    lines.iter().zip(keep).filter(|(_, keep)| *keep).map(|(line, _)| *line).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A history long enough to be compacted, ending with `tail`
+    fn long_history(tail: &str) -> String {
+        let filler: Vec<String> = (0..200).map(|i| format!("cargo test --package crate{i}")).collect();
+        format!("{}\n{tail}", filler.join("\n"))
+    }
+
+    #[test]
+    fn compress_drops_repeated_runs() {
+        assert_eq!(compress("make\nmake\nmake\ncargo build\nmake"), "make\ncargo build\nmake");
+    }
+
+    #[test]
+    fn compress_drops_noise_and_collapses_cds() {
+        let history = "ls\nls -la\nls src\npwd\ncd ..\ncd project\ncd src\nvim main.rs\ncd /tmp";
+        assert_eq!(compress(history), "ls src\ncd src\nvim main.rs\ncd /tmp");
+    }
+
+    #[test]
+    fn compress_keeps_near_duplicates() {
+        let history = "git commit -m fix\ngit commit -m 'fix'\ngit  commit -m fix\ngit commit -m fixes";
+        assert_eq!(compress(history), history);
+    }
+
+    #[test]
+    fn dedupe_keeps_the_most_recent_occurrence() {
+        assert_eq!(dedupe("make\ncargo build\nmake test\ncargo build\nmake"), "make test\ncargo build\nmake");
+    }
+
+    #[test]
+    fn dedupe_keeps_near_duplicates() {
+        let history = "git push\ngit push --force\ngit push origin\nGit push";
+        assert_eq!(dedupe(history), history);
+    }
+
+    #[test]
+    fn compact_leaves_short_history_alone() {
+        let history = "make\nmake\nls\ncd src\ncd ..\nmake";
+        assert_eq!(compact(history, true), history);
+    }
+
+    #[test]
+    fn compact_compresses_and_dedupes_long_history() {
+        let history = long_history("make\nmake\nls\ncd src\ncd ..\ncargo test --package crate0");
+        let compacted = compact(&history, true);
+        assert!(compacted.ends_with("crate199\nmake\ncd ..\ncargo test --package crate0"));
+        assert_eq!(compacted.lines().count(), 202);
+    }
+
+    #[test]
+    fn compact_only_dedupes_without_compress() {
+        let history = long_history("make\nmake\nls");
+        assert!(compact(&history, false).ends_with("crate199\nmake\nls"));
+    }
+
+    #[test]
+    fn most_relevant_ranks_matching_commands_first() {
+        let history = "ls -la\ncargo build\ndocker compose up -d\ngit status\nvim notes.md";
+        assert_eq!(most_relevant(history, "start the docker containers", 1, 0), "docker compose up -d");
+        assert_eq!(most_relevant(history, "docker status", 2, 0), "docker compose up -d\ngit status");
+    }
+
+    #[test]
+    fn most_relevant_breaks_ties_by_recency() {
+        let history = "kubectl get pods\nmake\nkubectl get nodes\ncargo build";
+        assert_eq!(most_relevant(history, "kubectl", 1, 0), "kubectl get nodes");
+    }
+
+    #[test]
+    fn most_relevant_keeps_the_most_recent_commands() {
+        let history = "docker ps\nmake\ncargo build";
+        assert_eq!(most_relevant(history, "docker", 1, 1), "docker ps\ncargo build");
+    }
+
+    #[test]
+    fn most_relevant_returns_nothing_without_a_count_or_query() {
+        let history = "docker ps\nmake\ncargo build";
+        assert_eq!(most_relevant(history, "docker", 0, 0), "");
+        assert_eq!(most_relevant(history, "", 3, 0), "");
+        assert_eq!(most_relevant(history, "the", 3, 0), "");
+        assert_eq!(most_relevant("", "docker", 3, 5), "");
+    }
+}
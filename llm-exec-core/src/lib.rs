@@ -0,0 +1,26 @@
+pub mod audit;
+pub mod color;
+pub mod config;
+pub mod context;
+pub mod conversation;
+pub mod credentials;
+pub mod execute;
+pub mod favorites;
+pub mod history;
+mod mcp;
+pub mod prompt;
+pub mod providers;
+pub mod redact;
+pub mod response_cache;
+pub mod risk;
+pub mod sandbox;
+pub mod sessions;
+pub mod shell_integration;
+mod tools;
+mod suggest;
+pub mod terminal;
+pub mod usage;
+
+pub use suggest::{suggest, suggest_structured, Refused, StructuredSuggestion, SuggestContext, Suggestion};
+
+pub const DEFAULT_MAX_TOKENS: u32 = 1024;
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::rc::Rc;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+use crate::config::McpServerConfig;
+use crate::tools::{self, Tool};
+
+/// MCP protocol revision llm-exec speaks
+const PROTOCOL_VERSION: &str = "2025-06-18";
+/// How long to wait for a server to start or answer a request
+const SERVER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum number of a server's resources listed for the model
+const MAX_LISTED_RESOURCES: usize = 50;
+
+/// A running MCP server, spoken to with JSON-RPC over its stdin and stdout
+struct Server {
+    io: tokio::sync::Mutex<ServerIo>,
+    _child: Child,
+}
+
+struct ServerIo {
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    next_id: u64,
+}
+
+impl Server {
+    /// Start the server and collect the tools it offers the model: its own, and one to read
+    /// its resources if it has any
+    async fn start(name: &str, config: &McpServerConfig) -> Result<Vec<Box<dyn Tool>>, Box<dyn std::error::Error>> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .envs(&config.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| format!("could not run {}: {}", config.command, e))?;
+
+        let io = ServerIo {
+            stdin: child.stdin.take().ok_or("no stdin")?,
+            stdout: BufReader::new(child.stdout.take().ok_or("no stdout")?).lines(),
+            next_id: 0,
+        };
+        let server = Rc::new(Server {
+            io: tokio::sync::Mutex::new(io),
+            _child: child,
+        });
+
+        let init = server
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {"name": "llm-exec", "version": env!("CARGO_PKG_VERSION")},
+                }),
+            )
+            .await?;
+        server.notify("notifications/initialized").await?;
+
+        let mut tools: Vec<Box<dyn Tool>> = Vec::new();
+        let capabilities = &init["capabilities"];
+        if capabilities.get("tools").is_some() {
+            for tool in server.list("tools/list", "tools").await? {
+                let tool_name = tool["name"].as_str().unwrap_or_default().to_string();
+                if config.tools.as_ref().is_some_and(|allowed| !allowed.contains(&tool_name)) {
+                    continue;
+                }
+                tools.push(Box::new(ServerTool {
+                    name: format!("{}.{}", name, tool_name),
+                    description: tool["description"].as_str().unwrap_or_default().trim().to_string(),
+                    parameters: match &tool["inputSchema"] {
+                        Value::Null => json!({"type": "object"}),
+                        schema => schema.clone(),
+                    },
+                    tool_name,
+                    server: server.clone(),
+                }));
+            }
+        }
+        if capabilities.get("resources").is_some() {
+            let resources = server.list("resources/list", "resources").await?;
+            if !resources.is_empty() {
+                tools.push(Box::new(ReadResource {
+                    name: format!("{}.read_resource", name),
+                    description: describe_resources(&resources),
+                    server: server.clone(),
+                }));
+            }
+        }
+
+        Ok(tools)
+    }
+
+    /// Send a request and wait for its result, ignoring anything else the server sends meanwhile
+    async fn request(&self, method: &str, params: Value) -> Result<Value, Box<dyn std::error::Error>> {
+        let mut io = self.io.lock().await;
+        io.next_id += 1;
+        let id = io.next_id;
+        io.send(&json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params})).await?;
+
+        let response = tokio::time::timeout(SERVER_TIMEOUT, async {
+            loop {
+                let Some(line) = io.stdout.next_line().await? else {
+                    return Err::<Value, Box<dyn std::error::Error>>("the server exited".into());
+                };
+                let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                    continue;
+                };
+
+                if message.get("method").is_some() {
+                    // We don't offer the server anything (sampling, roots, ...), so refuse its requests
+                    if let Some(request_id) = message.get("id") {
+                        let error = json!({"code": -32601, "message": "Method not found"});
+                        io.send(&json!({"jsonrpc": "2.0", "id": request_id, "error": error})).await?;
+                    }
+                } else if message["id"] == id {
+                    return Ok(message);
+                }
+            }
+        })
+        .await
+        .map_err(|_| format!("no response to {} after {}s", method, SERVER_TIMEOUT.as_secs()))??;
+
+        if let Some(error) = response.get("error") {
+            return Err(error["message"].as_str().unwrap_or("unknown error").to_string().into());
+        }
+        Ok(response["result"].clone())
+    }
+
+    async fn notify(&self, method: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.io.lock().await.send(&json!({"jsonrpc": "2.0", "method": method})).await
+    }
+
+    /// Every page of a paginated list method's `field`
+    async fn list(&self, method: &str, field: &str) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+        let mut items = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({"cursor": cursor}),
+                None => json!({}),
+            };
+            let result = self.request(method, params).await?;
+            items.extend(result[field].as_array().cloned().unwrap_or_default());
+
+            match result["nextCursor"].as_str() {
+                Some(next) => cursor = Some(next.to_string()),
+                None => return Ok(items),
+            }
+        }
+    }
+}
+
+impl ServerIo {
+    async fn send(&mut self, message: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        self.stdin.write_all(format!("{}\n", message).as_bytes()).await?;
+        self.stdin.flush().await?;
+        Ok(())
+    }
+}
+
+/// The text of a tool result or resource content item
+fn content_text(item: &Value) -> String {
+    if let Some(text) = item["text"].as_str().or(item["resource"]["text"].as_str()) {
+        return text.to_string();
+    }
+    format!("[{} content omitted]", item["type"].as_str().or(item["mimeType"].as_str()).unwrap_or("binary"))
+}
+
+/// How a server's resources are described to the model
+fn describe_resources(resources: &[Value]) -> String {
+    let mut description = String::from("Read one of these resources:");
+    for resource in resources.iter().take(MAX_LISTED_RESOURCES) {
+        let about = resource["description"].as_str().or(resource["name"].as_str()).unwrap_or_default();
+        description.push_str(&format!("\n  - {} {}", resource["uri"].as_str().unwrap_or_default(), about));
+    }
+    if resources.len() > MAX_LISTED_RESOURCES {
+        description.push_str(&format!("\n  - ... and {} more", resources.len() - MAX_LISTED_RESOURCES));
+    }
+    description
+}
+
+/// One of an MCP server's own tools
+struct ServerTool {
+    /// `<server>.<tool>`
+    name: String,
+    description: String,
+    /// JSON schema of the tool's arguments
+    parameters: Value,
+    /// The name the server knows the tool by
+    tool_name: String,
+    server: Rc<Server>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Tool for ServerTool {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters(&self) -> Value {
+        self.parameters.clone()
+    }
+
+    async fn call(&self, arguments: Value) -> Result<String, Box<dyn std::error::Error>> {
+        let result = self.server.request("tools/call", json!({"name": self.tool_name, "arguments": arguments})).await?;
+        let content = result["content"].as_array().cloned().unwrap_or_default();
+        let text = content.iter().map(content_text).collect::<Vec<_>>().join("\n");
+        if result["isError"].as_bool().unwrap_or(false) {
+            return Err(text.into());
+        }
+        Ok(text)
+    }
+}
+
+/// Reads one of an MCP server's resources by URI
+struct ReadResource {
+    /// `<server>.read_resource`
+    name: String,
+    description: String,
+    server: Rc<Server>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Tool for ReadResource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "uri": {"type": "string", "description": "The resource's URI"}
+            },
+            "required": ["uri"]
+        })
+    }
+
+    async fn call(&self, arguments: Value) -> Result<String, Box<dyn std::error::Error>> {
+        let uri = arguments["uri"].as_str().ok_or("read_resource needs a \"uri\" argument")?;
+        let result = self.server.request("resources/read", json!({"uri": uri})).await?;
+        let contents = result["contents"].as_array().cloned().unwrap_or_default();
+        Ok(contents.iter().map(content_text).collect::<Vec<_>>().join("\n"))
+    }
+}
+
+/// Start the `mcp_servers` and collect their tools, warning about any server that doesn't start
+pub(crate) async fn start(configs: &HashMap<String, McpServerConfig>) -> Vec<Box<dyn Tool>> {
+    let mut names: Vec<&String> = configs.keys().collect();
+    names.sort();
+
+    let mut tools = Vec::new();
+    for name in names {
+        tools::clear_progress();
+        match tokio::time::timeout(SERVER_TIMEOUT, Server::start(name, &configs[name])).await {
+            Ok(Ok(server_tools)) => tools.extend(server_tools),
+            Ok(Err(e)) => eprintln!("Warning: Could not start MCP server '{}': {}", name, e),
+            Err(_) => eprintln!("Warning: MCP server '{}' didn't start within {}s", name, SERVER_TIMEOUT.as_secs()),
+        }
+    }
+    tools
+}
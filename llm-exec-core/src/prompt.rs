@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::config::Config;
+use crate::context::Section;
+use crate::conversation::SystemPrompt;
+
+pub const DEFAULT_CONTEXT_FILES: &[&str] = &["CLAUDE.md", "AGENTS.md"];
+
+pub const DEFAULT_SYSTEM_PROMPT: &str = r#"You are a command-line assistant that outputs ONLY shell commands.
+
+RULES:
+1. Output ONLY a single shell command - nothing else
+2. NO explanations, NO markdown, NO code blocks, NO backticks, NO formatting
+3. If you cannot help, output: echo "Error: <reason>"
+4. Never suggest running "{}" - the user is already running that to talk to you
+
+Your entire response must be a valid shell command that can be executed directly."#;
+
+/// System prompt for `llm-exec ask`, which answers questions instead of suggesting a command
+pub const ASK_SYSTEM_PROMPT: &str = r#"You are a command-line assistant answering the user's questions about their shell, tools, and errors, such as what an exit code means or how a program's options work.
+
+Answer concisely in Markdown, putting any commands in code blocks. Nothing you write is run; the user copies whatever they want to use."#;
+
+/// Appended to every system prompt so the model suggests a way back from destructive commands
+pub const UNDO_INSTRUCTIONS: &str = "If the command is destructive or hard to reverse (deleting files, force-pushing, overwriting data, and so on), add a final line starting with \"# undo: \" followed by a command that reverses it or recovers the previous state, or a brief note on how to recover if no command can. Omit this line for other commands.";
+
+/// Added to the system prompt by `--script`, which lets the model answer with a script
+pub const SCRIPT_INSTRUCTIONS: &str = "The user asked for a script, so instead of a single command you may respond with a multi-line script, which will be saved to a file and run. Start it with a shebang line such as #!/usr/bin/env bash, or #!/usr/bin/env python3 for a script in another language. Output only the script, with no markdown or code fences, and leave out the undo line.";
+
+/// Added to the system prompt with `structured_output`, where the model replies through the `suggest_command` tool
+pub const STRUCTURED_INSTRUCTIONS: &str = "Reply by calling the suggest_command tool. Its command follows the rules above, except that if you cannot help, leave it empty and say why in the explanation. Give the undo command or note in its own field rather than on a line of the command.";
+
+/// Added to the `llm-exec ask` system prompt by `--plan` to get the steps of a task
+pub const PLAN_INSTRUCTIONS: &str = "Break the user's task into a short numbered list of steps (at most 8), each of which one shell command can do. Reply with only the list, one step per line as \"1. <what the step does>\", without the commands themselves.";
+
+/// Added to the system prompt by `--agent`, where the model runs commands and sees their output
+pub const AGENT_INSTRUCTIONS: &str = "You are carrying out the user's task yourself, one command at a time. After each command, the next message shows its exit status and output; use it to decide what to run next. When the task is done, or can't be done, reply with only a line starting with \"# done: \" followed by a one-sentence summary of the outcome.";
+
+/// Marks the model's response saying an `--agent` task is finished
+pub const DONE_MARKER: &str = "# done:";
+
+/// Marks the line of the model's response holding the undo command
+pub const UNDO_MARKER: &str = "# undo:";
+
+pub const EXPLAIN_SYSTEM_PROMPT: &str = r#"You explain shell commands to the user who is about to run them.
+
+Respond in plain text (no markdown headings, no code blocks) with:
+1. One short paragraph describing what the command does as a whole
+2. A breakdown listing each program, flag, and argument on its own line as "<token> - <meaning>"
+
+Mention any side effects that are destructive or hard to undo."#;
+
+/// System prompt for summarizing what a command printed, for `--summarize`
+pub const SUMMARIZE_SYSTEM_PROMPT: &str = r#"You summarize the output of a shell command the user just ran.
+
+Respond in plain text (no markdown headings, no code blocks) with one short paragraph saying what happened and whether the command did what the user wanted. If something went wrong or is left to do, end with the next step to take."#;
+
+/// System prompt for condensing long shell history, with `history_summary_model`
+pub const HISTORY_SUMMARY_SYSTEM_PROMPT: &str = r#"You condense a user's shell history, oldest command first, so that another assistant can suggest their next command.
+
+Respond in plain text (no markdown headings, no code blocks) with at most 40 lines: the projects, directories, and tools the user works with, then the distinctive commands they run, copied exactly with their flags. Leave out routine commands."#;
+
+/// How a response starts when the model can't help, as the default system prompt asks
+pub const ERROR_SIGIL: &str = "echo \"Error: ";
+
+/// Load context files from current directory and parent directories.
+/// Files closer to the current directory take precedence (loaded last).
+pub fn load_context_files(filenames: &[String]) -> String {
+    if filenames.is_empty() {
+        return String::new();
+    }
+
+    let mut context_parts: Vec<String> = Vec::new();
+
+    let Ok(cwd) = std::env::current_dir() else {
+        return String::new();
+    };
+
+    // Collect all parent directories from root to cwd
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    let mut current = Some(cwd.as_path());
+    while let Some(dir) = current {
+        dirs.push(dir.to_path_buf());
+        current = dir.parent();
+    }
+
+    // Process from root to cwd (so closer files are appended last)
+    dirs.reverse();
+
+    for dir in dirs {
+        for filename in filenames {
+            let path = dir.join(filename);
+            if path.exists() {
+                if let Ok(content) = std::fs::read_to_string(&path) {
+                    let content = content.trim();
+                    if !content.is_empty() {
+                        context_parts.push(format!("# {}\n\n{}", path.display(), content));
+                    }
+                }
+            }
+        }
+    }
+
+    context_parts.join("\n\n")
+}
+
+/// The shell commands are generated for and run with
+pub fn user_shell(config: &Config) -> String {
+    let default_shell = if cfg!(windows) { "pwsh" } else { "/bin/sh" };
+    config
+        .shell
+        .clone()
+        .or_else(|| if cfg!(windows) { None } else { std::env::var("SHELL").ok() })
+        .unwrap_or_else(|| default_shell.to_string())
+}
+
+/// Human-readable name of a shell, e.g. "/opt/homebrew/bin/fish" -> "fish"
+pub fn shell_name(shell: &str) -> String {
+    let name = std::path::Path::new(shell)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| shell.to_string());
+
+    match name.as_str() {
+        "pwsh" | "powershell" => "PowerShell".to_string(),
+        _ => name,
+    }
+}
+
+pub fn os_name() -> &'static str {
+    match std::env::consts::OS {
+        "macos" => "macOS",
+        "linux" => "Linux",
+        "windows" => "Windows",
+        "freebsd" => "FreeBSD",
+        "openbsd" => "OpenBSD",
+        "netbsd" => "NetBSD",
+        other => other,
+    }
+}
+
+/// Build the system prompt, with the parts least likely to change between requests first so
+/// providers can cache them: the instructions and context files, then the shell history,
+/// then the context sections gathered for this request.
+pub fn build_system_prompt(history: &str, context_files: &str, sections: &[Section], config: &Config, argv0: &str, shell: &str) -> SystemPrompt {
+    let base_prompt = config
+        .system_prompt
+        .as_deref()
+        .unwrap_or(DEFAULT_SYSTEM_PROMPT)
+        .replace("{}", argv0);
+
+    let mut instructions = base_prompt;
+
+    let shell = shell_name(shell);
+    instructions.push_str(&format!("\n\nGenerate commands for {} on {}, using {} syntax.", shell, os_name(), shell));
+    if cfg!(target_os = "macos") {
+        instructions.push_str(" The userland is BSD, not GNU, so use BSD-compatible flags.");
+    }
+    instructions.push_str("\n\n");
+    instructions.push_str(UNDO_INSTRUCTIONS);
+
+    with_context(instructions, history, context_files, sections, config)
+}
+
+/// Build the system prompt for `llm-exec ask` from the same context as `build_system_prompt`
+pub fn build_ask_system_prompt(history: &str, context_files: &str, sections: &[Section], config: &Config, shell: &str) -> SystemPrompt {
+    let shell = shell_name(shell);
+    let instructions = format!("{}\n\nThe user runs {} on {}.", ASK_SYSTEM_PROMPT, shell, os_name());
+    with_context(instructions, history, context_files, sections, config)
+}
+
+/// Follow the instructions with the context files and suffix, then the shell history and context sections
+fn with_context(mut instructions: String, history: &str, context_files: &str, sections: &[Section], config: &Config) -> SystemPrompt {
+    if !context_files.is_empty() {
+        instructions.push_str("\n\n");
+        instructions.push_str(context_files);
+    }
+
+    if let Some(suffix) = &config.system_prompt_suffix {
+        instructions.push_str("\n\n");
+        instructions.push_str(suffix);
+    }
+
+    let cache = config.prompt_cache.unwrap_or(true);
+    let mut system_prompt = SystemPrompt::default();
+    system_prompt.push(instructions, cache);
+    if !history.is_empty() {
+        system_prompt.push(
+            format!("The user's recent shell history:\n{}", history),
+            cache && config.prompt_cache_history.unwrap_or(false),
+        );
+    }
+    for section in sections {
+        system_prompt.push(format!("{}:\n{}", section.title, section.body), false);
+    }
+
+    system_prompt
+}
+
+/// Split the model's response into the command and the undo command that follows it, if any
+pub fn split_undo(response: &str) -> (String, Option<String>) {
+    match undo_line_start(response) {
+        Some(start) => {
+            let undo = response[start..].trim_start().trim_start_matches(UNDO_MARKER).trim();
+            (response[..start].trim().to_string(), (!undo.is_empty()).then(|| undo.to_string()))
+        }
+        None => (response.trim().to_string(), None),
+    }
+}
+
+/// Byte offset of the line starting with `UNDO_MARKER`
+pub fn undo_line_start(text: &str) -> Option<usize> {
+    let mut start = 0;
+    for line in text.split_inclusive('\n') {
+        if start > 0 && line.trim_start().starts_with(UNDO_MARKER) {
+            return Some(start);
+        }
+        start += line.len();
+    }
+    None
+}
+
+/// The text inside a markdown code fence wrapping the whole response, or the response as is
+pub fn strip_code_fence(response: &str) -> &str {
+    let trimmed = response.trim();
+    let Some(inner) = trimmed.strip_prefix("```").and_then(|rest| rest.strip_suffix("```")) else {
+        return trimmed;
+    };
+    // Drop the language tag on the opening line
+    inner.split_once('\n').map_or(inner, |(_, body)| body).trim()
+}
+
+/// A suggestion without the formatting models sometimes add despite the instructions: it's cut
+/// down to the first code block if there is one (keeping an undo line after it), and loses
+/// surrounding backticks, lines of prose before the command, like "Here's the command:", and
+/// the explanation after it, from the first line that reads like a sentence. If it still has
+/// several paragraphs, it's prose rather than a command, so it's replaced by the error sigil
+/// and handled like a refusal.
+pub fn normalize_response(response: &str) -> String {
+    let response = response.trim().replace("\r\n", "\n");
+    if response.starts_with(ERROR_SIGIL) {
+        return response;
+    }
+
+    let (text, fenced) = match response.split_once("```") {
+        Some((_, fenced)) => {
+            // Drop the language tag on the opening line
+            let body = fenced.split_once('\n').map_or("", |(_, body)| body);
+            let (block, after) = body.split_once("```").unwrap_or((body, ""));
+            let text = match after.lines().find(|line| line.trim_start().starts_with(UNDO_MARKER)) {
+                Some(undo) => format!("{}\n{}", block.trim(), undo.trim()),
+                None => block.trim().to_string(),
+            };
+            (text, true)
+        }
+        None => (response, false),
+    };
+
+    let (command, undo) = split_undo(&text);
+    let mut lines: Vec<&str> = command.lines().collect();
+    while lines.len() > 1 && is_prose_intro(lines[0]) {
+        lines.remove(0);
+    }
+    // Outside a code block, an explanation may follow the command; running it would fail at best
+    if !fenced {
+        if let Some(end) = lines.iter().skip(1).position(|line| is_prose_sentence(line)) {
+            lines.truncate(end + 1);
+        }
+    }
+    let command = lines.join("\n");
+    let command = unquote_backticks(command.trim());
+
+    if command.contains("\n\n") {
+        return format!("{}The model replied with an explanation instead of a command; try rephrasing the request\"", ERROR_SIGIL);
+    }
+    match undo {
+        Some(undo) => format!("{}\n{} {}", command, UNDO_MARKER, undo),
+        None => command.to_string(),
+    }
+}
+
+/// Whether a line reads like an introduction to the command, such as "Here's the command:"
+fn is_prose_intro(line: &str) -> bool {
+    let line = line.trim();
+    line.ends_with(':') && line.contains(' ') && line.starts_with(char::is_uppercase)
+}
+
+/// Whether a line reads like a sentence explaining the command, such as "This lists all files."
+fn is_prose_sentence(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with(char::is_uppercase)
+        && line.contains(' ')
+        && line.ends_with(['.', '!', '?'])
+        && !line.contains(['|', '&', ';', '<', '>', '$', '`', '\\', '='])
+}
+
+/// A command wrapped in single backticks, like `ls -la`, without them
+fn unquote_backticks(command: &str) -> &str {
+    match command.strip_prefix('`').and_then(|rest| rest.strip_suffix('`')) {
+        Some(inner) if !inner.contains('`') && !inner.trim().is_empty() => inner.trim(),
+        _ => command,
+    }
+}
+
+/// Fill in a `templates` entry's `{placeholder}`s from `name=value` arguments. Any other arguments
+/// are added to the end of the prompt.
+pub fn expand_template(template: &str, args: &[String]) -> Result<String, Box<dyn std::error::Error>> {
+    let mut values = HashMap::new();
+    let mut extra = Vec::new();
+    for arg in args {
+        match arg.split_once('=') {
+            Some((name, value)) if template.contains(&format!("{{{}}}", name)) => {
+                values.insert(name, value);
+            }
+            _ => extra.push(arg.as_str()),
+        }
+    }
+
+    let mut prompt = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        prompt.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let name = after.find('}').map(|end| &after[..end]).filter(|name| {
+            !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        });
+        match name {
+            Some(name) => {
+                let value = values.get(name).ok_or_else(|| format!("The template needs a value for {{{}}} (add {}=...)", name, name))?;
+                prompt.push_str(value);
+                rest = &after[name.len() + 1..];
+            }
+            None => {
+                prompt.push('{');
+                rest = after;
+            }
+        }
+    }
+    prompt.push_str(rest);
+
+    if !extra.is_empty() {
+        prompt.push(' ');
+        prompt.push_str(&extra.join(" "));
+    }
+    Ok(prompt)
+}
+
+/// The steps of a numbered list like "1. Find the large files", as `PLAN_INSTRUCTIONS` asks for
+pub fn plan_steps(response: &str) -> Vec<String> {
+    response
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_start();
+            let rest = line.trim_start_matches(|c: char| c.is_ascii_digit());
+            if rest.len() == line.len() {
+                return None;
+            }
+            let step = rest.strip_prefix(['.', ')'])?.trim();
+            (!step.is_empty()).then(|| step.to_string())
+        })
+        .collect()
+}
+
+/// The reason the model gave for not suggesting a command, if the command is its error sigil
+pub fn refusal(command: &str) -> Option<&str> {
+    command.strip_prefix(ERROR_SIGIL).map(|reason| reason.strip_suffix('"').unwrap_or(reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_response_keeps_fenced_block() {
+        assert_eq!(normalize_response("Here you go:\n```bash\nls -la\n```\nThis lists all files."), "ls -la");
+        assert_eq!(normalize_response("```\nfind . -name '*.rs'\n```\n# undo: true"), "find . -name '*.rs'\n# undo: true");
+    }
+
+    #[test]
+    fn normalize_response_drops_intro_lines() {
+        assert_eq!(normalize_response("Here's the command:\ndu -sh *"), "du -sh *");
+        assert_eq!(normalize_response("`git status`"), "git status");
+    }
+
+    #[test]
+    fn normalize_response_drops_trailing_prose() {
+        assert_eq!(normalize_response("ls -la\nThis lists all files."), "ls -la");
+        assert_eq!(normalize_response("rm old.txt\nThis deletes the file.\n# undo: true"), "rm old.txt\n# undo: true");
+        assert_eq!(normalize_response("tar -czf out.tgz \\\n  src"), "tar -czf out.tgz \\\n  src");
+    }
+
+    #[test]
+    fn normalize_response_rejects_paragraphs() {
+        assert!(refusal(&normalize_response("ls\n\nls lists files")).is_some());
+    }
+}
@@ -0,0 +1,546 @@
+use serde::{Deserialize, Serialize};
+
+use super::{for_each_line, ApiKey, Completion, Http, ModelInfo, Provider, Sampling, StatusError, ToolSpec, ToolUse, Usage};
+use crate::conversation::{SystemPrompt, ToolCall, Turn};
+use crate::config::Config;
+use crate::DEFAULT_MAX_TOKENS;
+
+pub(super) const DEFAULT_API_KEY_ENV: &str = "ANTHROPIC_API_KEY";
+pub(super) const DEFAULT_MODEL: &str = "claude-haiku-4-5-20251001";
+/// Short names for the latest model of each tier
+pub(super) const MODEL_ALIASES: &[(&str, &str)] = &[
+    ("haiku", "claude-haiku-4-5-20251001"),
+    ("sonnet", "claude-sonnet-4-5-20250929"),
+    ("opus", "claude-opus-4-1-20250805"),
+];
+const API_URL: &str = "https://api.anthropic.com/v1/messages";
+const MODELS_URL: &str = "https://api.anthropic.com/v1/models";
+const API_VERSION: &str = "2023-06-01";
+/// The smallest `thinking_budget` the API accepts
+const MIN_THINKING_BUDGET: u32 = 1024;
+
+#[derive(Serialize)]
+pub(super) struct Message {
+    role: String,
+    content: MessageContent,
+}
+
+impl Message {
+    pub(super) fn new(turn: &Turn) -> Self {
+        let content = if !turn.tool_results.is_empty() {
+            MessageContent::Blocks(
+                turn.tool_results
+                    .iter()
+                    .map(|result| InputBlock::ToolResult {
+                        tool_use_id: result.id.clone(),
+                        content: result.content.clone(),
+                        is_error: result.is_error,
+                    })
+                    .collect(),
+            )
+        } else if !turn.tool_calls.is_empty() {
+            MessageContent::Blocks(
+                turn.tool_calls
+                    .iter()
+                    .map(|call| InputBlock::ToolUse {
+                        id: call.id.clone(),
+                        name: call.name.clone(),
+                        input: call.arguments.clone(),
+                    })
+                    .collect(),
+            )
+        } else if turn.images.is_empty() {
+            MessageContent::Text(turn.content.clone())
+        } else {
+            // Images go before the text that refers to them
+            let mut blocks: Vec<InputBlock> = turn
+                .images
+                .iter()
+                .map(|image| InputBlock::Image {
+                    source: ImageSource {
+                        kind: "base64",
+                        media_type: image.media_type.clone(),
+                        data: image.data.clone(),
+                    },
+                })
+                .collect();
+            blocks.push(InputBlock::Text { text: turn.content.clone() });
+            MessageContent::Blocks(blocks)
+        };
+        Message {
+            role: turn.role.as_str().to_string(),
+            content,
+        }
+    }
+}
+
+/// Plain text, text with images, or tool calls or their results
+#[derive(Serialize)]
+#[serde(untagged)]
+enum MessageContent {
+    Text(String),
+    Blocks(Vec<InputBlock>),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum InputBlock {
+    Image { source: ImageSource },
+    Text { text: String },
+    ToolUse { id: String, name: String, input: serde_json::Value },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        is_error: bool,
+    },
+}
+
+#[derive(Serialize)]
+struct ImageSource {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    media_type: String,
+    data: String,
+}
+
+#[derive(Serialize)]
+struct CacheControl {
+    #[serde(rename = "type")]
+    kind: &'static str,
+}
+
+/// A system prompt block, marked to be cached up to its end if requested
+#[derive(Serialize)]
+pub(super) struct SystemBlock {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
+}
+
+/// The system prompt as text blocks, with `cache_control` on the ones marked for caching
+pub(super) fn system_blocks(system: &SystemPrompt) -> Vec<SystemBlock> {
+    system
+        .blocks()
+        .iter()
+        .filter(|block| !block.text.is_empty())
+        .map(|block| SystemBlock {
+            kind: "text",
+            text: block.text.clone(),
+            cache_control: block.cache.then_some(CacheControl { kind: "ephemeral" }),
+        })
+        .collect()
+}
+
+/// Extended thinking, with the most tokens the model may think for
+#[derive(Serialize, Clone, Copy)]
+pub(super) struct Thinking {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    pub(super) budget_tokens: u32,
+}
+
+impl Thinking {
+    /// Extended thinking with the `thinking_budget` from the config, if it's on
+    pub(super) fn new(config: &Config) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        match config.thinking_budget {
+            None | Some(0) => Ok(None),
+            Some(budget) if budget < MIN_THINKING_BUDGET => {
+                Err(format!("thinking_budget must be at least {} tokens (or 0 to turn thinking off), not {}", MIN_THINKING_BUDGET, budget).into())
+            }
+            Some(budget_tokens) => Ok(Some(Thinking { kind: "enabled", budget_tokens })),
+        }
+    }
+}
+
+/// The sampling settings from the config, without `temperature` and `top_k` when thinking,
+/// since the API rejects them then
+pub(super) fn sampling(config: &Config, thinking: Option<Thinking>) -> Sampling {
+    let mut sampling = Sampling::new(config);
+    if thinking.is_some() && (sampling.temperature.is_some() || sampling.top_k.is_some()) {
+        tracing::warn!("ignoring temperature and top_k, which can't be used with extended thinking");
+        sampling.temperature = None;
+        sampling.top_k = None;
+    }
+    sampling
+}
+
+/// `max_tokens` from the config, plus room for thinking, which counts towards it
+pub(super) fn max_tokens(config: &Config, thinking: Option<Thinking>) -> u32 {
+    config.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS) + thinking.map_or(0, |thinking| thinking.budget_tokens)
+}
+
+/// A tool the model is offered
+#[derive(Serialize)]
+pub(super) struct ToolDefinition {
+    name: String,
+    description: String,
+    input_schema: serde_json::Value,
+}
+
+/// Makes the model reply by calling the named tool
+#[derive(Serialize)]
+pub(super) struct ToolChoice {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    name: String,
+}
+
+/// The `tools` and `tool_choice` that make the model call `tool`
+pub(super) fn forced_tool(tool: &ToolSpec) -> (Vec<ToolDefinition>, Option<ToolChoice>) {
+    let choice = ToolChoice { kind: "tool", name: tool.name.clone() };
+    (offered_tools(std::slice::from_ref(tool)), Some(choice))
+}
+
+/// The `tools` the model may call if it chooses to
+pub(super) fn offered_tools(tools: &[ToolSpec]) -> Vec<ToolDefinition> {
+    tools
+        .iter()
+        .map(|tool| ToolDefinition {
+            name: tool.name.clone(),
+            description: tool.description.clone(),
+            input_schema: tool.parameters.clone(),
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct AnthropicRequest {
+    model: String,
+    max_tokens: u32,
+    system: Vec<SystemBlock>,
+    messages: Vec<Message>,
+    #[serde(flatten)]
+    sampling: Sampling,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<Thinking>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolDefinition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<ToolChoice>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+/// A text block, a `thinking` block if extended thinking is on, or a `tool_use` block with the tool's input
+#[derive(Deserialize)]
+pub(super) struct ContentBlock {
+    pub(super) text: Option<String>,
+    pub(super) thinking: Option<String>,
+    pub(super) id: Option<String>,
+    pub(super) name: Option<String>,
+    pub(super) input: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct AnthropicUsage {
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
+    #[serde(default)]
+    cache_creation_input_tokens: u64,
+    #[serde(default)]
+    cache_read_input_tokens: u64,
+}
+
+impl From<AnthropicUsage> for Usage {
+    fn from(usage: AnthropicUsage) -> Self {
+        Usage {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            cache_write_tokens: usage.cache_creation_input_tokens,
+            cache_read_tokens: usage.cache_read_input_tokens,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub(super) struct AnthropicResponse {
+    pub(super) content: Vec<ContentBlock>,
+    pub(super) usage: Option<AnthropicUsage>,
+}
+
+impl AnthropicResponse {
+    /// The first text block, any thinking, and the reported usage, or `None` if there is no text
+    pub(super) fn into_completion(self) -> Option<Completion> {
+        let thinking: Vec<&str> = self.content.iter().filter_map(|block| block.thinking.as_deref()).collect();
+        let thinking = (!thinking.is_empty()).then(|| thinking.join("\n\n"));
+        let text = self.content.into_iter().find_map(|block| block.text)?;
+        Some(Completion {
+            text,
+            usage: self.usage.map(Usage::from),
+            thinking,
+        })
+    }
+
+    /// The input of the first tool call as JSON text, with the reported usage, or `None` if there isn't one
+    pub(super) fn into_tool_call(self) -> Option<Completion> {
+        let input = self.content.into_iter().find_map(|block| block.input)?;
+        Some(Completion {
+            text: input.to_string(),
+            usage: self.usage.map(Usage::from),
+            thinking: None,
+        })
+    }
+
+    /// The text and any tool calls, with the reported usage, or `None` if there's neither
+    pub(super) fn into_tool_use(self) -> Option<ToolUse> {
+        let mut text = String::new();
+        let mut calls = Vec::new();
+        for block in self.content {
+            match block {
+                ContentBlock { text: Some(block_text), .. } => text.push_str(&block_text),
+                ContentBlock { id: Some(id), name: Some(name), input: Some(arguments), .. } => calls.push(ToolCall { id, name, arguments }),
+                _ => {}
+            }
+        }
+        if text.is_empty() && calls.is_empty() {
+            return None;
+        }
+        Some(ToolUse {
+            completion: Completion {
+                text,
+                usage: self.usage.map(Usage::from),
+                thinking: None,
+            },
+            calls,
+        })
+    }
+}
+
+/// A `text_delta`, or a `thinking_delta` if extended thinking is on
+#[derive(Deserialize)]
+struct StreamDelta {
+    text: Option<String>,
+    thinking: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StreamError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct StreamMessage {
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize)]
+struct StreamEvent {
+    #[serde(rename = "type")]
+    kind: String,
+    delta: Option<StreamDelta>,
+    error: Option<StreamError>,
+    /// Present on `message_start`, carrying the input token count
+    message: Option<StreamMessage>,
+    /// Present on `message_delta`, carrying the running output token count
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize)]
+struct ModelEntry {
+    id: String,
+    max_input_tokens: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<ModelEntry>,
+    #[serde(default)]
+    has_more: bool,
+    last_id: Option<String>,
+}
+
+/// Anthropic's messages API (reads `ANTHROPIC_API_KEY` unless `api_key_env` says otherwise).
+pub struct Anthropic {
+    model: String,
+    max_tokens: u32,
+    sampling: Sampling,
+    thinking: Option<Thinking>,
+    http: Http,
+    api_key: ApiKey,
+}
+
+impl Anthropic {
+    pub(super) fn new(config: &Config, http: Http) -> Result<Self, Box<dyn std::error::Error>> {
+        let thinking = Thinking::new(config)?;
+        Ok(Anthropic {
+            model: config.model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+            max_tokens: max_tokens(config, thinking),
+            sampling: sampling(config, thinking),
+            thinking,
+            http,
+            api_key: ApiKey::new("anthropic", config, Some(DEFAULT_API_KEY_ENV)),
+        })
+    }
+
+    /// Send the conversation, offering the model `tools` and making it call one with `tool_choice`
+    async fn send(
+        &self,
+        system: &SystemPrompt,
+        messages: &[Turn],
+        stream: bool,
+        (tools, tool_choice): (Vec<ToolDefinition>, Option<ToolChoice>),
+    ) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+        let api_key = self.api_key.require()?;
+
+        let api_messages: Vec<Message> = messages.iter().map(Message::new).collect();
+
+        let request = AnthropicRequest {
+            model: self.model.clone(),
+            max_tokens: self.max_tokens,
+            system: system_blocks(system),
+            messages: api_messages,
+            sampling: self.sampling,
+            thinking: self.thinking,
+            tools,
+            tool_choice,
+            stream,
+        };
+
+        let request = self.http
+            .post(API_URL)
+            .header("x-api-key", api_key)
+            .header("anthropic-version", API_VERSION)
+            .header("content-type", "application/json")
+            .json(&request);
+        let response = self.http.send(request).await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await?;
+            return Err(StatusError { status, body }.into());
+        }
+
+        Ok(response)
+    }
+
+    /// Every model the API key can use, newest first
+    pub(super) async fn models(&self) -> Result<Vec<ModelInfo>, Box<dyn std::error::Error>> {
+        let api_key = self.api_key.require()?;
+        let mut models = Vec::new();
+        let mut after: Option<String> = None;
+        loop {
+            let mut url = format!("{}?limit=1000", MODELS_URL);
+            if let Some(id) = &after {
+                url.push_str(&format!("&after_id={}", id));
+            }
+            let request = self.http.get(&url).header("x-api-key", &api_key).header("anthropic-version", API_VERSION);
+            let response = self.http.send(request).await?;
+            if !response.status().is_success() {
+                let status = response.status();
+                let body = response.text().await?;
+                return Err(StatusError { status, body }.into());
+            }
+
+            let list: ModelList = response.json().await?;
+            models.extend(list.data.into_iter().map(|model| ModelInfo {
+                id: model.id,
+                context_tokens: model.max_input_tokens,
+            }));
+            match list.last_id.filter(|_| list.has_more) {
+                Some(last_id) => after = Some(last_id),
+                None => return Ok(models),
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Provider for Anthropic {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, system: &SystemPrompt, messages: &[Turn]) -> Result<Completion, Box<dyn std::error::Error>> {
+        let response = self.send(system, messages, false, Default::default()).await?;
+        let result: AnthropicResponse = response.json().await?;
+
+        result.into_completion().ok_or_else(|| "No response from Claude".into())
+    }
+
+    async fn complete_with_tool(&self, system: &SystemPrompt, messages: &[Turn], tool: &ToolSpec) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        // Extended thinking can't be combined with making the model call a particular tool
+        if self.thinking.is_some() {
+            return Ok(None);
+        }
+        let response = self.send(system, messages, false, forced_tool(tool)).await?;
+        let result: AnthropicResponse = response.json().await?;
+
+        result.into_tool_call().map(Some).ok_or_else(|| "Claude didn't call the tool".into())
+    }
+
+    async fn complete_with_tools(&self, system: &SystemPrompt, messages: &[Turn], tools: &[ToolSpec]) -> Result<Option<ToolUse>, Box<dyn std::error::Error>> {
+        // With extended thinking, each thinking block would have to be sent back with the tool calls
+        if self.thinking.is_some() {
+            return Ok(None);
+        }
+        let response = self.send(system, messages, false, (offered_tools(tools), None)).await?;
+        let result: AnthropicResponse = response.json().await?;
+
+        result.into_tool_use().map(Some).ok_or_else(|| "No response from Claude".into())
+    }
+
+    async fn stream(
+        &self,
+        system: &SystemPrompt,
+        messages: &[Turn],
+        on_token: &mut dyn for<'t> FnMut(&'t str),
+    ) -> Result<Completion, Box<dyn std::error::Error>> {
+        let response = self.send(system, messages, true, Default::default()).await?;
+        let mut text = String::new();
+        let mut thinking = String::new();
+        let mut usage = Usage::default();
+
+        for_each_line(response, |line| {
+            let Some(data) = line.strip_prefix("data: ") else {
+                return Ok(());
+            };
+            let event: StreamEvent = serde_json::from_str(data)?;
+            match event.kind.as_str() {
+                "message_start" => {
+                    if let Some(start) = event.message.and_then(|m| m.usage) {
+                        usage = start.into();
+                    }
+                }
+                "message_delta" => {
+                    if let Some(delta) = event.usage {
+                        usage.output_tokens = delta.output_tokens;
+                    }
+                }
+                "content_block_delta" => {
+                    let Some(delta) = event.delta else {
+                        return Ok(());
+                    };
+                    if let Some(token) = delta.text {
+                        on_token(&token);
+                        text.push_str(&token);
+                    }
+                    if let Some(token) = delta.thinking {
+                        thinking.push_str(&token);
+                    }
+                }
+                "error" => {
+                    let message = event.error.map(|e| e.message).unwrap_or_default();
+                    return Err(format!("API error: {}", message).into());
+                }
+                _ => {}
+            }
+            Ok(())
+        })
+        .await?;
+
+        if text.is_empty() {
+            return Err("No response from Claude".into());
+        }
+
+        Ok(Completion {
+            text,
+            usage: Some(usage),
+            thinking: (!thinking.is_empty()).then_some(thinking),
+        })
+    }
+}
@@ -0,0 +1,537 @@
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use super::anthropic::{forced_tool, max_tokens, offered_tools, sampling, system_blocks, AnthropicResponse, Message, SystemBlock, Thinking, ToolChoice, ToolDefinition};
+use super::{Completion, Http, Provider, Sampling, StatusError, ToolSpec, ToolUse};
+use crate::conversation::{SystemPrompt, Turn};
+use crate::config::Config;
+
+pub(super) const DEFAULT_MODEL: &str = "us.anthropic.claude-haiku-4-5-20251001-v1:0";
+/// Short names for the latest Claude model of each tier, through the US cross-region inference profiles
+pub(super) const MODEL_ALIASES: &[(&str, &str)] = &[
+    ("haiku", "us.anthropic.claude-haiku-4-5-20251001-v1:0"),
+    ("sonnet", "us.anthropic.claude-sonnet-4-5-20250929-v1:0"),
+    ("opus", "us.anthropic.claude-opus-4-1-20250805-v1:0"),
+];
+const DEFAULT_REGION: &str = "us-east-1";
+const BEDROCK_ANTHROPIC_VERSION: &str = "bedrock-2023-05-31";
+const SERVICE: &str = "bedrock";
+/// Where ECS tasks get their role's credentials when `AWS_CONTAINER_CREDENTIALS_RELATIVE_URI` is set
+const CONTAINER_CREDENTIALS_HOST: &str = "http://169.254.170.2";
+/// The EC2 instance metadata service
+const IMDS_URL: &str = "http://169.254.169.254/latest";
+/// How long to wait for the container or instance metadata services, which don't exist off AWS
+const METADATA_TIMEOUT: Duration = Duration::from_secs(1);
+/// Fetch new credentials when the cached ones expire within this long
+const EXPIRY_MARGIN: chrono::TimeDelta = chrono::TimeDelta::minutes(5);
+
+#[derive(Serialize)]
+struct BedrockRequest {
+    anthropic_version: &'static str,
+    max_tokens: u32,
+    system: Vec<SystemBlock>,
+    messages: Vec<Message>,
+    #[serde(flatten)]
+    sampling: Sampling,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<Thinking>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolDefinition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<ToolChoice>,
+}
+
+#[derive(Clone)]
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    /// When temporary credentials stop working
+    expires: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Credentials {
+    fn expiring(&self) -> bool {
+        self.expires.is_some_and(|expires| expires - chrono::Utc::now() < EXPIRY_MARGIN)
+    }
+}
+
+/// Credentials as printed by a `credential_process` or `aws configure export-credentials`, and
+/// served by the container and instance metadata services (which call the session token `Token`)
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct JsonCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    #[serde(alias = "Token")]
+    session_token: Option<String>,
+    expiration: Option<String>,
+}
+
+impl From<JsonCredentials> for Credentials {
+    fn from(json: JsonCredentials) -> Self {
+        Credentials {
+            access_key_id: json.access_key_id,
+            secret_access_key: json.secret_access_key,
+            session_token: json.session_token.filter(|token| !token.is_empty()),
+            expires: json
+                .expiration
+                .and_then(|expiration| chrono::DateTime::parse_from_rfc3339(&expiration).ok())
+                .map(|expires| expires.to_utc()),
+        }
+    }
+}
+
+/// Anthropic models hosted on AWS Bedrock, authenticated with SigV4.
+pub struct Bedrock {
+    model: String,
+    max_tokens: u32,
+    sampling: Sampling,
+    thinking: Option<Thinking>,
+    http: Http,
+    region: Option<String>,
+    profile: Option<String>,
+    /// The last credentials found, reused until they're about to expire
+    credentials: RefCell<Option<Credentials>>,
+}
+
+impl Bedrock {
+    pub(super) fn new(config: &Config, http: Http) -> Result<Self, Box<dyn std::error::Error>> {
+        let thinking = Thinking::new(config)?;
+        Ok(Bedrock {
+            model: config.model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+            max_tokens: max_tokens(config, thinking),
+            sampling: sampling(config, thinking),
+            thinking,
+            http,
+            region: config.aws_region.clone(),
+            profile: config.aws_profile.clone(),
+            credentials: RefCell::new(None),
+        })
+    }
+
+    fn profile(&self) -> String {
+        self.profile
+            .clone()
+            .or_else(|| std::env::var("AWS_PROFILE").ok())
+            .unwrap_or_else(|| "default".to_string())
+    }
+
+    /// Region from config, then `AWS_REGION`/`AWS_DEFAULT_REGION`, then `~/.aws/config`
+    fn region(&self) -> String {
+        self.region
+            .clone()
+            .or_else(|| std::env::var("AWS_REGION").ok())
+            .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+            .or_else(|| {
+                let profile = self.profile();
+                let section = if profile == "default" { profile } else { format!("profile {}", profile) };
+                read_ini_value(aws_file("AWS_CONFIG_FILE", "config")?, &section, "region")
+            })
+            .unwrap_or_else(|| DEFAULT_REGION.to_string())
+    }
+
+    /// Credentials from the standard AWS chain, reusing the last ones until they're about to expire
+    async fn credentials(&self) -> Result<Credentials, Box<dyn std::error::Error>> {
+        if let Some(credentials) = self.credentials.borrow().as_ref().filter(|credentials| !credentials.expiring()) {
+            return Ok(credentials.clone());
+        }
+        let credentials = self.find_credentials().await?;
+        *self.credentials.borrow_mut() = Some(credentials.clone());
+        Ok(credentials)
+    }
+
+    /// Credentials from the environment, then the profile, then the ECS task role, then the
+    /// EC2 instance role
+    async fn find_credentials(&self) -> Result<Credentials, Box<dyn std::error::Error>> {
+        if let (Ok(access_key_id), Ok(secret_access_key)) =
+            (std::env::var("AWS_ACCESS_KEY_ID"), std::env::var("AWS_SECRET_ACCESS_KEY"))
+        {
+            return Ok(Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+                expires: None,
+            });
+        }
+
+        let profile = self.profile();
+        if let Some(credentials) = profile_credentials(&profile)? {
+            return Ok(credentials);
+        }
+        if let Some(credentials) = container_credentials().await? {
+            return Ok(credentials);
+        }
+        if let Ok(credentials) = instance_credentials().await {
+            return Ok(credentials);
+        }
+        Err(format!(
+            "Could not find AWS credentials for profile \"{}\" (set AWS_ACCESS_KEY_ID/AWS_SECRET_ACCESS_KEY, add keys, a credential_process, or SSO to the profile in ~/.aws, or run with an ECS task or EC2 instance role)",
+            profile
+        )
+        .into())
+    }
+
+    /// Send the conversation to the model, offering it `tools` and making it call one with `tool_choice`
+    async fn invoke(
+        &self,
+        system: &SystemPrompt,
+        messages: &[Turn],
+        (tools, tool_choice): (Vec<ToolDefinition>, Option<ToolChoice>),
+    ) -> Result<AnthropicResponse, Box<dyn std::error::Error>> {
+        let credentials = self.credentials().await?;
+        let region = self.region();
+
+        let request = BedrockRequest {
+            anthropic_version: BEDROCK_ANTHROPIC_VERSION,
+            max_tokens: self.max_tokens,
+            system: system_blocks(system),
+            messages: messages.iter().map(Message::new).collect(),
+            sampling: self.sampling,
+            thinking: self.thinking,
+            tools,
+            tool_choice,
+        };
+        let body = serde_json::to_vec(&request)?;
+
+        let host = format!("bedrock-runtime.{}.amazonaws.com", region);
+        let path = format!("/model/{}/invoke", uri_encode(&self.model));
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let authorization = sign(&credentials, &region, &host, &path, &amz_date, &body);
+
+        let mut builder = self.http
+            .post(format!("https://{}{}", host, path))
+            .header("content-type", "application/json")
+            .header("x-amz-date", &amz_date)
+            .header("authorization", authorization);
+        if let Some(token) = &credentials.session_token {
+            builder = builder.header("x-amz-security-token", token);
+        }
+        let response = self.http.send(builder.body(body)).await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await?;
+            return Err(StatusError { status, body }.into());
+        }
+
+        Ok(response.json().await?)
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Provider for Bedrock {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, system: &SystemPrompt, messages: &[Turn]) -> Result<Completion, Box<dyn std::error::Error>> {
+        let result = self.invoke(system, messages, Default::default()).await?;
+        result.into_completion().ok_or_else(|| "No response from Bedrock".into())
+    }
+
+    async fn complete_with_tool(&self, system: &SystemPrompt, messages: &[Turn], tool: &ToolSpec) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        // Extended thinking can't be combined with making the model call a particular tool
+        if self.thinking.is_some() {
+            return Ok(None);
+        }
+        let result = self.invoke(system, messages, forced_tool(tool)).await?;
+        result.into_tool_call().map(Some).ok_or_else(|| "The model didn't call the tool".into())
+    }
+
+    async fn complete_with_tools(&self, system: &SystemPrompt, messages: &[Turn], tools: &[ToolSpec]) -> Result<Option<ToolUse>, Box<dyn std::error::Error>> {
+        // With extended thinking, each thinking block would have to be sent back with the tool calls
+        if self.thinking.is_some() {
+            return Ok(None);
+        }
+        let result = self.invoke(system, messages, (offered_tools(tools), None)).await?;
+        result.into_tool_use().map(Some).ok_or_else(|| "No response from Bedrock".into())
+    }
+}
+
+/// Build the SigV4 `Authorization` header for a Bedrock runtime POST.
+fn sign(credentials: &Credentials, region: &str, host: &str, path: &str, amz_date: &str, body: &[u8]) -> String {
+    let date_stamp = &amz_date[..8];
+    let (canonical_request, signed_headers) = canonical_request(credentials, host, path, amz_date, body);
+    let scope = format!("{}/{}/{}/aws4_request", date_stamp, region, SERVICE);
+    let string_to_sign = string_to_sign(amz_date, &scope, &canonical_request);
+    let signing_key = signing_key(&credentials.secret_access_key, date_stamp, region, SERVICE);
+    let signature = hex(&hmac(&signing_key, string_to_sign.as_bytes()));
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key_id, scope, signed_headers, signature
+    )
+}
+
+/// The SigV4 canonical request for a Bedrock runtime POST, and the names of the headers it signs.
+fn canonical_request(credentials: &Credentials, host: &str, path: &str, amz_date: &str, body: &[u8]) -> (String, String) {
+    let mut headers = vec![
+        ("content-type", "application/json".to_string()),
+        ("host", host.to_string()),
+        ("x-amz-date", amz_date.to_string()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+
+    let canonical_headers: String = headers.iter().map(|(k, v)| format!("{}:{}\n", k, v.trim())).collect();
+    let signed_headers = headers.iter().map(|(k, _)| *k).collect::<Vec<_>>().join(";");
+
+    // Non-S3 services expect each path segment to be encoded a second time
+    let canonical_uri = path.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+
+    let canonical_request = format!(
+        "POST\n{}\n\n{}\n{}\n{}",
+        canonical_uri,
+        canonical_headers,
+        signed_headers,
+        hex(&Sha256::digest(body))
+    );
+    (canonical_request, signed_headers)
+}
+
+fn string_to_sign(amz_date: &str, scope: &str, canonical_request: &str) -> String {
+    format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    )
+}
+
+fn signing_key(secret_access_key: &str, date_stamp: &str, region: &str, service: &str) -> Vec<u8> {
+    let k_date = hmac(format!("AWS4{}", secret_access_key).as_bytes(), date_stamp.as_bytes());
+    let k_region = hmac(&k_date, region.as_bytes());
+    let k_service = hmac(&k_region, service.as_bytes());
+    hmac(&k_service, b"aws4_request")
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Percent-encode everything except RFC 3986 unreserved characters, as SigV4 requires.
+fn uri_encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Credentials for `profile` from `~/.aws/credentials` or `~/.aws/config`: its keys, its
+/// `credential_process`, or for SSO and assumed roles, what the AWS CLI exports for it.
+/// `None` if the profile has none of these.
+fn profile_credentials(profile: &str) -> Result<Option<Credentials>, Box<dyn std::error::Error>> {
+    let config_section = if profile == "default" { profile.to_string() } else { format!("profile {}", profile) };
+    let files = [
+        aws_file("AWS_SHARED_CREDENTIALS_FILE", "credentials").map(|path| (path, profile.to_string())),
+        aws_file("AWS_CONFIG_FILE", "config").map(|path| (path, config_section)),
+    ];
+    let value = |key: &str| files.iter().flatten().find_map(|(path, section)| read_ini_value(path.clone(), section, key));
+
+    if let (Some(access_key_id), Some(secret_access_key)) = (value("aws_access_key_id"), value("aws_secret_access_key")) {
+        return Ok(Some(Credentials {
+            access_key_id,
+            secret_access_key,
+            session_token: value("aws_session_token"),
+            expires: None,
+        }));
+    }
+    if let Some(command) = value("credential_process") {
+        let output = run_credential_command(&command).map_err(|e| format!("credential_process for profile \"{}\" failed: {}", profile, e))?;
+        return Ok(Some(output));
+    }
+    // Signing in with SSO and assuming roles take the CLI's token cache and STS, so leave them to it
+    if ["sso_session", "sso_start_url", "role_arn"].iter().any(|key| value(key).is_some()) {
+        let command = format!("aws configure export-credentials --format process --profile '{}'", profile.replace('\'', ""));
+        let output = run_credential_command(&command).map_err(|e| {
+            format!("Could not get credentials for profile \"{}\" from the AWS CLI (version 2), which SSO and role profiles need (run `aws sso login` if it's expired): {}", profile, e)
+        })?;
+        return Ok(Some(output));
+    }
+    Ok(None)
+}
+
+/// Run a command through the shell that prints credentials as JSON
+fn run_credential_command(command: &str) -> Result<Credentials, Box<dyn std::error::Error>> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").arg("/C").arg(command).stderr(Stdio::inherit()).output()
+    } else {
+        Command::new("/bin/sh").arg("-c").arg(command).stderr(Stdio::inherit()).output()
+    }?;
+    if !output.status.success() {
+        return Err(format!("it exited with {}", output.status).into());
+    }
+    let credentials: JsonCredentials = serde_json::from_slice(&output.stdout)?;
+    Ok(credentials.into())
+}
+
+/// The ECS task role's credentials, if running in a container that provides them
+async fn container_credentials() -> Result<Option<Credentials>, Box<dyn std::error::Error>> {
+    let url = match (std::env::var("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI"), std::env::var("AWS_CONTAINER_CREDENTIALS_FULL_URI")) {
+        (Ok(relative), _) => format!("{}{}", CONTAINER_CREDENTIALS_HOST, relative),
+        (_, Ok(full)) => full,
+        _ => return Ok(None),
+    };
+    let token = match std::env::var("AWS_CONTAINER_AUTHORIZATION_TOKEN_FILE") {
+        Ok(path) => Some(std::fs::read_to_string(path)?.trim().to_string()),
+        Err(_) => std::env::var("AWS_CONTAINER_AUTHORIZATION_TOKEN").ok(),
+    };
+
+    let mut request = metadata_client()?.get(&url);
+    if let Some(token) = token {
+        request = request.header("authorization", token);
+    }
+    let response = request.send().await.map_err(|e| format!("Could not get container credentials from {}: {}", url, e))?;
+    if !response.status().is_success() {
+        return Err(format!("Could not get container credentials from {}: {}", url, response.status()).into());
+    }
+    let credentials: JsonCredentials = response.json().await?;
+    Ok(Some(credentials.into()))
+}
+
+/// The EC2 instance role's credentials, from the instance metadata service (IMDSv2)
+async fn instance_credentials() -> Result<Credentials, Box<dyn std::error::Error>> {
+    let client = metadata_client()?;
+    let token = client
+        .put(format!("{}/api/token", IMDS_URL))
+        .header("x-aws-ec2-metadata-token-ttl-seconds", "21600")
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let get = |path: String| client.get(format!("{}/meta-data/iam/security-credentials/{}", IMDS_URL, path)).header("x-aws-ec2-metadata-token", &token).send();
+
+    let roles = get(String::new()).await?.error_for_status()?.text().await?;
+    let role = roles.lines().next().ok_or("The instance has no IAM role")?.trim().to_string();
+    let credentials: JsonCredentials = get(role).await?.error_for_status()?.json().await?;
+    Ok(credentials.into())
+}
+
+/// A client for the container and instance metadata services, which are never behind a proxy
+fn metadata_client() -> Result<reqwest::Client, reqwest::Error> {
+    reqwest::Client::builder().no_proxy().connect_timeout(METADATA_TIMEOUT).timeout(METADATA_TIMEOUT * 3).build()
+}
+
+/// Locate an AWS shared file, honoring its override environment variable.
+fn aws_file(env_var: &str, name: &str) -> Option<PathBuf> {
+    let path = match std::env::var(env_var) {
+        Ok(p) => PathBuf::from(p),
+        Err(_) => dirs::home_dir()?.join(".aws").join(name),
+    };
+    path.exists().then_some(path)
+}
+
+/// Read `key` from `[section]` of an INI-style AWS config/credentials file.
+fn read_ini_value(path: PathBuf, section: &str, key: &str) -> Option<String> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let mut in_section = false;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_section = name.trim() == section;
+        } else if in_section {
+            if let Some((k, v)) = line.split_once('=') {
+                if k.trim() == key {
+                    return Some(v.trim().to_string());
+                }
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The credentials and date of the AWS SigV4 test suite
+    const ACCESS_KEY_ID: &str = "AKIDEXAMPLE";
+    const SECRET_ACCESS_KEY: &str = "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY";
+    const AMZ_DATE: &str = "20150830T123600Z";
+
+    #[test]
+    fn derives_the_signing_key_from_the_aws_example() {
+        let key = signing_key(SECRET_ACCESS_KEY, "20120215", "us-east-1", "iam");
+        assert_eq!(hex(&key), "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d");
+    }
+
+    #[test]
+    fn signs_the_aws_post_vanilla_test() {
+        let canonical_request = format!(
+            "POST\n/\n\nhost:example.amazonaws.com\nx-amz-date:{}\n\nhost;x-amz-date\n{}",
+            AMZ_DATE,
+            hex(&Sha256::digest(b""))
+        );
+        let string_to_sign = string_to_sign(AMZ_DATE, "20150830/us-east-1/service/aws4_request", &canonical_request);
+        assert_eq!(
+            string_to_sign,
+            "AWS4-HMAC-SHA256\n20150830T123600Z\n20150830/us-east-1/service/aws4_request\n553f88c9e4d10fc9e109e2aeb65f030801b70c2f6468faca261d401ae622fc87"
+        );
+        let key = signing_key(SECRET_ACCESS_KEY, "20150830", "us-east-1", "service");
+        assert_eq!(hex(&hmac(&key, string_to_sign.as_bytes())), "5da7c1a2acd57cee7505fc6676e4e544621c30862966e37dddb68e92efbe5d6b");
+    }
+
+    #[test]
+    fn signs_a_bedrock_request_with_a_session_token() {
+        let credentials = Credentials {
+            access_key_id: ACCESS_KEY_ID.to_string(),
+            secret_access_key: SECRET_ACCESS_KEY.to_string(),
+            session_token: Some("AQoDYXdzEXAMPLE/session+token=".to_string()),
+            expires: None,
+        };
+        let host = "bedrock-runtime.us-east-1.amazonaws.com";
+        let path = format!("/model/{}/invoke", uri_encode("us.anthropic.claude-haiku-4-5-20251001-v1:0"));
+        let body = br#"{"max_tokens":1}"#;
+
+        let (canonical_request, signed_headers) = canonical_request(&credentials, host, &path, AMZ_DATE, body);
+        assert_eq!(
+            canonical_request,
+            "POST\n\
+             /model/us.anthropic.claude-haiku-4-5-20251001-v1%253A0/invoke\n\
+             \n\
+             content-type:application/json\n\
+             host:bedrock-runtime.us-east-1.amazonaws.com\n\
+             x-amz-date:20150830T123600Z\n\
+             x-amz-security-token:AQoDYXdzEXAMPLE/session+token=\n\
+             \n\
+             content-type;host;x-amz-date;x-amz-security-token\n\
+             3dad411eb0f5c33818edd8c544777853ec18ac0c3302379c36079ea194193599"
+        );
+        assert_eq!(signed_headers, "content-type;host;x-amz-date;x-amz-security-token");
+        assert_eq!(
+            string_to_sign(AMZ_DATE, "20150830/us-east-1/bedrock/aws4_request", &canonical_request),
+            "AWS4-HMAC-SHA256\n20150830T123600Z\n20150830/us-east-1/bedrock/aws4_request\n67621cf4dfd7354a2b66bf9d7476ec3fef9f45ba1a3c502b77d8bc5a985ffdec"
+        );
+        assert_eq!(
+            sign(&credentials, "us-east-1", host, &path, AMZ_DATE, body),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/bedrock/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date;x-amz-security-token, \
+             Signature=a114505359645229b8a4f74807998b22f909211012b7da8469334c6dd6ad4cbb"
+        );
+    }
+
+    #[test]
+    fn uri_encode_leaves_only_unreserved_characters() {
+        assert_eq!(uri_encode("us.anthropic.claude-v1:0"), "us.anthropic.claude-v1%3A0");
+        assert_eq!(uri_encode("a b/c~d_e"), "a%20b%2Fc~d_e");
+    }
+}
@@ -0,0 +1,153 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Once};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+use super::{Completion, Provider, ToolSpec, ToolUse};
+use crate::conversation::{SystemPrompt, Turn};
+
+pub(super) const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+/// Set while a request is in flight, when Ctrl-C cancels the request rather than exiting
+static IN_REQUEST: AtomicBool = AtomicBool::new(false);
+/// Notified when Ctrl-C is pressed during a request
+static CANCEL: LazyLock<Notify> = LazyLock::new(Notify::new);
+static WATCH_CTRL_C: Once = Once::new();
+
+/// A request that took longer than `timeout_secs`
+#[derive(Debug)]
+pub struct TimedOut {
+    model: String,
+    secs: u64,
+}
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The request to {} timed out after {}s. Try again, or allow longer with --timeout or the timeout_secs config key.",
+            self.model, self.secs
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// A request the user cancelled with Ctrl-C
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Request cancelled")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// A request the provider failed, such as a network, authentication, or rate limit error
+#[derive(Debug)]
+pub struct ApiError(pub(super) Box<dyn std::error::Error>);
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Once Ctrl-C is handled it no longer exits the process, so outside of requests this
+/// exits the way the default handler would (with the status a shell reports for SIGINT).
+fn watch_ctrl_c() {
+    WATCH_CTRL_C.call_once(|| {
+        tokio::spawn(async {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if IN_REQUEST.load(Ordering::SeqCst) {
+                    CANCEL.notify_waiters();
+                } else {
+                    std::process::exit(130);
+                }
+            }
+        });
+    });
+}
+
+/// Wraps a provider so requests give up after the `timeout_secs` config key (or `--timeout`),
+/// and Ctrl-C drops the request instead of killing the process.
+pub(super) struct Deadline {
+    inner: Box<dyn Provider>,
+    /// `None` if `timeout_secs` is 0
+    timeout: Option<Duration>,
+}
+
+impl Deadline {
+    pub(super) fn new(inner: Box<dyn Provider>, timeout_secs: u64) -> Self {
+        Deadline {
+            inner,
+            timeout: (timeout_secs > 0).then(|| Duration::from_secs(timeout_secs)),
+        }
+    }
+
+    async fn run<T>(
+        &self,
+        request: impl std::future::Future<Output = Result<T, Box<dyn std::error::Error>>>,
+    ) -> Result<T, Box<dyn std::error::Error>> {
+        watch_ctrl_c();
+
+        // Register for the notification before a Ctrl-C could send it
+        let cancelled = CANCEL.notified();
+        tokio::pin!(cancelled);
+        cancelled.as_mut().enable();
+        IN_REQUEST.store(true, Ordering::SeqCst);
+
+        let request = async { request.await.map_err(|e| ApiError(e).into()) };
+        let result = tokio::select! {
+            result = async {
+                match self.timeout {
+                    Some(timeout) => tokio::time::timeout(timeout, request).await.unwrap_or_else(|_| {
+                        Err(TimedOut {
+                            model: self.inner.model().to_string(),
+                            secs: timeout.as_secs(),
+                        }
+                        .into())
+                    }),
+                    None => request.await,
+                }
+            } => result,
+            _ = cancelled => Err(Interrupted.into()),
+        };
+
+        IN_REQUEST.store(false, Ordering::SeqCst);
+        result
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Provider for Deadline {
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    async fn complete(&self, system: &SystemPrompt, messages: &[Turn]) -> Result<Completion, Box<dyn std::error::Error>> {
+        self.run(self.inner.complete(system, messages)).await
+    }
+
+    async fn stream(
+        &self,
+        system: &SystemPrompt,
+        messages: &[Turn],
+        on_token: &mut dyn for<'t> FnMut(&'t str),
+    ) -> Result<Completion, Box<dyn std::error::Error>> {
+        self.run(self.inner.stream(system, messages, on_token)).await
+    }
+
+    async fn complete_with_tool(&self, system: &SystemPrompt, messages: &[Turn], tool: &ToolSpec) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        self.run(self.inner.complete_with_tool(system, messages, tool)).await
+    }
+
+    async fn complete_with_tools(&self, system: &SystemPrompt, messages: &[Turn], tools: &[ToolSpec]) -> Result<Option<ToolUse>, Box<dyn std::error::Error>> {
+        self.run(self.inner.complete_with_tools(system, messages, tools)).await
+    }
+}
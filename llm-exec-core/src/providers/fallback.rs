@@ -0,0 +1,115 @@
+use std::cell::Cell;
+use std::ops::ControlFlow;
+
+use super::{ApiError, Completion, Interrupted, Provider, StatusError, TimedOut, ToolSpec, ToolUse};
+use crate::color;
+use crate::conversation::{SystemPrompt, Turn};
+
+/// Tries each provider in turn (the configured one, then the `fallbacks`) until one answers
+pub(super) struct Fallback {
+    /// Each provider with the `provider:model` it's called in notes
+    providers: Vec<(String, Box<dyn Provider>)>,
+    /// Index of the provider that answered the last request
+    served: Cell<usize>,
+}
+
+impl Fallback {
+    pub(super) fn new(providers: Vec<(String, Box<dyn Provider>)>) -> Self {
+        Fallback { providers, served: Cell::new(0) }
+    }
+
+    /// Send `request` to each provider until one succeeds or fails in a way the next one wouldn't fix
+    async fn run<'a, T, F>(&'a self, mut request: impl FnMut(&'a dyn Provider) -> F) -> Result<T, Box<dyn std::error::Error>>
+    where
+        F: std::future::Future<Output = Result<T, Box<dyn std::error::Error>>>,
+    {
+        for (index, (_, provider)) in self.providers.iter().enumerate() {
+            if let ControlFlow::Break(result) = self.finish(index, request(provider.as_ref()).await, true) {
+                return result;
+            }
+        }
+        unreachable!("the last provider's result is always returned")
+    }
+
+    /// Return the result of a request to the provider at `index`, or continue with the next
+    /// provider if `may_retry` and it might not fail the same way
+    fn finish<T>(&self, index: usize, result: Result<T, Box<dyn std::error::Error>>, may_retry: bool) -> ControlFlow<Result<T, Box<dyn std::error::Error>>> {
+        let label = &self.providers[index].0;
+        match result {
+            Ok(result) => {
+                self.served.set(index);
+                if index > 0 {
+                    eprintln!("\r{}", color::epaint(color::DIM, format!("(answered by {})", label)));
+                }
+                ControlFlow::Break(Ok(result))
+            }
+            Err(e) => match self.providers.get(index + 1) {
+                Some((next, _)) if may_retry && should_fall_back(e.as_ref()) => {
+                    eprintln!("\r{}", color::epaint(color::WARNING, format!("{} failed: {}; trying {}", label, e, next)));
+                    ControlFlow::Continue(())
+                }
+                _ => ControlFlow::Break(Err(e)),
+            },
+        }
+    }
+}
+
+/// Whether another provider might succeed where this error failed: on timeouts, connection
+/// and authentication failures, rate limits, unavailable models, and server errors, but not
+/// when the request was cancelled or the provider rejected it as invalid
+fn should_fall_back(error: &(dyn std::error::Error + 'static)) -> bool {
+    if error.is::<Interrupted>() {
+        return false;
+    }
+    if error.is::<TimedOut>() {
+        return true;
+    }
+    let Some(ApiError(inner)) = error.downcast_ref::<ApiError>() else {
+        return false;
+    };
+    match inner.downcast_ref::<StatusError>() {
+        Some(StatusError { status, .. }) => matches!(status.as_u16(), 401 | 403 | 404 | 408 | 429) || status.is_server_error(),
+        None => true,
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Provider for Fallback {
+    fn model(&self) -> &str {
+        self.providers[self.served.get()].1.model()
+    }
+
+    async fn complete(&self, system: &SystemPrompt, messages: &[Turn]) -> Result<Completion, Box<dyn std::error::Error>> {
+        self.run(|provider| provider.complete(system, messages)).await
+    }
+
+    async fn stream(
+        &self,
+        system: &SystemPrompt,
+        messages: &[Turn],
+        on_token: &mut dyn for<'t> FnMut(&'t str),
+    ) -> Result<Completion, Box<dyn std::error::Error>> {
+        // Once part of the reply has been shown, another provider's reply can't replace it
+        for (index, (_, provider)) in self.providers.iter().enumerate() {
+            let mut streamed = false;
+            let result = provider
+                .stream(system, messages, &mut |token| {
+                    streamed = true;
+                    on_token(token);
+                })
+                .await;
+            if let ControlFlow::Break(result) = self.finish(index, result, !streamed) {
+                return result;
+            }
+        }
+        unreachable!("the last provider's result is always returned")
+    }
+
+    async fn complete_with_tool(&self, system: &SystemPrompt, messages: &[Turn], tool: &ToolSpec) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        self.run(|provider| provider.complete_with_tool(system, messages, tool)).await
+    }
+
+    async fn complete_with_tools(&self, system: &SystemPrompt, messages: &[Turn], tools: &[ToolSpec]) -> Result<Option<ToolUse>, Box<dyn std::error::Error>> {
+        self.run(|provider| provider.complete_with_tools(system, messages, tools)).await
+    }
+}
@@ -0,0 +1,369 @@
+use serde::{Deserialize, Serialize};
+
+use super::{for_each_line, ApiKey, Completion, Http, ModelInfo, Provider, Sampling, StatusError, ToolSpec, Usage};
+use crate::conversation::{Role, SystemPrompt, Turn};
+use crate::config::Config;
+use crate::DEFAULT_MAX_TOKENS;
+
+pub(super) const DEFAULT_API_KEY_ENV: &str = "GEMINI_API_KEY";
+pub(super) const DEFAULT_MODEL: &str = "gemini-2.5-flash";
+const API_URL: &str = "https://generativelanguage.googleapis.com/v1beta/models";
+
+#[derive(Serialize, Deserialize)]
+struct Part {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    /// In requests, an attached image
+    #[serde(rename = "inlineData", skip_deserializing, skip_serializing_if = "Option::is_none")]
+    inline_data: Option<InlineData>,
+    /// In replies, a call to a function the model was offered
+    #[serde(rename = "functionCall", skip_serializing)]
+    function_call: Option<FunctionCall>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InlineData {
+    mime_type: String,
+    data: String,
+}
+
+#[derive(Deserialize)]
+struct FunctionCall {
+    args: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct FunctionDeclaration {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Tool {
+    function_declarations: Vec<FunctionDeclaration>,
+}
+
+/// Makes the model reply by calling one of the allowed functions
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FunctionCallingConfig {
+    mode: &'static str,
+    allowed_function_names: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ToolConfig {
+    function_calling_config: FunctionCallingConfig,
+}
+
+#[derive(Serialize)]
+struct Content {
+    role: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Serialize)]
+struct SystemInstruction {
+    parts: Vec<Part>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerationConfig {
+    max_output_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<u32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateRequest {
+    system_instruction: SystemInstruction,
+    contents: Vec<Content>,
+    generation_config: GenerationConfig,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Tool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_config: Option<ToolConfig>,
+}
+
+#[derive(Deserialize)]
+struct CandidateContent {
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Deserialize)]
+struct Candidate {
+    content: Option<CandidateContent>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u64,
+    #[serde(default)]
+    candidates_token_count: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateResponse {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    usage_metadata: Option<UsageMetadata>,
+}
+
+impl GenerateResponse {
+    /// Arguments of the first function call in the first candidate
+    fn function_args(&self) -> Option<&serde_json::Value> {
+        let content = self.candidates.first()?.content.as_ref()?;
+        content.parts.iter().find_map(|part| part.function_call.as_ref()).map(|call| &call.args)
+    }
+
+    /// Concatenated text parts of the first candidate
+    fn text(&self) -> String {
+        self.candidates
+            .first()
+            .and_then(|candidate| candidate.content.as_ref())
+            .map(|content| content.parts.iter().filter_map(|part| part.text.as_deref()).collect())
+            .unwrap_or_default()
+    }
+
+    fn usage(&self) -> Option<Usage> {
+        self.usage_metadata.as_ref().map(|usage| Usage {
+            input_tokens: usage.prompt_token_count,
+            output_tokens: usage.candidates_token_count,
+            ..Usage::default()
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ModelEntry {
+    /// `models/<id>`
+    name: String,
+    input_token_limit: Option<u64>,
+    #[serde(default)]
+    supported_generation_methods: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ModelList {
+    #[serde(default)]
+    models: Vec<ModelEntry>,
+    next_page_token: Option<String>,
+}
+
+/// Google's Gemini generateContent API (reads `GEMINI_API_KEY` unless `api_key_env` says otherwise).
+pub struct Gemini {
+    model: String,
+    max_tokens: u32,
+    sampling: Sampling,
+    http: Http,
+    api_key: ApiKey,
+}
+
+impl Gemini {
+    pub(super) fn new(config: &Config, http: Http) -> Self {
+        Gemini {
+            model: config.model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+            max_tokens: config.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            sampling: Sampling::new(config),
+            http,
+            api_key: ApiKey::new("gemini", config, Some(DEFAULT_API_KEY_ENV)),
+        }
+    }
+
+    async fn send(&self, system: &SystemPrompt, messages: &[Turn], stream: bool, tool: Option<&ToolSpec>) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+        let api_key = self.api_key.require()?;
+
+        // Gemini calls the assistant role "model"
+        let contents: Vec<Content> = messages
+            .iter()
+            .map(|turn| Content {
+                role: match turn.role {
+                    Role::User => "user".to_string(),
+                    Role::Assistant => "model".to_string(),
+                },
+                parts: turn
+                    .images
+                    .iter()
+                    .map(|image| Part {
+                        text: None,
+                        inline_data: Some(InlineData {
+                            mime_type: image.media_type.clone(),
+                            data: image.data.clone(),
+                        }),
+                        function_call: None,
+                    })
+                    .chain([Part {
+                        text: Some(turn.content.clone()),
+                        inline_data: None,
+                        function_call: None,
+                    }])
+                    .collect(),
+            })
+            .collect();
+
+        let request = GenerateRequest {
+            system_instruction: SystemInstruction {
+                parts: vec![Part {
+                    text: Some(system.text()),
+                    inline_data: None,
+                    function_call: None,
+                }],
+            },
+            contents,
+            generation_config: GenerationConfig {
+                max_output_tokens: self.max_tokens,
+                temperature: self.sampling.temperature,
+                top_p: self.sampling.top_p,
+                top_k: self.sampling.top_k,
+            },
+            tools: tool
+                .map(|tool| Tool {
+                    function_declarations: vec![FunctionDeclaration {
+                        name: tool.name.clone(),
+                        description: tool.description.clone(),
+                        parameters: tool.parameters.clone(),
+                    }],
+                })
+                .into_iter()
+                .collect(),
+            tool_config: tool.map(|tool| ToolConfig {
+                function_calling_config: FunctionCallingConfig {
+                    mode: "ANY",
+                    allowed_function_names: vec![tool.name.clone()],
+                },
+            }),
+        };
+
+        let url = if stream {
+            format!("{}/{}:streamGenerateContent?alt=sse", API_URL, self.model)
+        } else {
+            format!("{}/{}:generateContent", API_URL, self.model)
+        };
+        let request = self.http.post(&url).header("x-goog-api-key", api_key).json(&request);
+        let response = self.http.send(request).await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await?;
+            return Err(StatusError { status, body }.into());
+        }
+
+        Ok(response)
+    }
+
+    /// The models that can generate content with the API key
+    pub(super) async fn models(&self) -> Result<Vec<ModelInfo>, Box<dyn std::error::Error>> {
+        let api_key = self.api_key.require()?;
+        let mut models = Vec::new();
+        let mut page: Option<String> = None;
+        loop {
+            let mut url = format!("{}?pageSize=1000", API_URL);
+            if let Some(token) = &page {
+                url.push_str(&format!("&pageToken={}", token));
+            }
+            let response = self.http.send(self.http.get(&url).header("x-goog-api-key", &api_key)).await?;
+            if !response.status().is_success() {
+                let status = response.status();
+                let body = response.text().await?;
+                return Err(StatusError { status, body }.into());
+            }
+
+            let list: ModelList = response.json().await?;
+            models.extend(
+                list.models
+                    .into_iter()
+                    .filter(|model| model.supported_generation_methods.iter().any(|method| method == "generateContent"))
+                    .map(|model| ModelInfo {
+                        id: model.name.trim_start_matches("models/").to_string(),
+                        context_tokens: model.input_token_limit,
+                    }),
+            );
+            match list.next_page_token.filter(|token| !token.is_empty()) {
+                Some(token) => page = Some(token),
+                None => return Ok(models),
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Provider for Gemini {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, system: &SystemPrompt, messages: &[Turn]) -> Result<Completion, Box<dyn std::error::Error>> {
+        let response = self.send(system, messages, false, None).await?;
+        let result: GenerateResponse = response.json().await?;
+        let text = result.text();
+
+        if text.is_empty() {
+            return Err("No response from Gemini".into());
+        }
+
+        Ok(Completion {
+            text,
+            usage: result.usage(),
+            thinking: None,
+        })
+    }
+
+    async fn complete_with_tool(&self, system: &SystemPrompt, messages: &[Turn], tool: &ToolSpec) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        let response = self.send(system, messages, false, Some(tool)).await?;
+        let result: GenerateResponse = response.json().await?;
+        let args = result.function_args().ok_or("Gemini didn't call the tool")?;
+
+        Ok(Some(Completion {
+            text: args.to_string(),
+            usage: result.usage(),
+            thinking: None,
+        }))
+    }
+
+    async fn stream(
+        &self,
+        system: &SystemPrompt,
+        messages: &[Turn],
+        on_token: &mut dyn for<'t> FnMut(&'t str),
+    ) -> Result<Completion, Box<dyn std::error::Error>> {
+        let response = self.send(system, messages, true, None).await?;
+        let mut text = String::new();
+        let mut usage = None;
+
+        for_each_line(response, |line| {
+            let Some(data) = line.strip_prefix("data: ") else {
+                return Ok(());
+            };
+            let chunk: GenerateResponse = serde_json::from_str(data)?;
+            let token = chunk.text();
+            on_token(&token);
+            text.push_str(&token);
+            // Each chunk reports cumulative counts, so the last one wins
+            usage = chunk.usage().or(usage);
+            Ok(())
+        })
+        .await?;
+
+        if text.is_empty() {
+            return Err("No response from Gemini".into());
+        }
+
+        Ok(Completion { text, usage, thinking: None })
+    }
+}
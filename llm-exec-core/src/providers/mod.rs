@@ -0,0 +1,542 @@
+mod anthropic;
+mod bedrock;
+mod deadline;
+mod fallback;
+mod gemini;
+mod ollama;
+mod openai;
+
+use serde::Serialize;
+use std::cell::OnceCell;
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::conversation::{SystemPrompt, ToolCall, Turn};
+
+pub use anthropic::Anthropic;
+pub use bedrock::Bedrock;
+pub use deadline::{ApiError, Interrupted, TimedOut};
+pub use gemini::Gemini;
+pub use ollama::Ollama;
+pub use openai::OpenAi;
+
+pub const DEFAULT_PROVIDER: &str = "anthropic";
+const DEFAULT_MAX_ATTEMPTS: u32 = 4;
+/// Delay before the first retry, doubling (with jitter) for each one after
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// Give up rather than wait if the server asks for a longer pause than this
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Tokens consumed by a request, as reported by the provider
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Usage {
+    /// Input tokens that were neither written to nor read from the prompt cache
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// Input tokens written to the prompt cache
+    pub cache_write_tokens: u64,
+    /// Input tokens read from the prompt cache
+    pub cache_read_tokens: u64,
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_write_tokens += other.cache_write_tokens;
+        self.cache_read_tokens += other.cache_read_tokens;
+    }
+}
+
+/// A model reply, with token usage if the provider reported it
+#[derive(Default)]
+pub struct Completion {
+    pub text: String,
+    pub usage: Option<Usage>,
+    /// The model's reasoning before it replied, when extended thinking is on
+    pub thinking: Option<String>,
+}
+
+/// A model a provider offers, as listed by `llm-exec models`
+pub struct ModelInfo {
+    pub id: String,
+    /// Most input tokens the model accepts, if the provider says
+    pub context_tokens: Option<u64>,
+}
+
+/// An error status from the provider's API, after any retries
+#[derive(Debug)]
+pub struct StatusError {
+    pub status: reqwest::StatusCode,
+    pub body: String,
+}
+
+impl std::fmt::Display for StatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "API error ({}): {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// A request that couldn't be sent to a server, described like "Ollama at http://localhost:11434"
+#[derive(Debug)]
+pub struct Unreachable {
+    pub server: String,
+    pub source: reqwest::Error,
+}
+
+impl std::fmt::Display for Unreachable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Could not reach {}: {}", self.server, self.source)
+    }
+}
+
+impl std::error::Error for Unreachable {}
+
+/// Whether a request failed because the provider couldn't be reached or didn't answer, as
+/// opposed to answering with an error such as a bad API key
+pub fn is_unreachable(error: &(dyn std::error::Error + 'static)) -> bool {
+    if error.is::<TimedOut>() {
+        return true;
+    }
+    let Some(ApiError(inner)) = error.downcast_ref::<ApiError>() else {
+        return false;
+    };
+    if inner.is::<Unreachable>() {
+        return true;
+    }
+    if let Some(e) = inner.downcast_ref::<reqwest::Error>() {
+        return e.is_connect() || e.is_timeout();
+    }
+    inner.downcast_ref::<StatusError>().is_some_and(|e| e.status.is_server_error())
+}
+
+/// A function the model can call, with a JSON schema of its arguments
+pub struct ToolSpec {
+    pub name: String,
+    pub description: String,
+    pub parameters: serde_json::Value,
+}
+
+/// A model reply that may call tools, with any text it wrote alongside the calls
+pub struct ToolUse {
+    pub completion: Completion,
+    /// Empty if the model answered instead
+    pub calls: Vec<ToolCall>,
+}
+
+/// A chat-style LLM backend that turns a system prompt and a conversation into a reply.
+#[async_trait::async_trait(?Send)]
+pub trait Provider {
+    /// The model this provider will send requests to
+    fn model(&self) -> &str;
+
+    /// Send the conversation and return the model's reply
+    async fn complete(&self, system: &SystemPrompt, messages: &[Turn]) -> Result<Completion, Box<dyn std::error::Error>>;
+
+    /// Like `complete`, but calls `on_token` with each piece of the reply as it arrives.
+    /// Providers without a streaming implementation deliver the whole reply at once.
+    async fn stream(
+        &self,
+        system: &SystemPrompt,
+        messages: &[Turn],
+        on_token: &mut dyn for<'t> FnMut(&'t str),
+    ) -> Result<Completion, Box<dyn std::error::Error>> {
+        let completion = self.complete(system, messages).await?;
+        on_token(&completion.text);
+        Ok(completion)
+    }
+
+    /// Like `complete`, but the model must reply by calling `tool`, and the completion's text is
+    /// the arguments it passed as JSON. `None` if the provider can't make the model call a tool.
+    async fn complete_with_tool(&self, _system: &SystemPrompt, _messages: &[Turn], _tool: &ToolSpec) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        Ok(None)
+    }
+
+    /// Like `complete`, but the model may call any of `tools` through the API's own tool use
+    /// instead of answering. `None` if the provider doesn't support it, so the tools have to be
+    /// described in the prompt instead.
+    async fn complete_with_tools(&self, _system: &SystemPrompt, _messages: &[Turn], _tools: &[ToolSpec]) -> Result<Option<ToolUse>, Box<dyn std::error::Error>> {
+        Ok(None)
+    }
+}
+
+/// Names accepted by the `provider` config key
+pub const PROVIDERS: &[&str] = &["anthropic", "openai", "ollama", "gemini", "bedrock", "custom"];
+
+/// The full model ID a built-in alias such as `sonnet` stands for with `provider`, if it is one
+pub fn builtin_model_alias(provider: &str, alias: &str) -> Option<&'static str> {
+    let aliases = match provider {
+        "anthropic" => anthropic::MODEL_ALIASES,
+        "bedrock" => bedrock::MODEL_ALIASES,
+        _ => return None,
+    };
+    aliases.iter().find(|(name, _)| *name == alias).map(|(_, model)| *model)
+}
+
+/// The model a provider uses when none is configured
+pub fn default_model(provider: &str) -> Option<&'static str> {
+    match provider {
+        "anthropic" => Some(anthropic::DEFAULT_MODEL),
+        "openai" => Some(openai::DEFAULT_MODEL),
+        "ollama" => Some(ollama::DEFAULT_MODEL),
+        "gemini" => Some(gemini::DEFAULT_MODEL),
+        "bedrock" => Some(bedrock::DEFAULT_MODEL),
+        _ => None,
+    }
+}
+
+/// The environment variable a provider reads its API key from when `api_key_env` isn't set
+pub fn default_api_key_env(provider: &str) -> Option<&'static str> {
+    match provider {
+        "anthropic" => Some(anthropic::DEFAULT_API_KEY_ENV),
+        "openai" => Some(openai::DEFAULT_API_KEY_ENV),
+        "gemini" => Some(gemini::DEFAULT_API_KEY_ENV),
+        _ => None,
+    }
+}
+
+/// Sampling parameters from the config, sent only when set so the provider's defaults apply otherwise
+#[derive(Serialize, Clone, Copy, Default)]
+pub(super) struct Sampling {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) top_k: Option<u32>,
+}
+
+impl Sampling {
+    pub(super) fn new(config: &Config) -> Self {
+        Sampling {
+            temperature: config.temperature,
+            top_p: config.top_p,
+            top_k: config.top_k,
+        }
+    }
+}
+
+/// Where a provider gets its API key, in order: the environment variable, the
+/// `api_key_cmd` command, then the OS keychain (`llm-exec auth login`).
+pub(super) struct ApiKey {
+    /// Provider name the keychain entry is filed under
+    provider: &'static str,
+    env: Option<String>,
+    cmd: Option<String>,
+    /// Output of `cmd`, so password managers are only asked once per run
+    cmd_output: OnceCell<String>,
+}
+
+impl ApiKey {
+    pub(super) fn new(provider: &'static str, config: &Config, default_env: Option<&str>) -> Self {
+        ApiKey {
+            provider,
+            env: config.api_key_env.clone().or_else(|| default_env.map(str::to_string)),
+            cmd: config.api_key_cmd.clone(),
+            cmd_output: OnceCell::new(),
+        }
+    }
+
+    /// The key from the first source that has one, or `None` if none do
+    pub(super) fn get(&self) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        if let Some(key) = self.env.as_ref().and_then(|env| std::env::var(env).ok()) {
+            return Ok(Some(key));
+        }
+        if let Some(cmd) = &self.cmd {
+            if let Some(key) = self.cmd_output.get() {
+                return Ok(Some(key.clone()));
+            }
+            let key = run_key_command(cmd)?;
+            return Ok(Some(self.cmd_output.get_or_init(|| key).clone()));
+        }
+        Ok(crate::credentials::stored_key(self.provider))
+    }
+
+    /// Like `get`, but it's an error to have no key
+    pub(super) fn require(&self) -> Result<String, Box<dyn std::error::Error>> {
+        self.get()?.ok_or_else(|| {
+            let env = self.env.as_deref().unwrap_or("api_key_env");
+            format!("{} environment variable not set (or set api_key_cmd, or run `llm-exec auth login`)", env).into()
+        })
+    }
+}
+
+/// The HTTP client for a provider's requests, going through the `proxy` (or the proxy
+/// environment variables) and trusting `ca_bundle`. Sends requests, retrying rate limits (429)
+/// and server errors (5xx) with jittered exponential backoff, or after the delay the server
+/// gives in `Retry-After`.
+pub(super) struct Http {
+    client: reqwest::Client,
+    max_attempts: u32,
+}
+
+impl Http {
+    pub(super) fn new(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        // The proxy environment variables (and NO_PROXY) are used unless `proxy` is set
+        let mut builder = reqwest::Client::builder();
+        if let Some(url) = &config.proxy {
+            let proxy = reqwest::Proxy::all(url).map_err(|e| format!("Invalid proxy '{}': {}", url, e))?;
+            builder = builder.proxy(proxy.no_proxy(reqwest::NoProxy::from_env()));
+        }
+        if let Some(path) = &config.ca_bundle {
+            let pem = std::fs::read(path).map_err(|e| format!("Could not read ca_bundle {}: {}", path.display(), e))?;
+            let certificates = reqwest::Certificate::from_pem_bundle(&pem)
+                .map_err(|e| format!("Could not load certificates from {}: {}", path.display(), e))?;
+            if certificates.is_empty() {
+                return Err(format!("No certificates found in {}", path.display()).into());
+            }
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        Ok(Http {
+            client: builder.build()?,
+            max_attempts: config.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1),
+        })
+    }
+
+    pub(super) fn post(&self, url: impl reqwest::IntoUrl) -> reqwest::RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub(super) fn get(&self, url: impl reqwest::IntoUrl) -> reqwest::RequestBuilder {
+        self.client.get(url)
+    }
+
+    /// The response to the last attempt, which callers still need to check for an error status
+    pub(super) async fn send(&self, request: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+        let request = request.build()?;
+        trace_request(&request);
+
+        let mut attempt = 1;
+        loop {
+            // Requests with a streaming body can't be cloned, so can't be retried
+            let Some(retry_request) = request.try_clone().filter(|_| attempt < self.max_attempts) else {
+                return self.execute(request).await;
+            };
+
+            let response = self.execute(retry_request).await?;
+            let status = response.status();
+            if !(status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()) {
+                return Ok(response);
+            }
+
+            let delay = match retry_after(&response) {
+                Some(delay) if delay > MAX_RETRY_AFTER => return Ok(response),
+                Some(delay) => delay,
+                None => backoff(attempt),
+            };
+            let reason = if status == reqwest::StatusCode::TOO_MANY_REQUESTS { "Rate limited" } else { "Server error" };
+            eprintln!(
+                "\r{} ({}); retrying in {:.1}s (attempt {} of {})...",
+                reason,
+                status,
+                delay.as_secs_f64(),
+                attempt + 1,
+                self.max_attempts
+            );
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Send a single attempt, tracing its status and how long the response took to start
+    async fn execute(&self, request: reqwest::Request) -> reqwest::Result<reqwest::Response> {
+        let url = request.url().clone();
+        let start = Instant::now();
+        let result = self.client.execute(request).await;
+        let latency_ms = start.elapsed().as_millis() as u64;
+
+        match &result {
+            Ok(response) => tracing::info!(
+                %url,
+                status = response.status().as_u16(),
+                latency_ms,
+                response_bytes = response.content_length(),
+                "response"
+            ),
+            Err(e) => tracing::warn!(%url, latency_ms, error = %e, "request failed"),
+        }
+        result
+    }
+}
+
+/// Headers whose values are credentials, in addition to any whose name mentions a key, token, or secret
+const SECRET_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie"];
+
+fn is_secret_header(name: &str) -> bool {
+    SECRET_HEADERS.contains(&name) || ["key", "token", "secret", "auth"].iter().any(|word| name.contains(word))
+}
+
+/// Trace the request's size, and with `-vv` its headers, with credentials redacted
+fn trace_request(request: &reqwest::Request) {
+    let request_bytes = request.body().and_then(|body| body.as_bytes()).map(<[u8]>::len);
+    tracing::info!(method = %request.method(), url = %request.url(), request_bytes, "request");
+
+    for (name, value) in request.headers() {
+        let value = if is_secret_header(name.as_str()) {
+            "[redacted]"
+        } else {
+            value.to_str().unwrap_or("[binary]")
+        };
+        tracing::debug!(header = %name, value, "request header");
+    }
+}
+
+/// The delay a response asks for in its `Retry-After` header, in seconds or as an HTTP date
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Duration::try_from_secs_f64(seconds).ok();
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.to_utc() - chrono::Utc::now()).to_std().unwrap_or_default())
+}
+
+/// Exponential backoff for the given attempt, scaled by a random factor between 0.5 and 1
+/// so that clients rate limited together don't all retry at the same moment
+fn backoff(attempt: u32) -> Duration {
+    let delay = INITIAL_BACKOFF.saturating_mul(2u32.saturating_pow(attempt - 1)).min(MAX_BACKOFF);
+    // RandomState is seeded randomly for each instance, which is all the randomness this needs
+    let random = RandomState::new().build_hasher().finish();
+    delay.mul_f64(0.5 + (random % 1000) as f64 / 2000.0)
+}
+
+/// Run `api_key_cmd` through the shell and return its trimmed stdout
+fn run_key_command(cmd: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").arg("/C").arg(cmd).stderr(Stdio::inherit()).output()
+    } else {
+        Command::new("/bin/sh").arg("-c").arg(cmd).stderr(Stdio::inherit()).output()
+    }
+    .map_err(|e| format!("Could not run api_key_cmd: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("api_key_cmd failed ({})", output.status).into());
+    }
+
+    let key = String::from_utf8(output.stdout)?.trim().to_string();
+    if key.is_empty() {
+        return Err("api_key_cmd printed nothing".into());
+    }
+    Ok(key)
+}
+
+/// The models available from the provider selected by the `provider` config key, for `llm-exec models`
+pub async fn list_models(config: &Config) -> Result<Vec<ModelInfo>, Box<dyn std::error::Error>> {
+    let http = Http::new(config)?;
+    match config.provider.as_deref().unwrap_or(DEFAULT_PROVIDER) {
+        "anthropic" => Anthropic::new(config, http)?.models().await,
+        "openai" => OpenAi::new(config, http).models().await,
+        "ollama" => Ollama::new(config, http).models().await,
+        "gemini" => Gemini::new(config, http).models().await,
+        "custom" => OpenAi::custom(config, http)?.models().await,
+        "bedrock" => Err("Listing models isn't supported for bedrock; see the model access page of the Bedrock console".into()),
+        other => Err(format!("Unknown provider \"{}\" (expected one of: {})", other, PROVIDERS.join(", ")).into()),
+    }
+}
+
+/// Build the provider selected by the `provider` config key (default: anthropic),
+/// with requests limited to `timeout_secs` and any enabled tools and `mcp_servers` available to the model.
+pub fn from_config(config: &Config) -> Result<Box<dyn Provider>, Box<dyn std::error::Error>> {
+    let fallbacks = config.fallbacks.as_deref().unwrap_or_default();
+    let provider = if fallbacks.is_empty() {
+        with_deadline(config)?
+    } else {
+        let mut configs = vec![config.clone()];
+        configs.extend(fallbacks.iter().map(|entry| fallback_config(config, entry)));
+        let mut providers = Vec::new();
+        for config in &configs {
+            let provider = with_deadline(config)?;
+            let label = format!("{}:{}", config.provider.as_deref().unwrap_or(DEFAULT_PROVIDER), provider.model());
+            providers.push((label, provider));
+        }
+        Box::new(fallback::Fallback::new(providers))
+    };
+    let builtin = crate::tools::builtin(config);
+    let mcp_servers = config.mcp_servers.clone().unwrap_or_default();
+    if builtin.is_empty() && mcp_servers.is_empty() {
+        return Ok(provider);
+    }
+    Ok(Box::new(crate::tools::WithTools::new(provider, builtin, mcp_servers)))
+}
+
+/// The config for a `fallbacks` entry, `provider[:model]`
+fn fallback_config(config: &Config, entry: &str) -> Config {
+    match entry.split_once(':') {
+        Some((provider, model)) => switch_provider(config, provider.trim(), Some(model.trim())),
+        None => switch_provider(config, entry.trim(), None),
+    }
+}
+
+/// The config with `spec`, `[provider:]model`, as its model, from the given provider or else
+/// the configured one
+pub fn with_model(config: &Config, spec: &str) -> Config {
+    // Model names can have colons too, like Ollama's `llama3:8b`
+    match spec.split_once(':').filter(|(provider, _)| PROVIDERS.contains(provider)) {
+        Some((provider, model)) => switch_provider(config, provider, Some(model)),
+        None => switch_provider(config, config.provider.as_deref().unwrap_or(DEFAULT_PROVIDER), Some(spec)),
+    }
+}
+
+/// The same settings with another provider and model, without the connection settings if
+/// it's a different provider
+fn switch_provider(config: &Config, provider: &str, model: Option<&str>) -> Config {
+    let mut switched = config.clone();
+    if switched.provider.as_deref().unwrap_or(DEFAULT_PROVIDER) != provider {
+        switched.base_url = None;
+        switched.api_key_env = None;
+        switched.api_key_cmd = None;
+        switched.headers = None;
+        switched.aws_region = None;
+        switched.aws_profile = None;
+    }
+    switched.provider = Some(provider.to_string());
+    switched.model = model.map(|model| crate::config::resolve_model(&switched, model.to_string()));
+    switched
+}
+
+/// The provider `config` selects, with requests limited by `timeout_secs` and cancelled by Ctrl-C
+fn with_deadline(config: &Config) -> Result<Box<dyn Provider>, Box<dyn std::error::Error>> {
+    let http = Http::new(config)?;
+    let provider: Box<dyn Provider> = match config.provider.as_deref().unwrap_or(DEFAULT_PROVIDER) {
+        "anthropic" => Box::new(Anthropic::new(config, http)?),
+        "openai" => Box::new(OpenAi::new(config, http)),
+        "ollama" => Box::new(Ollama::new(config, http)),
+        "gemini" => Box::new(Gemini::new(config, http)),
+        "bedrock" => Box::new(Bedrock::new(config, http)?),
+        "custom" => Box::new(OpenAi::custom(config, http)?),
+        other => return Err(format!("Unknown provider \"{}\" (expected one of: {})", other, PROVIDERS.join(", ")).into()),
+    };
+    let timeout_secs = config.timeout_secs.unwrap_or(deadline::DEFAULT_TIMEOUT_SECS);
+    Ok(Box::new(deadline::Deadline::new(provider, timeout_secs)))
+}
+
+/// Feed each line of a streaming response body to `on_line` as it arrives.
+async fn for_each_line(
+    mut response: reqwest::Response,
+    mut on_line: impl FnMut(&str) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut buffer: Vec<u8> = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        buffer.extend_from_slice(&chunk);
+        while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=pos).collect();
+            on_line(String::from_utf8_lossy(&line).trim_end())?;
+        }
+    }
+
+    if !buffer.is_empty() {
+        on_line(String::from_utf8_lossy(&buffer).trim_end())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use serde::{Deserialize, Serialize};
+
+use super::{for_each_line, Completion, Http, ModelInfo, Provider, Sampling, StatusError, ToolSpec, Unreachable, Usage};
+use crate::conversation::{SystemPrompt, Turn};
+use crate::config::Config;
+use crate::DEFAULT_MAX_TOKENS;
+
+pub(super) const DEFAULT_MODEL: &str = "llama3.2";
+const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
+#[derive(Serialize)]
+struct Message {
+    role: String,
+    content: String,
+    /// Base64-encoded images
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Options {
+    num_predict: u32,
+    #[serde(flatten)]
+    sampling: Sampling,
+}
+
+#[derive(Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<Message>,
+    stream: bool,
+    options: Options,
+    /// JSON schema the reply must follow
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    message: ResponseMessage,
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+}
+
+/// Streamed chunks are partial responses; token counts arrive on the final one
+#[derive(Deserialize)]
+struct ChatChunk {
+    message: Option<ResponseMessage>,
+    error: Option<String>,
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+}
+
+fn usage(prompt_eval_count: Option<u64>, eval_count: Option<u64>) -> Option<Usage> {
+    if prompt_eval_count.is_none() && eval_count.is_none() {
+        return None;
+    }
+    Some(Usage {
+        input_tokens: prompt_eval_count.unwrap_or(0),
+        output_tokens: eval_count.unwrap_or(0),
+        ..Usage::default()
+    })
+}
+
+#[derive(Deserialize)]
+struct Tag {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct TagList {
+    models: Vec<Tag>,
+}
+
+/// Details of a model, whose `model_info` has its context length under `<architecture>.context_length`
+#[derive(Deserialize)]
+struct ModelDetails {
+    #[serde(default)]
+    model_info: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A local Ollama server's chat API (no API key required).
+pub struct Ollama {
+    model: String,
+    max_tokens: u32,
+    sampling: Sampling,
+    http: Http,
+    base_url: String,
+}
+
+impl Ollama {
+    pub(super) fn new(config: &Config, http: Http) -> Self {
+        Ollama {
+            model: config.model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+            max_tokens: config.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            sampling: Sampling::new(config),
+            http,
+            base_url: config.base_url.clone().unwrap_or_else(|| DEFAULT_BASE_URL.to_string()),
+        }
+    }
+
+    async fn send(&self, system: &SystemPrompt, messages: &[Turn], stream: bool, tool: Option<&ToolSpec>) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+        let mut api_messages = vec![Message {
+            role: "system".to_string(),
+            content: system.text(),
+            images: Vec::new(),
+        }];
+        api_messages.extend(messages.iter().map(|turn| Message {
+            role: turn.role.as_str().to_string(),
+            content: turn.content.clone(),
+            images: turn.images.iter().map(|image| image.data.clone()).collect(),
+        }));
+
+        let request = ChatRequest {
+            model: self.model.clone(),
+            messages: api_messages,
+            stream,
+            options: Options {
+                num_predict: self.max_tokens,
+                sampling: self.sampling,
+            },
+            format: tool.map(|tool| tool.parameters.clone()),
+        };
+
+        let url = format!("{}/api/chat", self.base_url.trim_end_matches('/'));
+        let response = self
+            .http
+            .send(self.http.post(&url).json(&request))
+            .await
+            .map_err(|e| Unreachable { server: format!("Ollama at {}", self.base_url), source: e })?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await?;
+            return Err(StatusError { status, body }.into());
+        }
+
+        Ok(response)
+    }
+
+    /// The models pulled to the server, with the context length of each
+    pub(super) async fn models(&self) -> Result<Vec<ModelInfo>, Box<dyn std::error::Error>> {
+        let url = format!("{}/api/tags", self.base_url.trim_end_matches('/'));
+        let response = self
+            .http
+            .send(self.http.get(&url))
+            .await
+            .map_err(|e| Unreachable { server: format!("Ollama at {}", self.base_url), source: e })?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await?;
+            return Err(StatusError { status, body }.into());
+        }
+        let tags: TagList = response.json().await?;
+
+        let show_url = format!("{}/api/show", self.base_url.trim_end_matches('/'));
+        let mut models = Vec::new();
+        for tag in tags.models {
+            let request = self.http.post(&show_url).json(&serde_json::json!({ "model": tag.name }));
+            let context_tokens = match self.http.send(request).await {
+                Ok(response) if response.status().is_success() => response.json::<ModelDetails>().await.ok().and_then(|details| {
+                    details.model_info.iter().find(|(key, _)| key.ends_with(".context_length")).and_then(|(_, value)| value.as_u64())
+                }),
+                _ => None,
+            };
+            models.push(ModelInfo { id: tag.name, context_tokens });
+        }
+        models.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(models)
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Provider for Ollama {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, system: &SystemPrompt, messages: &[Turn]) -> Result<Completion, Box<dyn std::error::Error>> {
+        let response = self.send(system, messages, false, None).await?;
+        let result: ChatResponse = response.json().await?;
+        Ok(Completion {
+            text: result.message.content,
+            usage: usage(result.prompt_eval_count, result.eval_count),
+            thinking: None,
+        })
+    }
+
+    /// Ollama's tool calls can't be forced, so this asks for a reply matching the tool's
+    /// parameters as structured output instead
+    async fn complete_with_tool(&self, system: &SystemPrompt, messages: &[Turn], tool: &ToolSpec) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        let response = self.send(system, messages, false, Some(tool)).await?;
+        let result: ChatResponse = response.json().await?;
+        Ok(Some(Completion {
+            text: result.message.content,
+            usage: usage(result.prompt_eval_count, result.eval_count),
+            thinking: None,
+        }))
+    }
+
+    async fn stream(
+        &self,
+        system: &SystemPrompt,
+        messages: &[Turn],
+        on_token: &mut dyn for<'t> FnMut(&'t str),
+    ) -> Result<Completion, Box<dyn std::error::Error>> {
+        let response = self.send(system, messages, true, None).await?;
+        let mut text = String::new();
+        let mut final_usage = None;
+
+        // Ollama streams one JSON object per line
+        for_each_line(response, |line| {
+            if line.is_empty() {
+                return Ok(());
+            }
+            let chunk: ChatChunk = serde_json::from_str(line)?;
+            if let Some(error) = chunk.error {
+                return Err(format!("Ollama error: {}", error).into());
+            }
+            if let Some(message) = chunk.message {
+                on_token(&message.content);
+                text.push_str(&message.content);
+            }
+            final_usage = usage(chunk.prompt_eval_count, chunk.eval_count).or(final_usage);
+            Ok(())
+        })
+        .await?;
+
+        Ok(Completion {
+            text,
+            usage: final_usage,
+            thinking: None,
+        })
+    }
+}
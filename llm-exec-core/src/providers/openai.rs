@@ -0,0 +1,391 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::{for_each_line, ApiKey, Completion, Http, ModelInfo, Provider, Sampling, StatusError, ToolSpec, Usage};
+use crate::conversation::{SystemPrompt, Turn};
+use crate::config::Config;
+use crate::DEFAULT_MAX_TOKENS;
+
+pub(super) const DEFAULT_MODEL: &str = "gpt-4o-mini";
+const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+pub(super) const DEFAULT_API_KEY_ENV: &str = "OPENAI_API_KEY";
+
+#[derive(Serialize)]
+struct Message {
+    role: String,
+    content: MessageContent,
+}
+
+/// Plain text, or text with images
+#[derive(Serialize)]
+#[serde(untagged)]
+enum MessageContent {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+}
+
+#[derive(Serialize)]
+struct ImageUrl {
+    url: String,
+}
+
+impl From<&Turn> for Message {
+    fn from(turn: &Turn) -> Self {
+        let content = if turn.images.is_empty() {
+            MessageContent::Text(turn.content.clone())
+        } else {
+            let mut parts = vec![ContentPart::Text { text: turn.content.clone() }];
+            parts.extend(turn.images.iter().map(|image| ContentPart::ImageUrl { image_url: ImageUrl { url: image.data_url() } }));
+            MessageContent::Parts(parts)
+        };
+        Message {
+            role: turn.role.as_str().to_string(),
+            content,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Function {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+}
+
+/// A function the model is offered
+#[derive(Serialize)]
+struct ToolDefinition {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: Function,
+}
+
+#[derive(Serialize)]
+struct FunctionName {
+    name: String,
+}
+
+/// Makes the model reply by calling the named function
+#[derive(Serialize)]
+struct ToolChoice {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: FunctionName,
+}
+
+#[derive(Serialize)]
+struct ChatRequest {
+    model: String,
+    max_tokens: u32,
+    messages: Vec<Message>,
+    #[serde(flatten)]
+    sampling: Sampling,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolDefinition>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<ToolChoice>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+/// Asks for a final streamed chunk carrying token usage
+#[derive(Serialize)]
+struct StreamOptions {
+    include_usage: bool,
+}
+
+#[derive(Deserialize)]
+struct ApiUsage {
+    #[serde(default)]
+    prompt_tokens: u64,
+    #[serde(default)]
+    completion_tokens: u64,
+}
+
+impl From<ApiUsage> for Usage {
+    fn from(usage: ApiUsage) -> Self {
+        Usage {
+            input_tokens: usage.prompt_tokens,
+            output_tokens: usage.completion_tokens,
+            ..Usage::default()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FunctionCall {
+    /// JSON text
+    arguments: String,
+}
+
+#[derive(Deserialize)]
+struct ToolCall {
+    function: FunctionCall,
+}
+
+#[derive(Deserialize)]
+struct ResponseMessage {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCall>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ResponseMessage,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+    usage: Option<ApiUsage>,
+}
+
+#[derive(Deserialize)]
+struct Delta {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChunkChoice {
+    delta: Delta,
+}
+
+#[derive(Deserialize)]
+struct ChatChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    usage: Option<ApiUsage>,
+}
+
+/// Compatible servers may say how much context a model takes, under one of several names
+#[derive(Deserialize)]
+struct ModelEntry {
+    id: String,
+    #[serde(alias = "max_model_len", alias = "context_window")]
+    context_length: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ModelList {
+    data: Vec<ModelEntry>,
+}
+
+/// OpenAI's chat completions API, or any server that speaks the same protocol
+/// (vLLM, LM Studio, OpenRouter, LiteLLM, ...).
+pub struct OpenAi {
+    model: String,
+    max_tokens: u32,
+    sampling: Sampling,
+    http: Http,
+    base_url: String,
+    /// Requests are unauthenticated if no key is configured
+    api_key: ApiKey,
+    headers: HashMap<String, String>,
+}
+
+impl OpenAi {
+    /// OpenAI itself (reads `OPENAI_API_KEY` unless `api_key_env` says otherwise).
+    pub(super) fn new(config: &Config, http: Http) -> Self {
+        OpenAi {
+            model: config.model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+            max_tokens: config.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            // OpenAI itself rejects `top_k`, though some compatible servers take it
+            sampling: Sampling {
+                top_k: None,
+                ..Sampling::new(config)
+            },
+            http,
+            base_url: config.base_url.clone().unwrap_or_else(|| DEFAULT_BASE_URL.to_string()),
+            api_key: ApiKey::new("openai", config, Some(DEFAULT_API_KEY_ENV)),
+            headers: config.headers.clone().unwrap_or_default(),
+        }
+    }
+
+    /// A user-configured OpenAI-compatible endpoint; `base_url` and `model` are required.
+    pub(super) fn custom(config: &Config, http: Http) -> Result<Self, Box<dyn std::error::Error>> {
+        let base_url = config.base_url.clone().ok_or("The custom provider requires `base_url` in config")?;
+        let model = config.model.clone().ok_or("The custom provider requires `model` in config")?;
+
+        Ok(OpenAi {
+            model,
+            max_tokens: config.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            sampling: Sampling::new(config),
+            http,
+            base_url,
+            api_key: ApiKey::new("custom", config, None),
+            headers: config.headers.clone().unwrap_or_default(),
+        })
+    }
+
+    async fn send(&self, system: &SystemPrompt, messages: &[Turn], stream: bool, tool: Option<&ToolSpec>) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+        let api_key = self.api_key.get()?;
+
+        // OpenAI takes the system prompt as the first message in the conversation
+        let mut api_messages = vec![Message {
+            role: "system".to_string(),
+            content: MessageContent::Text(system.text()),
+        }];
+        api_messages.extend(messages.iter().map(Message::from));
+
+        let tools = tool.map(|tool| ToolDefinition {
+            kind: "function",
+            function: Function {
+                name: tool.name.clone(),
+                description: tool.description.clone(),
+                parameters: tool.parameters.clone(),
+            },
+        });
+        let tool_choice = tool.map(|tool| ToolChoice {
+            kind: "function",
+            function: FunctionName { name: tool.name.clone() },
+        });
+        let request = ChatRequest {
+            model: self.model.clone(),
+            max_tokens: self.max_tokens,
+            messages: api_messages,
+            sampling: self.sampling,
+            tools: tools.into_iter().collect(),
+            tool_choice,
+            stream,
+            stream_options: stream.then_some(StreamOptions { include_usage: true }),
+        };
+
+        let url = format!("{}/chat/completions", self.base_url.trim_end_matches('/'));
+        let mut builder = self.http.post(&url).json(&request);
+        if let Some(api_key) = api_key {
+            builder = builder.bearer_auth(api_key);
+        }
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        let response = self.http.send(builder).await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await?;
+            return Err(StatusError { status, body }.into());
+        }
+
+        Ok(response)
+    }
+
+    /// The models the server offers, from its `/models` endpoint
+    pub(super) async fn models(&self) -> Result<Vec<ModelInfo>, Box<dyn std::error::Error>> {
+        let url = format!("{}/models", self.base_url.trim_end_matches('/'));
+        let mut builder = self.http.get(&url);
+        if let Some(api_key) = self.api_key.get()? {
+            builder = builder.bearer_auth(api_key);
+        }
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        let response = self.http.send(builder).await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await?;
+            return Err(StatusError { status, body }.into());
+        }
+
+        let list: ModelList = response.json().await?;
+        let mut models: Vec<ModelInfo> = list
+            .data
+            .into_iter()
+            .map(|model| ModelInfo {
+                id: model.id,
+                context_tokens: model.context_length,
+            })
+            .collect();
+        models.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(models)
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Provider for OpenAi {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn complete(&self, system: &SystemPrompt, messages: &[Turn]) -> Result<Completion, Box<dyn std::error::Error>> {
+        let response = self.send(system, messages, false, None).await?;
+        let result: ChatResponse = response.json().await?;
+
+        let text = result
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .ok_or("No response from model")?;
+
+        Ok(Completion {
+            text,
+            usage: result.usage.map(Usage::from),
+            thinking: None,
+        })
+    }
+
+    async fn complete_with_tool(&self, system: &SystemPrompt, messages: &[Turn], tool: &ToolSpec) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        let response = self.send(system, messages, false, Some(tool)).await?;
+        let result: ChatResponse = response.json().await?;
+
+        let arguments = result
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.tool_calls.into_iter().next())
+            .map(|call| call.function.arguments)
+            .ok_or("The model didn't call the tool")?;
+
+        Ok(Some(Completion {
+            text: arguments,
+            usage: result.usage.map(Usage::from),
+            thinking: None,
+        }))
+    }
+
+    async fn stream(
+        &self,
+        system: &SystemPrompt,
+        messages: &[Turn],
+        on_token: &mut dyn for<'t> FnMut(&'t str),
+    ) -> Result<Completion, Box<dyn std::error::Error>> {
+        let response = self.send(system, messages, true, None).await?;
+        let mut text = String::new();
+        let mut usage = None;
+
+        for_each_line(response, |line| {
+            let Some(data) = line.strip_prefix("data: ") else {
+                return Ok(());
+            };
+            if data == "[DONE]" {
+                return Ok(());
+            }
+            let chunk: ChatChunk = serde_json::from_str(data)?;
+            if let Some(chunk_usage) = chunk.usage {
+                usage = Some(Usage::from(chunk_usage));
+            }
+            if let Some(token) = chunk.choices.into_iter().next().and_then(|c| c.delta.content) {
+                on_token(&token);
+                text.push_str(&token);
+            }
+            Ok(())
+        })
+        .await?;
+
+        if text.is_empty() {
+            return Err("No response from model".into());
+        }
+
+        Ok(Completion { text, usage, thinking: None })
+    }
+}
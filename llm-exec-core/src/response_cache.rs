@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config;
+use crate::conversation::Turn;
+
+pub const DEFAULT_TTL_SECS: u64 = 300;
+const RESPONSES_DIR: &str = "responses";
+
+/// A cached response and when it was received
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    created: u64,
+    text: String,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Responses to recent requests, so asking the same thing again within the TTL
+/// doesn't need another API call. Entries are keyed by the model, the request's
+/// context, and the conversation.
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+    /// Hash of everything in the key except the conversation
+    scope: Sha256,
+}
+
+impl ResponseCache {
+    /// `None` if the TTL is zero or there's no cache directory
+    pub fn new(ttl_secs: u64, model: &str, context: &str) -> Option<Self> {
+        if ttl_secs == 0 {
+            return None;
+        }
+
+        let mut scope = Sha256::new();
+        for part in [model, context] {
+            scope.update(part.len().to_le_bytes());
+            scope.update(part);
+        }
+
+        Some(ResponseCache {
+            dir: config::cache_dir()?.join(RESPONSES_DIR),
+            ttl: Duration::from_secs(ttl_secs),
+            scope,
+        })
+    }
+
+    fn path(&self, turns: &[Turn]) -> PathBuf {
+        let mut hasher = self.scope.clone();
+        for turn in turns {
+            hasher.update(turn.role.as_str());
+            hasher.update(turn.content.len().to_le_bytes());
+            hasher.update(&turn.content);
+            for image in &turn.images {
+                hasher.update(&image.data);
+            }
+        }
+        let key: String = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+        self.dir.join(format!("{}.json", key))
+    }
+
+    fn is_fresh(&self, entry: &Entry) -> bool {
+        now().saturating_sub(entry.created) < self.ttl.as_secs()
+    }
+
+    /// The response to the same conversation, if it was cached within the TTL
+    pub fn get(&self, turns: &[Turn]) -> Option<String> {
+        let content = std::fs::read_to_string(self.path(turns)).ok()?;
+        let entry: Entry = serde_json::from_str(&content).ok()?;
+        self.is_fresh(&entry).then_some(entry.text)
+    }
+
+    /// Cache the response to the conversation, and clear out expired entries
+    pub fn put(&self, turns: &[Turn], text: &str) {
+        if let Err(e) = self.write(turns, text) {
+            eprintln!("Warning: Could not cache the response: {}", e);
+        }
+        self.remove_expired();
+    }
+
+    fn write(&self, turns: &[Turn], text: &str) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(&self.dir)?;
+        let entry = Entry {
+            created: now(),
+            text: text.to_string(),
+        };
+        std::fs::write(self.path(turns), serde_json::to_string(&entry)?)?;
+        Ok(())
+    }
+
+    fn remove_expired(&self) {
+        let Ok(files) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        for path in files.flatten().map(|file| file.path()) {
+            let expired = std::fs::read_to_string(&path)
+                .ok()
+                .and_then(|content| serde_json::from_str::<Entry>(&content).ok())
+                .is_none_or(|entry| !self.is_fresh(&entry));
+            if expired {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
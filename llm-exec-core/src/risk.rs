@@ -0,0 +1,344 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, ConfirmConfig};
+
+/// How much damage a command could do if it isn't what the user wanted
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Risk {
+    Low,
+    Medium,
+    High,
+}
+
+impl Risk {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Risk::Low => "LOW",
+            Risk::Medium => "MEDIUM",
+            Risk::High => "HIGH",
+        }
+    }
+}
+
+/// The risk of a command and the reasons it was given that rating
+pub struct Assessment {
+    pub risk: Risk,
+    pub reasons: Vec<&'static str>,
+}
+
+/// Patterns for risky commands: (risk, regex, reason)
+const RULES: &[(Risk, &str, &str)] = &[
+    (Risk::High, r"\bdd\b[^|;&]*\bof=", "writes raw data with dd"),
+    (Risk::High, r"\bmkfs(\.\w+)?\b", "creates a filesystem, erasing what was on the device"),
+    (Risk::High, r"\b(wipefs|shred|fdisk|sfdisk|parted)\b", "modifies or destroys disk contents"),
+    (Risk::High, r">\s*/dev/(sd|hd|vd|xvd|nvme|mmcblk|disk|rdisk)", "overwrites a block device"),
+    (Risk::High, r":\(\)\s*\{\s*:\s*\|\s*:\s*&\s*\}", "is a fork bomb"),
+    (
+        Risk::High,
+        r"\b(chmod|chown|chgrp)\s+(-\S+\s+)*-[a-zA-Z]*R.*\s/\*?(\s|[;&|]|$)",
+        "changes ownership or permissions of the whole filesystem",
+    ),
+    (Risk::High, r"\bgit\b.*\bpush\b.*(\s--force(\s|$)|\s-f\b|\s\+\S)", "force-pushes, overwriting remote history"),
+    (
+        Risk::High,
+        r"\b(curl|wget)\b[^|]*\|\s*(sudo\s+)?(ba|z|da|k)?sh\b",
+        "runs a script downloaded from the internet",
+    ),
+    (Risk::High, r"(?i)\bformat-volume\b|\bclear-disk\b", "erases a disk"),
+    (Risk::Medium, r"\bsudo\b|\bdoas\b", "runs with root privileges"),
+    (Risk::Medium, r"\s-delete\b", "deletes files"),
+    (Risk::Medium, r"(?i)\bremove-item\b", "deletes files"),
+    (Risk::Medium, r"\b(chmod|chown|chgrp)\b\s+(-\S+\s+)*-[a-zA-Z]*R", "changes ownership or permissions recursively"),
+    (Risk::Medium, r"\bgit\b.*\bpush\b.*--force-with-lease", "force-pushes, overwriting remote history"),
+    (Risk::Medium, r"\bgit\b.*\breset\b.*--hard", "discards uncommitted changes"),
+    (Risk::Medium, r"\bgit\b.*\bclean\b.*\s-[a-zA-Z]*f", "deletes untracked files"),
+    (Risk::Medium, r"\bgit\b.*\bbranch\b.*\s-D\b", "deletes a branch even if it isn't merged"),
+    (Risk::Medium, r"\b(kill|pkill|killall)\b", "terminates processes"),
+    (Risk::Medium, r"\b(shutdown|reboot|halt|poweroff)\b", "shuts down or restarts the machine"),
+    (Risk::Medium, r"\btruncate\b", "truncates files"),
+];
+
+static PATTERNS: LazyLock<Vec<(Risk, Regex, &'static str)>> = LazyLock::new(|| {
+    RULES
+        .iter()
+        .map(|&(risk, pattern, reason)| (risk, Regex::new(pattern).expect("invalid risk pattern"), reason))
+        .collect()
+});
+
+/// Each `rm` invocation in the command, capturing the flags passed to it. It's matched as any
+/// word, not just a command's first, so it's caught behind wrappers like `env`, `nice`, `sudo`,
+/// `xargs`, or `find -exec`, inside `bash -c '...'`, and as a path like `/bin/rm` or `\rm`.
+static RM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(^|[\s;&|('"/\\])rm\b((\s+-\S*)*)"#).expect("invalid rm pattern")
+});
+
+/// `rm` is HIGH risk with both recursive and force flags (in any order or spelling), otherwise MEDIUM
+fn rm_risk(command: &str) -> Option<(Risk, &'static str)> {
+    let mut risk = None;
+    for captures in RM.captures_iter(command) {
+        let flags: Vec<&str> = captures[2].split_whitespace().collect();
+        let has = |short: &[char], long: &str| {
+            flags.iter().any(|flag| match flag.strip_prefix("--") {
+                Some(name) => name == long,
+                None => flag.trim_start_matches('-').contains(short),
+            })
+        };
+        if has(&['r', 'R'], "recursive") && has(&['f'], "force") {
+            return Some((Risk::High, "recursively force-deletes files"));
+        }
+        risk = Some((Risk::Medium, "deletes files"));
+    }
+    risk
+}
+
+/// Rate a command by matching it against the known risky patterns.
+pub fn assess(command: &str) -> Assessment {
+    let mut risk = Risk::Low;
+    let mut reasons = Vec::new();
+    let mut flag = |rule_risk: Risk, reason: &'static str| {
+        risk = risk.max(rule_risk);
+        if !reasons.contains(&reason) {
+            reasons.push(reason);
+        }
+    };
+
+    if let Some((rule_risk, reason)) = rm_risk(command) {
+        flag(rule_risk, reason);
+    }
+    for (rule_risk, pattern, reason) in PATTERNS.iter() {
+        if pattern.is_match(command) {
+            flag(*rule_risk, reason);
+        }
+    }
+
+    Assessment { risk, reasons }
+}
+
+/// How a command is confirmed before it runs, set for each risk level in the `confirm` config keys
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Policy {
+    /// Run without asking when `--yes` is given
+    Auto,
+    /// Always ask, even with `--yes`
+    Confirm,
+    /// Have the command typed out exactly (or "yes") before it runs, even with `--yes`
+    TypeToConfirm,
+}
+
+impl Policy {
+    fn parse(level: &str, value: Option<&str>, default: Policy) -> Result<Policy, Box<dyn std::error::Error>> {
+        match value {
+            None => Ok(default),
+            Some("auto") => Ok(Policy::Auto),
+            Some("confirm") => Ok(Policy::Confirm),
+            Some("type-to-confirm") => Ok(Policy::TypeToConfirm),
+            Some(other) => Err(format!("Invalid confirm.{} '{}' (expected auto, confirm, or type-to-confirm)", level, other).into()),
+        }
+    }
+}
+
+/// The confirmation policy of each risk level
+pub struct Policies {
+    low: Policy,
+    medium: Policy,
+    high: Policy,
+}
+
+impl Policies {
+    pub fn new(config: &ConfirmConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Policies {
+            low: Policy::parse("low", config.low.as_deref(), Policy::Auto)?,
+            medium: Policy::parse("medium", config.medium.as_deref(), Policy::Auto)?,
+            high: Policy::parse("high", config.high.as_deref(), Policy::TypeToConfirm)?,
+        })
+    }
+
+    pub fn for_risk(&self, risk: Risk) -> Policy {
+        match risk {
+            Risk::Low => self.low,
+            Risk::Medium => self.medium,
+            Risk::High => self.high,
+        }
+    }
+}
+
+/// Commands the user never wants run without `--force`, from the `blocklist` config key
+pub struct Blocklist {
+    patterns: Vec<Regex>,
+}
+
+impl Blocklist {
+    pub fn new(patterns: &[String]) -> Result<Self, Box<dyn std::error::Error>> {
+        let patterns = patterns.iter().map(|pattern| config::pattern("blocklist", pattern)).collect::<Result<_, _>>()?;
+        Ok(Blocklist { patterns })
+    }
+
+    /// The first pattern that matches the command, if any
+    pub fn matching(&self, command: &str) -> Option<&str> {
+        self.patterns.iter().find(|pattern| pattern.is_match(command)).map(Regex::as_str)
+    }
+}
+
+/// Redirections that don't write to a file: duplicating a descriptor like `2>&1`, or discarding
+/// output like `>/dev/null` and `&> /dev/null`
+static HARMLESS_REDIRECT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\d*[<>]&(\d+|-)|(\d*|&)>>?\s*/dev/null\b").expect("invalid redirect pattern")
+});
+
+/// The only commands that may be run when the `allowlist` config key is set;
+/// anything else can be printed or copied but not executed.
+pub struct Allowlist {
+    prefixes: Vec<Vec<String>>,
+}
+
+impl Allowlist {
+    /// Each entry is a command's first word(s), e.g. "git" or "kubectl get"
+    pub fn new(entries: &[String]) -> Self {
+        let prefixes = entries
+            .iter()
+            .map(|entry| entry.split_whitespace().map(str::to_string).collect::<Vec<_>>())
+            .filter(|words| !words.is_empty())
+            .collect();
+        Allowlist { prefixes }
+    }
+
+    /// Whether every command in a pipeline or list starts with an allowed prefix.
+    /// Command and process substitutions could run anything, and output redirections
+    /// (other than to another descriptor or /dev/null) could overwrite any file, so
+    /// they're never allowed.
+    pub fn permits(&self, command: &str) -> bool {
+        if ["$(", "`", "<(", ">("].iter().any(|s| command.contains(s)) {
+            return false;
+        }
+
+        let command = HARMLESS_REDIRECT.replace_all(command, " ");
+        if command.contains('>') {
+            return false;
+        }
+        command
+            .split(['\n', ';', '|', '&'])
+            .map(str::trim)
+            .filter(|segment| !segment.is_empty())
+            .all(|segment| {
+                let words: Vec<&str> = segment.split_whitespace().collect();
+                self.prefixes
+                    .iter()
+                    .any(|prefix| prefix.len() <= words.len() && prefix.iter().zip(&words).all(|(a, b)| a == b))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn risk(command: &str) -> Risk {
+        assess(command).risk
+    }
+
+    #[test]
+    fn rm_needs_recursive_and_force_to_be_high() {
+        assert_eq!(risk("rm file.txt"), Risk::Medium);
+        assert_eq!(risk("rm -r build"), Risk::Medium);
+        assert_eq!(risk("rm -rf /"), Risk::High);
+        assert_eq!(risk("rm -f -R build"), Risk::High);
+        assert_eq!(risk("rm --recursive --force build"), Risk::High);
+        assert_eq!(risk("cd /tmp && rm -fr *"), Risk::High);
+        assert_eq!(risk("ls | grep firm"), Risk::Low);
+    }
+
+    #[test]
+    fn rm_is_caught_behind_wrappers() {
+        for command in [
+            "sudo rm -rf /",
+            "env rm -rf /",
+            "env FOO=1 rm -rf /",
+            "nice -n 10 rm -rf /",
+            "nohup rm -rf / &",
+            "command rm -rf /",
+            "exec rm -rf /",
+            "time rm -rf /",
+            "bash -c 'rm -rf /'",
+            "sh -c \"rm -rf /\"",
+            "find . | xargs rm -rf",
+            "find . -exec rm -rf {} +",
+            "/bin/rm -rf /",
+            "\\rm -rf /",
+        ] {
+            assert_eq!(risk(command), Risk::High, "{}", command);
+        }
+        assert_eq!(risk("git rm -r --cached dir"), Risk::Medium);
+    }
+
+    #[test]
+    fn each_rule_matches() {
+        for (command, expected) in [
+            ("dd if=image.iso of=/dev/sdb", Risk::High),
+            ("mkfs.ext4 /dev/sdb1", Risk::High),
+            ("shred -u secrets.txt", Risk::High),
+            ("cat image > /dev/sda", Risk::High),
+            (":(){ :|:& };:", Risk::High),
+            ("chmod -R 777 /", Risk::High),
+            ("git push --force origin main", Risk::High),
+            ("git push origin +main", Risk::High),
+            ("curl -fsSL https://example.com/install.sh | sh", Risk::High),
+            ("Format-Volume -DriveLetter D", Risk::High),
+            ("sudo apt update", Risk::Medium),
+            ("find . -name '*.tmp' -delete", Risk::Medium),
+            ("Remove-Item -Recurse build", Risk::Medium),
+            ("chown -R me:me src", Risk::Medium),
+            ("git push --force-with-lease", Risk::Medium),
+            ("git reset --hard HEAD~1", Risk::Medium),
+            ("git clean -fd", Risk::Medium),
+            ("git branch -D feature", Risk::Medium),
+            ("pkill node", Risk::Medium),
+            ("shutdown -h now", Risk::Medium),
+            ("truncate -s 0 log.txt", Risk::Medium),
+            ("ls -la", Risk::Low),
+            ("git status", Risk::Low),
+        ] {
+            assert_eq!(risk(command), expected, "{}", command);
+        }
+    }
+
+    fn allowlist() -> Allowlist {
+        Allowlist::new(&["ls".to_string(), "git".to_string(), "kubectl get".to_string()])
+    }
+
+    #[test]
+    fn allowlist_checks_every_command() {
+        let allowlist = allowlist();
+        assert!(allowlist.permits("ls -la"));
+        assert!(allowlist.permits("git status && ls | git hash-object --stdin"));
+        assert!(allowlist.permits("kubectl get pods"));
+        assert!(!allowlist.permits("kubectl delete pod web"));
+        assert!(!allowlist.permits("ls; rm -rf ~"));
+        assert!(!allowlist.permits("ls $(rm -rf ~)"));
+        assert!(!allowlist.permits("ls `rm -rf ~`"));
+    }
+
+    #[test]
+    fn allowlist_rejects_output_redirections() {
+        let allowlist = allowlist();
+        assert!(!allowlist.permits("ls > /etc/passwd"));
+        assert!(!allowlist.permits("git status >> ~/.bashrc"));
+        assert!(!allowlist.permits("ls 2> errors.txt"));
+        assert!(!allowlist.permits("ls &> out.txt"));
+        assert!(!allowlist.permits("ls >| out.txt"));
+        assert!(!allowlist.permits("ls >(cat)"));
+    }
+
+    #[test]
+    fn allowlist_allows_descriptor_redirections() {
+        let allowlist = allowlist();
+        assert!(allowlist.permits("ls missing 2>&1 | git hash-object --stdin"));
+        assert!(allowlist.permits("ls >&2"));
+        assert!(allowlist.permits("ls 2>/dev/null"));
+        assert!(allowlist.permits("git fetch &> /dev/null && ls"));
+        assert!(allowlist.permits("git apply < fix.patch"));
+    }
+}
@@ -0,0 +1,80 @@
+use std::io::IsTerminal;
+use std::process::{Command, ExitStatus, Stdio};
+
+use crate::config::SandboxConfig;
+
+/// Container runtimes to look for, in order, when `sandbox.runtime` isn't set
+const RUNTIMES: &[&str] = &["docker", "podman"];
+const DEFAULT_IMAGE: &str = "ubuntu";
+/// Where the current directory is mounted inside the container
+const WORKDIR: &str = "/work";
+
+/// How `--sandbox` runs commands: a throwaway container with the current directory mounted
+pub struct Sandbox {
+    runtime: String,
+    image: String,
+    writable: bool,
+}
+
+impl Sandbox {
+    pub fn new(config: &SandboxConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let runtime = match &config.runtime {
+            Some(runtime) => runtime.clone(),
+            None => RUNTIMES
+                .iter()
+                .find(|runtime| is_installed(runtime))
+                .ok_or("--sandbox needs docker or podman, and neither was found")?
+                .to_string(),
+        };
+
+        Ok(Sandbox {
+            runtime,
+            image: config.image.clone().unwrap_or_else(|| DEFAULT_IMAGE.to_string()),
+            writable: config.writable.unwrap_or(false),
+        })
+    }
+
+    pub fn image(&self) -> &str {
+        &self.image
+    }
+
+    /// Whether the mounted directory can be changed from inside the container
+    pub fn writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Run the command with `sh` in a new container, removed when it exits
+    pub fn run(&self, command: &str, stdin: Stdio) -> Result<ExitStatus, Box<dyn std::error::Error>> {
+        let cwd = std::env::current_dir()?;
+        let mode = if self.writable { "rw" } else { "ro" };
+
+        let mut container = Command::new(&self.runtime);
+        container.args(["run", "--rm", "-i"]);
+        if std::io::stdout().is_terminal() {
+            container.arg("-t");
+        }
+        container
+            .arg("-v")
+            .arg(format!("{}:{}:{}", cwd.display(), WORKDIR, mode))
+            .args(["-w", WORKDIR])
+            .arg(&self.image)
+            .args(["sh", "-c", command]);
+
+        let status = container
+            .stdin(stdin)
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .status()
+            .map_err(|e| format!("Could not run {}: {}", self.runtime, e))?;
+        Ok(status)
+    }
+}
+
+fn is_installed(program: &str) -> bool {
+    Command::new(program)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::config;
+use crate::conversation::Turn;
+
+const SESSIONS_DIR: &str = "sessions";
+/// Saved sessions kept, most recent first; older ones are deleted
+const MAX_SESSIONS: usize = 50;
+
+/// A conversation saved so it can be continued later with `--resume`
+#[derive(Serialize, Deserialize)]
+pub struct SavedSession {
+    pub id: String,
+    pub updated: String,
+    pub cwd: String,
+    /// The prompts, feedback, and suggestions so far
+    pub turns: Vec<Turn>,
+    /// What happened to the last suggestion (the command run, its exit status, and its error
+    /// output), told to the model along with the next prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_outcome: Option<String>,
+}
+
+impl SavedSession {
+    /// A session with a new ID, from the current time and process ID
+    pub fn new() -> Self {
+        SavedSession {
+            id: format!("{}-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"), std::process::id()),
+            updated: String::new(),
+            cwd: String::new(),
+            turns: Vec::new(),
+            last_outcome: None,
+        }
+    }
+
+    /// Write the session to its file, warning (but carrying on) if it can't be written
+    pub fn save(&mut self) {
+        self.updated = chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false);
+        self.cwd = std::env::current_dir().map(|cwd| cwd.display().to_string()).unwrap_or_default();
+        if let Err(e) = self.try_save() {
+            eprintln!("Warning: Could not save the session: {}", e);
+        }
+    }
+
+    fn try_save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let dir = sessions_dir().ok_or("Could not determine the state directory")?;
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join(format!("{}.json", self.id)), serde_json::to_string(self)?)?;
+        prune(&dir);
+        Ok(())
+    }
+}
+
+impl Default for SavedSession {
+    fn default() -> Self {
+        SavedSession::new()
+    }
+}
+
+fn sessions_dir() -> Option<PathBuf> {
+    Some(config::state_dir()?.join(SESSIONS_DIR))
+}
+
+/// Session IDs, oldest first
+fn ids(dir: &std::path::Path) -> Vec<String> {
+    let mut ids: Vec<String> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_string_lossy().strip_suffix(".json").map(str::to_string))
+        .collect();
+    // IDs are timestamps, so they sort by age
+    ids.sort();
+    ids
+}
+
+/// Delete all but the `MAX_SESSIONS` most recent sessions
+fn prune(dir: &std::path::Path) {
+    let ids = ids(dir);
+    for id in &ids[..ids.len().saturating_sub(MAX_SESSIONS)] {
+        let _ = std::fs::remove_file(dir.join(format!("{}.json", id)));
+    }
+}
+
+/// Load the session with this ID, or the most recent one
+pub fn load(id: Option<&str>) -> Result<SavedSession, Box<dyn std::error::Error>> {
+    let dir = sessions_dir().ok_or("Could not determine the state directory")?;
+    let id = match id {
+        Some(id) => id.to_string(),
+        None => ids(&dir).pop().ok_or("There are no saved sessions to resume")?,
+    };
+
+    let path = dir.join(format!("{}.json", id));
+    let text = std::fs::read_to_string(&path).map_err(|e| format!("Could not read session '{}': {}", id, e))?;
+    Ok(serde_json::from_str(&text).map_err(|e| format!("Could not parse {}: {}", path.display(), e))?)
+}
@@ -0,0 +1,91 @@
+/// Environment variable naming the file the shell hooks write to. After every command,
+/// the hooks record its exit status and command line there, one per line.
+pub const LAST_COMMAND_FILE_ENV: &str = "LLM_EXEC_LAST_COMMAND_FILE";
+
+const BASH: &str = r#"# llm-exec shell integration for bash
+export LLM_EXEC_LAST_COMMAND_FILE="${TMPDIR:-/tmp}/llm-exec-last-$$"
+__llm_exec_record() {
+  local status=$?
+  local cmd
+  cmd=$(HISTTIMEFORMAT= builtin history 1 | sed 's/^ *[0-9]* *//')
+  printf '%s\n%s\n' "$status" "$cmd" >| "$LLM_EXEC_LAST_COMMAND_FILE"
+  return $status
+}
+PROMPT_COMMAND="__llm_exec_record${PROMPT_COMMAND:+;$PROMPT_COMMAND}"
+"#;
+
+const ZSH: &str = r#"# llm-exec shell integration for zsh
+export LLM_EXEC_LAST_COMMAND_FILE="${TMPDIR:-/tmp}/llm-exec-last-$$"
+__llm_exec_preexec() { __llm_exec_cmd=$1 }
+__llm_exec_precmd() {
+  local exit_status=$?
+  [[ -n $__llm_exec_cmd ]] && printf '%s\n%s\n' $exit_status "$__llm_exec_cmd" >| "$LLM_EXEC_LAST_COMMAND_FILE"
+  __llm_exec_cmd=
+}
+autoload -Uz add-zsh-hook
+add-zsh-hook preexec __llm_exec_preexec
+add-zsh-hook precmd __llm_exec_precmd
+
+# Ctrl-X Ctrl-L replaces the command line with the command it describes
+llm-exec-widget() {
+  [[ -z $BUFFER ]] && return
+  local prompt=$BUFFER suggestion
+  zle -R "llm-exec: thinking..."
+  suggestion=$(command llm-exec --print -- "$prompt" 2>/dev/null)
+  if [[ $? -eq 0 && -n $suggestion ]]; then
+    BUFFER=$suggestion
+    CURSOR=$#BUFFER
+  else
+    zle -M "llm-exec: no suggestion"
+  fi
+}
+zle -N llm-exec-widget
+bindkey '^X^L' llm-exec-widget
+
+# `llm <prompt>` puts the suggestion on the next command line for review
+llm() { print -z -- "$(command llm-exec --print -- "$*")" }
+"#;
+
+const FISH: &str = r#"# llm-exec shell integration for fish
+set -q TMPDIR; and set -l __llm_exec_tmp $TMPDIR; or set -l __llm_exec_tmp /tmp
+set -gx LLM_EXEC_LAST_COMMAND_FILE (string trim -r -c / -- $__llm_exec_tmp)/llm-exec-last-$fish_pid
+function __llm_exec_postexec --on-event fish_postexec
+    printf '%s\n%s\n' $status $argv[1] > $LLM_EXEC_LAST_COMMAND_FILE
+end
+
+# Ctrl-X Ctrl-L replaces the command line with the command it describes
+function llm-exec-widget
+    set -l prompt (commandline)
+    test -n "$prompt"; or return
+    set -l suggestion (command llm-exec --print -- $prompt 2>/dev/null)
+    and commandline -r -- (string join \n -- $suggestion)
+    commandline -f repaint
+end
+bind \cx\cl llm-exec-widget
+bind -M insert \cx\cl llm-exec-widget 2>/dev/null
+"#;
+
+/// Shells that `--shell-init` supports
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+/// The hooks and key bindings to `eval` in the given shell's rc file
+pub fn script(shell: &str) -> Option<&'static str> {
+    match shell {
+        "bash" => Some(BASH),
+        "zsh" => Some(ZSH),
+        "fish" => Some(FISH),
+        _ => None,
+    }
+}
+
+/// The previous command's exit status and command line, as recorded by the hooks
+pub fn last_command() -> Option<(i32, String)> {
+    let path = std::env::var_os(LAST_COMMAND_FILE_ENV)?;
+    let content = std::fs::read_to_string(path).ok()?;
+    let (status, command) = content.split_once('\n')?;
+    let command = command.trim();
+    if command.is_empty() {
+        return None;
+    }
+    Some((status.trim().parse().ok()?, command.to_string()))
+}
@@ -0,0 +1,309 @@
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::Config;
+use crate::context::{self, ContextProvider, Section};
+use crate::conversation::{Conversation, Role, SystemPrompt, Turn};
+use crate::providers::{self, Provider, ToolSpec, Usage};
+use crate::redact::Redactor;
+use crate::risk::{self, Assessment, Risk};
+use crate::{history, prompt};
+
+/// History longer than this many estimated tokens is summarized when `history_summary_model` is set
+pub const HISTORY_SUMMARY_MIN_TOKENS: usize = 2000;
+
+/// Everything besides the prompt that a suggestion is generated from
+pub struct SuggestContext {
+    pub config: Config,
+    /// Shell to generate commands for
+    pub shell: String,
+    /// Recent shell history, one command per line
+    pub history: String,
+    /// Contents of the context files found in the current directory and its parents
+    pub context_files: String,
+    /// Extra context, such as git details or input piped in by the user
+    pub sections: Vec<Section>,
+    /// What the context was redacted with, unless `redact` is off
+    pub redactor: Option<Redactor>,
+}
+
+impl SuggestContext {
+    /// Collect context for `prompt` the way the `llm-exec` command does, as enabled by `config`,
+    /// with secrets redacted. Fails if a `redact_patterns` pattern or the `history_backend` is invalid.
+    pub fn gather(config: Config, prompt: &str, piped_input: Option<Section>) -> Result<Self, Box<dyn std::error::Error>> {
+        let history = if config.context.history.unwrap_or(true) {
+            let backend = history::from_config(&config)?;
+            let lines = config.history_lines.unwrap_or(history::DEFAULT_HISTORY_LINES);
+            let here = config.directory_history.unwrap_or(false);
+            let history = context::History { backend: backend.as_ref(), lines, here }.collect().map(|section| section.body).unwrap_or_default();
+            let history = history::compact(&history, config.compress_history.unwrap_or(true));
+            match config.relevant_history_lines.unwrap_or(0) {
+                0 => history,
+                count => history::most_relevant(&history, prompt, count, history::RECENT_LINES),
+            }
+        } else {
+            String::new()
+        };
+
+        let context_filenames: Vec<String> = config
+            .context_files
+            .clone()
+            .unwrap_or_else(|| prompt::DEFAULT_CONTEXT_FILES.iter().map(|s| s.to_string()).collect());
+
+        let mut context = SuggestContext {
+            shell: prompt::user_shell(&config),
+            history,
+            context_files: prompt::load_context_files(&context_filenames),
+            sections: context::gather(&config, prompt, piped_input),
+            redactor: None,
+            config,
+        };
+        if context.config.redact.unwrap_or(true) {
+            let redactor = Redactor::new(context.config.redact_patterns.as_deref().unwrap_or_default())?;
+            context.history = redactor.redact(&context.history);
+            context.context_files = redactor.redact(&context.context_files);
+            for section in &mut context.sections {
+                section.body = redactor.redact(&section.body);
+            }
+            context.redactor = Some(redactor);
+        }
+        match context.config.max_context_tokens.unwrap_or(context::DEFAULT_MAX_CONTEXT_TOKENS) {
+            0 => {}
+            max_tokens => context.fit(max_tokens),
+        }
+        Ok(context)
+    }
+
+    /// With `history_summary_model` set, have that model summarize the history if it's longer
+    /// than `HISTORY_SUMMARY_MIN_TOKENS`, and send the summary instead
+    pub async fn summarize_history(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(model) = self.config.history_summary_model.as_deref() else {
+            return Ok(());
+        };
+        if context::estimate_tokens(&self.history) <= HISTORY_SUMMARY_MIN_TOKENS {
+            return Ok(());
+        }
+
+        let provider = crate::usage::Metered::new(providers::from_config(&providers::with_model(&self.config, model))?, false);
+        let mut conversation = Conversation::new();
+        conversation.push(Role::User, self.history.as_str());
+        let summary = provider.complete(&prompt::HISTORY_SUMMARY_SYSTEM_PROMPT.into(), conversation.turns()).await?;
+        let summary = summary.text.trim().to_string();
+        tracing::info!(history_bytes = self.history.len(), summary_bytes = summary.len(), model = provider.model(), "summarized shell history");
+        provider.report();
+        self.history = summary;
+        Ok(())
+    }
+
+    /// Estimated tokens of the history, context files, and sections
+    fn tokens(&self) -> usize {
+        let sections: usize = self.sections.iter().map(|section| context::estimate_tokens(&section.body)).sum();
+        context::estimate_tokens(&self.history) + context::estimate_tokens(&self.context_files) + sections
+    }
+
+    /// Trim the history, then the largest sections, dropping their oldest lines first, until the
+    /// context is estimated at no more than `max_tokens`. Context files are left alone.
+    fn fit(&mut self, max_tokens: usize) {
+        let over = self.tokens().saturating_sub(max_tokens);
+        if over > 0 && !self.history.is_empty() {
+            let keep = context::estimate_tokens(&self.history).saturating_sub(over);
+            let dropped_lines = context::trim_to_tokens(&mut self.history, keep);
+            tracing::info!(block = "history", dropped_lines, max_tokens, "trimmed context to fit max_context_tokens");
+        }
+
+        while self.tokens() > max_tokens {
+            let over = self.tokens() - max_tokens;
+            let Some(largest) = self.sections.iter_mut().filter(|section| !section.body.is_empty()).max_by_key(|section| section.body.len()) else {
+                break;
+            };
+            let keep = context::estimate_tokens(&largest.body).saturating_sub(over);
+            let dropped_lines = context::trim_to_tokens(&mut largest.body, keep);
+            tracing::info!(block = %largest.source, dropped_lines, max_tokens, "trimmed context to fit max_context_tokens");
+        }
+        self.sections.retain(|section| !section.body.is_empty());
+    }
+
+    /// The kinds of context sent with each request, e.g. "shell history (100 lines)" or "git"
+    pub fn included(&self) -> Vec<String> {
+        let mut included = Vec::new();
+        if !self.history.is_empty() {
+            included.push(format!("shell history ({} lines)", self.history.lines().count()));
+        }
+        if !self.context_files.is_empty() {
+            included.push("context files".to_string());
+        }
+        included.extend(self.sections.iter().map(|section| section.source.clone()));
+        included
+    }
+
+    /// The system prompt for this context. `program` is the name llm-exec was run as,
+    /// which the model is told not to suggest.
+    pub fn system_prompt(&self, program: &str) -> SystemPrompt {
+        prompt::build_system_prompt(&self.history, &self.context_files, &self.sections, &self.config, program, &self.shell)
+    }
+
+    /// The system prompt for answering a question about this context in prose, as `llm-exec ask` does
+    pub fn ask_system_prompt(&self) -> SystemPrompt {
+        prompt::build_ask_system_prompt(&self.history, &self.context_files, &self.sections, &self.config, &self.shell)
+    }
+}
+
+/// A command suggested by the model
+pub struct Suggestion {
+    pub command: String,
+    /// How to reverse the command, if it's destructive
+    pub undo: Option<String>,
+    pub assessment: Assessment,
+    /// What the command does, if the model said (with `structured_output`)
+    pub explanation: Option<String>,
+    /// Whether the command has placeholders to fill in before running it
+    pub needs_placeholder: bool,
+    /// Tokens used, if the provider reported them
+    pub usage: Option<Usage>,
+}
+
+/// What the model passes to the `suggest_command` tool with `structured_output`
+#[derive(Deserialize)]
+pub struct StructuredSuggestion {
+    /// Empty if the model can't help
+    pub command: String,
+    /// What the command does, or why the model can't help
+    #[serde(default)]
+    pub explanation: String,
+    /// The model's own rating of the command
+    pub risk: Option<Risk>,
+    /// Whether the command has placeholders, like `<file>`, to fill in before running it
+    #[serde(default)]
+    pub needs_placeholder: bool,
+    /// How to reverse the command, if it's destructive
+    #[serde(default)]
+    pub undo: String,
+    /// Tokens used, if the provider reported them
+    #[serde(skip)]
+    pub usage: Option<Usage>,
+}
+
+impl StructuredSuggestion {
+    /// The suggestion the way the model writes it without the tool: the command (or the
+    /// error sigil, if it can't help), then any undo line
+    pub fn text(&self) -> String {
+        let command = self.command.trim();
+        if command.is_empty() {
+            return format!("{}{}\"", prompt::ERROR_SIGIL, self.explanation.trim().replace('"', "'"));
+        }
+        match self.undo.trim() {
+            "" => command.to_string(),
+            undo => format!("{}\n{} {}", command, prompt::UNDO_MARKER, undo),
+        }
+    }
+
+    /// `risk::assess` of the command, raised to the model's rating if that's higher
+    pub fn assessment(&self) -> Assessment {
+        let mut assessment = risk::assess(self.command.trim());
+        if let Some(risk) = self.risk.filter(|&risk| risk > assessment.risk) {
+            assessment.risk = risk;
+            assessment.reasons.push("is rated this risky by the model");
+        }
+        assessment
+    }
+}
+
+/// The tool the model replies through with `structured_output`
+fn suggest_tool() -> ToolSpec {
+    ToolSpec {
+        name: "suggest_command".to_string(),
+        description: "Suggest a shell command that does what the user asked".to_string(),
+        parameters: json!({
+            "type": "object",
+            "properties": {
+                "command": {
+                    "type": "string",
+                    "description": "The command to run, or an empty string if you cannot help"
+                },
+                "explanation": {
+                    "type": "string",
+                    "description": "One sentence on what the command does, or why you cannot help"
+                },
+                "risk": {
+                    "type": "string",
+                    "enum": ["low", "medium", "high"],
+                    "description": "How much damage the command could do if it isn't what the user wanted"
+                },
+                "needs_placeholder": {
+                    "type": "boolean",
+                    "description": "Whether the command has placeholders, like <file>, that the user must fill in before running it"
+                },
+                "undo": {
+                    "type": "string",
+                    "description": "For destructive or hard to reverse commands, a command that reverses it or a brief note on how to recover; otherwise an empty string"
+                }
+            },
+            "required": ["command", "explanation", "risk", "needs_placeholder"]
+        }),
+    }
+}
+
+/// Ask for a suggestion through the `suggest_command` tool, for `structured_output`.
+/// `None` if the provider can't make the model call it, so the suggestion should be asked for as usual.
+pub async fn suggest_structured(provider: &dyn Provider, system: &SystemPrompt, messages: &[Turn]) -> Result<Option<StructuredSuggestion>, Box<dyn std::error::Error>> {
+    let mut system = system.clone();
+    system.push(prompt::STRUCTURED_INSTRUCTIONS, false);
+    let Some(completion) = provider.complete_with_tool(&system, messages, &suggest_tool()).await? else {
+        return Ok(None);
+    };
+    let mut suggestion: StructuredSuggestion = serde_json::from_str(&completion.text).map_err(|e| format!("The model's suggest_command call wasn't valid: {}", e))?;
+    suggestion.usage = completion.usage;
+    Ok(Some(suggestion))
+}
+
+/// The model's explanation of why it couldn't suggest a command
+#[derive(Debug)]
+pub struct Refused(pub String);
+
+impl std::fmt::Display for Refused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Refused {}
+
+/// Ask the model configured in `context` for a command that does what `prompt` describes.
+/// Fails with [`Refused`] if the model says it can't help.
+pub async fn suggest(prompt: &str, context: &SuggestContext) -> Result<Suggestion, Box<dyn std::error::Error>> {
+    let provider = providers::from_config(&context.config)?;
+    let mut conversation = Conversation::new();
+    conversation.push(Role::User, prompt);
+
+    let system = context.system_prompt("llm-exec");
+    if context.config.structured_output.unwrap_or(false) {
+        if let Some(structured) = suggest_structured(provider.as_ref(), &system, conversation.turns()).await? {
+            if structured.command.trim().is_empty() {
+                return Err(Refused(structured.explanation).into());
+            }
+            return Ok(Suggestion {
+                command: structured.command.trim().to_string(),
+                undo: Some(structured.undo.trim().to_string()).filter(|undo| !undo.is_empty()),
+                assessment: structured.assessment(),
+                explanation: Some(structured.explanation).filter(|explanation| !explanation.is_empty()),
+                needs_placeholder: structured.needs_placeholder,
+                usage: structured.usage,
+            });
+        }
+    }
+
+    let completion = provider.complete(&system, conversation.turns()).await?;
+    let (command, undo) = prompt::split_undo(&prompt::normalize_response(&completion.text));
+    if let Some(reason) = prompt::refusal(&command) {
+        return Err(Refused(reason.to_string()).into());
+    }
+
+    Ok(Suggestion {
+        assessment: risk::assess(&command),
+        command,
+        undo,
+        explanation: None,
+        needs_placeholder: false,
+        usage: completion.usage,
+    })
+}
@@ -0,0 +1,74 @@
+use std::io::{self, BufRead, IsTerminal};
+
+/// The user's terminal, for reading input when stdin is a pipe
+pub fn open() -> io::Result<std::fs::File> {
+    let path = if cfg!(windows) { "CONIN$" } else { "/dev/tty" };
+    std::fs::File::open(path)
+}
+
+/// Read a line typed by the user, from the terminal even if stdin was piped in.
+/// Returns `None` at end of input.
+pub fn read_line() -> Option<String> {
+    let mut input = String::new();
+    let read = if io::stdin().is_terminal() {
+        io::stdin().read_line(&mut input)
+    } else {
+        open().and_then(|tty| io::BufReader::new(tty).read_line(&mut input))
+    };
+
+    match read {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(input),
+    }
+}
+
+/// Read a single key press without waiting for Enter, from the terminal even if stdin was piped in.
+/// Enter is returned as '\n'. Where the terminal can't be put in raw mode, this reads a whole line
+/// and returns its first character. Returns `None` at end of input.
+pub fn read_key() -> Option<char> {
+    if let Some(key) = read_raw_key() {
+        return key;
+    }
+    let line = read_line()?;
+    Some(line.trim().chars().next().unwrap_or('\n'))
+}
+
+/// The key pressed with the terminal in raw mode, or `None` if it couldn't be put in raw mode
+#[cfg(unix)]
+fn read_raw_key() -> Option<Option<char>> {
+    use std::io::Read;
+    use std::os::fd::AsRawFd;
+
+    let tty = open().ok()?;
+    let fd = tty.as_raw_fd();
+    // SAFETY: termios is plain data, filled in by tcgetattr before it's read
+    let mut original: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+        return None;
+    }
+
+    // Keep ISIG so Ctrl-C still interrupts
+    let mut raw = original;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+    raw.c_cc[libc::VMIN] = 1;
+    raw.c_cc[libc::VTIME] = 0;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+        return None;
+    }
+    let mut byte = [0u8];
+    let read = (&tty).read(&mut byte);
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+
+    Some(match (read, byte[0]) {
+        // Ctrl-D
+        (Ok(1), 4) => None,
+        (Ok(1), b'\r' | b'\n') => Some('\n'),
+        (Ok(1), byte) => Some(byte as char),
+        _ => None,
+    })
+}
+
+#[cfg(not(unix))]
+fn read_raw_key() -> Option<Option<char>> {
+    None
+}
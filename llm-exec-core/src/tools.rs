@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::io::{IsTerminal, Read, Write};
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+
+use crate::config::{Config, McpServerConfig};
+use crate::context;
+use crate::conversation::{Role, SystemPrompt, ToolCall, ToolResult, Turn};
+use crate::providers::{Completion, Provider, ToolSpec, ToolUse, Usage};
+use crate::{color, mcp, terminal};
+
+/// Tool calls allowed per suggestion, so a confused model can't loop forever
+const MAX_TOOL_CALLS: usize = 8;
+/// Maximum size of a tool result passed back to the model
+const MAX_RESULT_BYTES: usize = 16 * 1024;
+/// Lines of a file `read_file_head` returns unless the model asks for a different number
+const DEFAULT_HEAD_LINES: usize = 50;
+/// Most lines `read_file_head` returns
+const MAX_HEAD_LINES: usize = 200;
+/// Most bytes `read_file_head` reads from the start of a file
+const MAX_HEAD_BYTES: u64 = 16 * 1024;
+/// The line the model starts its reply with to call a tool, for providers without native tool use
+const TOOL_MARKER: &str = "# tool:";
+
+const TOOL_INSTRUCTIONS: &str = r##"Before answering, you can call tools to look things up, such as the files or resources that exist on the user's systems. To call a tool, reply with only a line starting with "# tool: " followed by the tool's name and its arguments as a JSON object, e.g.:
+# tool: server.tool_name {"argument": "value"}
+The result will come back in the next message. Call one tool per reply, only when it helps, and reply with the command as usual once you know enough.
+
+Available tools:"##;
+
+/// Something the model can call to look things up before answering
+#[async_trait::async_trait(?Send)]
+pub(crate) trait Tool {
+    /// Name the model calls the tool by
+    fn name(&self) -> &str;
+
+    /// What the tool does, for the model
+    fn description(&self) -> &str;
+
+    /// JSON schema of the arguments the tool takes
+    fn parameters(&self) -> Value;
+
+    /// Run the tool, returning its output for the model
+    async fn call(&self, arguments: Value) -> Result<String, Box<dyn std::error::Error>>;
+}
+
+/// Lists a directory within the current one, so the model can use real filenames
+struct ListDir;
+
+#[async_trait::async_trait(?Send)]
+impl Tool for ListDir {
+    fn name(&self) -> &str {
+        "list_dir"
+    }
+
+    fn description(&self) -> &str {
+        "List the files in a directory within the current directory, with their sizes."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": {"type": "string", "description": "Directory, relative to the current directory (default: .)"}
+            }
+        })
+    }
+
+    async fn call(&self, arguments: Value) -> Result<String, Box<dyn std::error::Error>> {
+        let dir = within_cwd(arguments["path"].as_str().unwrap_or("."))?;
+        Ok(context::listing(&dir)?)
+    }
+}
+
+/// Reads the start of a file within the current directory, such as a Makefile or script,
+/// asking the user first unless `tools.confirm` is off
+struct ReadFileHead {
+    confirm: bool,
+}
+
+#[async_trait::async_trait(?Send)]
+impl Tool for ReadFileHead {
+    fn name(&self) -> &str {
+        "read_file_head"
+    }
+
+    fn description(&self) -> &str {
+        "Read the first lines of a file within the current directory, e.g. to see a Makefile's targets or what a script does."
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "path": {"type": "string", "description": "File, relative to the current directory"},
+                "lines": {"type": "integer", "description": "How many lines to read (default: 50, at most 200)"}
+            },
+            "required": ["path"]
+        })
+    }
+
+    async fn call(&self, arguments: Value) -> Result<String, Box<dyn std::error::Error>> {
+        let path = arguments["path"].as_str().ok_or("read_file_head needs a \"path\" argument")?;
+        let lines = arguments["lines"].as_u64().map_or(DEFAULT_HEAD_LINES, |n| n as usize).min(MAX_HEAD_LINES);
+        let file = within_cwd(path)?;
+        if !file.is_file() {
+            return Err(format!("{} is not a file", path).into());
+        }
+
+        if self.confirm {
+            eprint!("Let the model read the first {} lines of {}? [Y/n]: ", lines, path);
+            std::io::stderr().flush()?;
+            // Without a terminal to answer on, there's no one to allow it
+            let Some(answer) = terminal::read_line() else {
+                eprintln!();
+                return Err("The user couldn't be asked to allow reading this file".into());
+            };
+            let answer = answer.trim().to_lowercase();
+            if !(answer.is_empty() || answer == "y" || answer == "yes") {
+                return Err("The user didn't allow reading this file".into());
+            }
+        }
+
+        let mut head = Vec::new();
+        std::fs::File::open(&file)?.take(MAX_HEAD_BYTES).read_to_end(&mut head)?;
+        if head.contains(&0) {
+            return Err(format!("{} is a binary file", path).into());
+        }
+        Ok(String::from_utf8_lossy(&head).lines().take(lines).collect::<Vec<_>>().join("\n"))
+    }
+}
+
+/// Resolve `path` against the current directory, refusing anything outside it (including through symlinks)
+fn within_cwd(path: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let cwd = std::env::current_dir()?.canonicalize()?;
+    let resolved = cwd.join(path).canonicalize().map_err(|e| format!("{}: {}", path, e))?;
+    if !resolved.starts_with(&cwd) {
+        return Err(format!("{} is outside the current directory", path).into());
+    }
+    Ok(resolved)
+}
+
+/// The built-in tools enabled by the `tools` config keys
+pub(crate) fn builtin(config: &Config) -> Vec<Box<dyn Tool>> {
+    let mut tools: Vec<Box<dyn Tool>> = Vec::new();
+    if config.tools.list_dir.unwrap_or(false) {
+        tools.push(Box::new(ListDir));
+    }
+    if config.tools.read_file_head.unwrap_or(false) {
+        tools.push(Box::new(ReadFileHead {
+            confirm: config.tools.confirm.unwrap_or(true),
+        }));
+    }
+    tools
+}
+
+/// The system prompt addition that describes `tools` for providers without native tool use
+fn instructions(tools: &[&dyn Tool]) -> String {
+    let mut instructions = TOOL_INSTRUCTIONS.to_string();
+    for tool in tools {
+        instructions.push_str(&format!("\n- {}: {}\n  Arguments (JSON schema): {}", tool.name(), tool.description(), tool.parameters()));
+    }
+    instructions
+}
+
+/// `name` as APIs with native tool use accept it, with only letters, digits, `_`, and `-`,
+/// so an MCP tool's `server.tool` is offered as `server__tool`
+fn native_name(name: &str) -> String {
+    name.replace('.', "__")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+/// The tool the model called by `name`, as it's described in the prompt or offered natively
+fn find<'a>(tools: &[&'a dyn Tool], name: &str) -> Option<&'a dyn Tool> {
+    tools.iter().copied().find(|tool| tool.name() == name || native_name(tool.name()) == name)
+}
+
+/// The tool and arguments if the reply is a tool call, e.g. `# tool: k8s.get_pods {"namespace": "web"}`
+fn tool_call(reply: &str) -> Option<(&str, Value)> {
+    let call = reply.trim().strip_prefix(TOOL_MARKER)?.trim();
+    let (name, arguments) = call.split_once(char::is_whitespace).unwrap_or((call, ""));
+    let arguments = serde_json::from_str(arguments.trim()).unwrap_or_else(|_| json!({}));
+    Some((name, arguments))
+}
+
+/// Clear "Thinking..." so messages about tool calls start on a clean line
+pub(crate) fn clear_progress() {
+    if std::io::stderr().is_terminal() {
+        eprint!("\r\x1b[K");
+    }
+}
+
+/// The first `MAX_RESULT_BYTES` of a tool result
+fn truncate(mut text: String) -> String {
+    if text.len() > MAX_RESULT_BYTES {
+        let mut end = MAX_RESULT_BYTES;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("\n[... truncated ...]");
+    }
+    text
+}
+
+/// Wraps a provider so the model can call the built-in tools and those of the `mcp_servers`
+/// before answering. Servers are started on the first request and stopped when this is dropped.
+pub(crate) struct WithTools {
+    inner: Box<dyn Provider>,
+    builtin: Vec<Box<dyn Tool>>,
+    mcp_servers: HashMap<String, McpServerConfig>,
+    mcp_tools: tokio::sync::OnceCell<Vec<Box<dyn Tool>>>,
+}
+
+impl WithTools {
+    pub(crate) fn new(inner: Box<dyn Provider>, builtin: Vec<Box<dyn Tool>>, mcp_servers: HashMap<String, McpServerConfig>) -> Self {
+        WithTools {
+            inner,
+            builtin,
+            mcp_servers,
+            mcp_tools: tokio::sync::OnceCell::new(),
+        }
+    }
+
+    /// Call the tool the model asked for, returning the result or error for the model
+    async fn call(&self, tools: &[&dyn Tool], call: &ToolCall) -> (String, ToolResult) {
+        let tool = find(tools, &call.name);
+        let name = tool.map_or(call.name.as_str(), |tool| tool.name());
+        clear_progress();
+        match call.arguments.as_object() {
+            Some(arguments) if !arguments.is_empty() => eprintln!("{}", color::epaint(color::DIM, format!("(calling {} {})", name, call.arguments))),
+            _ => eprintln!("{}", color::epaint(color::DIM, format!("(calling {})", name))),
+        }
+
+        let result = match tool {
+            Some(tool) => tool.call(call.arguments.clone()).await,
+            None => Err(format!("There is no tool named {}", name).into()),
+        };
+        let (content, is_error) = match result {
+            Ok(text) => (truncate(text), false),
+            Err(e) => (truncate(e.to_string()), true),
+        };
+        let text = if is_error { format!("{} failed: {}", name, content) } else { format!("Result of {}:\n{}", name, content) };
+        (text, ToolResult { id: call.id.clone(), content, is_error })
+    }
+
+    /// Ask the model for a reply that may be a `# tool:` line, for providers without native tool use.
+    /// When streaming, tokens are held back until it's clear the reply isn't a tool call.
+    async fn complete_text<'f>(
+        &self,
+        system: &SystemPrompt,
+        turns: &[Turn],
+        on_token: Option<&mut (dyn for<'t> FnMut(&'t str) + 'f)>,
+    ) -> Result<ToolUse, Box<dyn std::error::Error>> {
+        let completion = match on_token {
+            Some(on_token) => {
+                let mut held = String::new();
+                let mut passing = false;
+                let mut filter = |token: &str| {
+                    if passing {
+                        return on_token(token);
+                    }
+                    held.push_str(token);
+                    let start = held.trim_start();
+                    let undecided = if start.len() < TOOL_MARKER.len() { TOOL_MARKER.starts_with(start) } else { start.starts_with(TOOL_MARKER) };
+                    if !undecided {
+                        passing = true;
+                        on_token(&held);
+                    }
+                };
+                let completion = self.inner.stream(system, turns, &mut filter).await?;
+                if !passing && tool_call(&held).is_none() {
+                    on_token(&held);
+                }
+                completion
+            }
+            None => self.inner.complete(system, turns).await?,
+        };
+        let calls = tool_call(&completion.text)
+            .map(|(name, arguments)| ToolCall {
+                id: String::new(),
+                name: name.to_string(),
+                arguments,
+            })
+            .into_iter()
+            .collect();
+        Ok(ToolUse { completion, calls })
+    }
+
+    /// Ask the model, calling tools for it until it replies with something else. Tools are
+    /// offered through the provider's native tool use if it has it, and otherwise described in
+    /// the system prompt for the model to call with a `# tool:` line. With native tool use,
+    /// a streamed reply arrives all at once, as any text before a tool call isn't the answer.
+    async fn run(
+        &self,
+        system: &SystemPrompt,
+        messages: &[Turn],
+        mut on_token: Option<&mut dyn for<'t> FnMut(&'t str)>,
+    ) -> Result<Completion, Box<dyn std::error::Error>> {
+        let mcp_tools = self.mcp_tools.get_or_init(|| mcp::start(&self.mcp_servers)).await;
+        let tools: Vec<&dyn Tool> = self.builtin.iter().chain(mcp_tools).map(|tool| tool.as_ref()).collect();
+        if tools.is_empty() {
+            return match on_token {
+                Some(on_token) => self.inner.stream(system, messages, on_token).await,
+                None => self.inner.complete(system, messages).await,
+            };
+        }
+
+        let specs: Vec<ToolSpec> = tools
+            .iter()
+            .map(|tool| ToolSpec {
+                name: native_name(tool.name()),
+                description: tool.description().to_string(),
+                parameters: tool.parameters(),
+            })
+            .collect();
+        let mut system = system.clone();
+        let mut native = true;
+
+        let mut turns = messages.to_vec();
+        let mut usage: Option<Usage> = None;
+        for _ in 0..=MAX_TOOL_CALLS {
+            let reply = match native {
+                true => self.inner.complete_with_tools(&system, &turns, &specs).await?,
+                false => None,
+            };
+            let ToolUse { completion, calls } = match reply {
+                Some(reply) => {
+                    if let (Some(on_token), true) = (on_token.as_deref_mut(), reply.calls.is_empty()) {
+                        on_token(&reply.completion.text);
+                    }
+                    reply
+                }
+                None => {
+                    if native {
+                        native = false;
+                        system.push(instructions(&tools), false);
+                    }
+                    self.complete_text(&system, &turns, on_token.as_deref_mut()).await?
+                }
+            };
+
+            if let Some(more) = completion.usage {
+                *usage.get_or_insert_default() += more;
+            }
+            if calls.is_empty() {
+                return Ok(Completion { usage, ..completion });
+            }
+
+            let mut requests = Vec::new();
+            let mut texts = Vec::new();
+            let mut results = Vec::new();
+            for call in &calls {
+                let (text, result) = self.call(&tools, call).await;
+                let name = find(&tools, &call.name).map_or(call.name.as_str(), |tool| tool.name());
+                requests.push(format!("{} {} {}", TOOL_MARKER, name, call.arguments));
+                texts.push(text);
+                results.push(result);
+            }
+            turns.push(Turn {
+                role: Role::Assistant,
+                content: if native { requests.join("\n") } else { completion.text },
+                images: Vec::new(),
+                tool_calls: if native { calls } else { Vec::new() },
+                tool_results: Vec::new(),
+            });
+            turns.push(Turn {
+                role: Role::User,
+                content: texts.join("\n\n"),
+                images: Vec::new(),
+                tool_calls: Vec::new(),
+                tool_results: if native { results } else { Vec::new() },
+            });
+        }
+
+        Err(format!("The model called more than {} tools without suggesting a command", MAX_TOOL_CALLS).into())
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Provider for WithTools {
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    async fn complete(&self, system: &SystemPrompt, messages: &[Turn]) -> Result<Completion, Box<dyn std::error::Error>> {
+        self.run(system, messages, None).await
+    }
+
+    async fn stream(
+        &self,
+        system: &SystemPrompt,
+        messages: &[Turn],
+        on_token: &mut dyn for<'t> FnMut(&'t str),
+    ) -> Result<Completion, Box<dyn std::error::Error>> {
+        self.run(system, messages, Some(on_token)).await
+    }
+
+    /// The model replies through `tool` straight away, without calling the other tools first
+    async fn complete_with_tool(&self, system: &SystemPrompt, messages: &[Turn], tool: &ToolSpec) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        self.inner.complete_with_tool(system, messages, tool).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replies through any tool it's made to call, and otherwise with a `# tool:` line
+    struct Structured;
+
+    #[async_trait::async_trait(?Send)]
+    impl Provider for Structured {
+        fn model(&self) -> &str {
+            "structured"
+        }
+
+        async fn complete(&self, _system: &SystemPrompt, _messages: &[Turn]) -> Result<Completion, Box<dyn std::error::Error>> {
+            Ok(Completion {
+                text: "# tool: list_dir {}".to_string(),
+                ..Completion::default()
+            })
+        }
+
+        async fn complete_with_tool(&self, _system: &SystemPrompt, _messages: &[Turn], tool: &ToolSpec) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+            Ok(Some(Completion {
+                text: json!({"tool": tool.name, "command": "ls"}).to_string(),
+                ..Completion::default()
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn forwards_structured_completions() {
+        let provider = WithTools::new(Box::new(Structured), vec![Box::new(ListDir)], HashMap::new());
+        let tool = ToolSpec {
+            name: "suggest_command".to_string(),
+            description: String::new(),
+            parameters: json!({"type": "object"}),
+        };
+        let completion = provider.complete_with_tool(&SystemPrompt::default(), &[], &tool).await.unwrap().unwrap();
+        assert_eq!(completion.text, r#"{"tool":"suggest_command","command":"ls"}"#);
+    }
+}
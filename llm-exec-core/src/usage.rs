@@ -0,0 +1,262 @@
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::{color, config};
+use crate::conversation::{SystemPrompt, Turn};
+use crate::providers::{Completion, Provider, ToolSpec, Usage};
+
+const USAGE_FILE: &str = "usage.json";
+
+/// USD per million (input, output) tokens, matched against model names in order,
+/// so more specific names come first. Bedrock model IDs contain the same names.
+const PRICES: &[(&str, f64, f64)] = &[
+    ("claude-haiku-4-5", 1.0, 5.0),
+    ("claude-3-5-haiku", 0.8, 4.0),
+    ("claude-sonnet-4", 3.0, 15.0),
+    ("claude-3-7-sonnet", 3.0, 15.0),
+    ("claude-opus-4-5", 5.0, 25.0),
+    ("claude-opus-4", 15.0, 75.0),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4.1-nano", 0.1, 0.4),
+    ("gpt-4.1-mini", 0.4, 1.6),
+    ("gpt-4.1", 2.0, 8.0),
+    ("gpt-5-nano", 0.05, 0.4),
+    ("gpt-5-mini", 0.25, 2.0),
+    ("gpt-5", 1.25, 10.0),
+    ("gemini-2.5-flash-lite", 0.1, 0.4),
+    ("gemini-2.5-flash", 0.3, 2.5),
+    ("gemini-2.5-pro", 1.25, 10.0),
+];
+
+/// Prompt cache writes and reads are priced relative to regular input tokens
+const CACHE_WRITE_MULTIPLIER: f64 = 1.25;
+const CACHE_READ_MULTIPLIER: f64 = 0.1;
+
+/// Estimated cost in USD of a single request, or `None` for models without a known price
+pub fn request_cost(model: &str, usage: Usage) -> Option<f64> {
+    cost(model, &usage.into())
+}
+
+/// Estimated cost in USD, or `None` for models without a known price (including local ones)
+fn cost(model: &str, totals: &Totals) -> Option<f64> {
+    let (_, input_price, output_price) = PRICES.iter().find(|(name, _, _)| model.contains(name))?;
+    let input = totals.input_tokens as f64
+        + totals.cache_write_tokens as f64 * CACHE_WRITE_MULTIPLIER
+        + totals.cache_read_tokens as f64 * CACHE_READ_MULTIPLIER;
+    Some((input * input_price + totals.output_tokens as f64 * output_price) / 1_000_000.0)
+}
+
+fn format_cost(cost: Option<f64>) -> String {
+    cost.map_or_else(|| "-".to_string(), |cost| format!("${:.4}", cost))
+}
+
+/// Requests and tokens for one model on one day
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+struct Totals {
+    requests: u64,
+    input_tokens: u64,
+    output_tokens: u64,
+    #[serde(default)]
+    cache_write_tokens: u64,
+    #[serde(default)]
+    cache_read_tokens: u64,
+}
+
+impl Totals {
+    fn add(&mut self, other: &Totals) {
+        self.requests += other.requests;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_write_tokens += other.cache_write_tokens;
+        self.cache_read_tokens += other.cache_read_tokens;
+    }
+}
+
+impl From<Usage> for Totals {
+    fn from(usage: Usage) -> Self {
+        Totals {
+            requests: 1,
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            cache_write_tokens: usage.cache_write_tokens,
+            cache_read_tokens: usage.cache_read_tokens,
+        }
+    }
+}
+
+/// Running totals, by day (YYYY-MM-DD) then model
+type Tally = BTreeMap<String, BTreeMap<String, Totals>>;
+
+fn usage_file() -> Option<PathBuf> {
+    Some(config::state_dir()?.join(USAGE_FILE))
+}
+
+fn load() -> Tally {
+    usage_file()
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Add a request to today's totals for the model
+fn record(model: &str, usage: Usage) -> Result<(), Box<dyn std::error::Error>> {
+    let path = usage_file().ok_or("Could not determine the state directory")?;
+    let mut tally = load();
+
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+    tally.entry(today).or_default().entry(model.to_string()).or_default().add(&usage.into());
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Write to a temporary file first so an interrupted write can't lose the tally
+    let temp = path.with_extension("json.tmp");
+    std::fs::write(&temp, serde_json::to_string_pretty(&tally)?)?;
+    std::fs::rename(&temp, &path)?;
+    Ok(())
+}
+
+/// Wraps a provider to add each request's token usage to the running tally,
+/// keeping it to trace (and print with `--verbose`, after any thinking) once the reply has been shown.
+pub struct Metered {
+    inner: Box<dyn Provider>,
+    verbose: bool,
+    unreported: RefCell<Vec<(Usage, Option<String>)>>,
+}
+
+impl Metered {
+    pub fn new(inner: Box<dyn Provider>, verbose: bool) -> Self {
+        Metered {
+            inner,
+            verbose,
+            unreported: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn track(&self, completion: &Completion) {
+        let usage = completion.usage.unwrap_or_default();
+        if let Err(e) = record(self.inner.model(), usage) {
+            eprintln!("Warning: Could not record token usage: {}", e);
+        }
+        self.unreported.borrow_mut().push((usage, completion.thinking.clone()));
+    }
+
+    /// Trace the usage of requests made since the last report, and with `--verbose` print it
+    /// along with the model's thinking
+    pub fn report(&self) {
+        for (usage, thinking) in self.unreported.borrow_mut().drain(..) {
+            let model = self.inner.model();
+            tracing::info!(
+                model,
+                input_tokens = usage.input_tokens,
+                output_tokens = usage.output_tokens,
+                cache_write_tokens = usage.cache_write_tokens,
+                cache_read_tokens = usage.cache_read_tokens,
+                "usage"
+            );
+            if !self.verbose {
+                continue;
+            }
+
+            if let Some(thinking) = thinking {
+                eprintln!("{}", color::epaint(color::DIM, format!("Thinking:\n  {}", thinking.trim().replace('\n', "\n  "))));
+            }
+            let cached = if usage.cache_write_tokens + usage.cache_read_tokens > 0 {
+                format!(" ({} written to and {} read from the cache)", usage.cache_write_tokens, usage.cache_read_tokens)
+            } else {
+                String::new()
+            };
+            let cost = cost(model, &usage.into()).map(|cost| format!(", ~${:.4}", cost)).unwrap_or_default();
+            let tokens = usage.input_tokens + usage.cache_write_tokens + usage.cache_read_tokens;
+            eprintln!("{}", color::epaint(color::DIM, format!("{}: {} input{} + {} output tokens{}", model, tokens, cached, usage.output_tokens, cost)));
+        }
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Provider for Metered {
+    fn model(&self) -> &str {
+        self.inner.model()
+    }
+
+    async fn complete(&self, system: &SystemPrompt, messages: &[Turn]) -> Result<Completion, Box<dyn std::error::Error>> {
+        let completion = self.inner.complete(system, messages).await?;
+        self.track(&completion);
+        Ok(completion)
+    }
+
+    async fn stream(
+        &self,
+        system: &SystemPrompt,
+        messages: &[Turn],
+        on_token: &mut dyn for<'t> FnMut(&'t str),
+    ) -> Result<Completion, Box<dyn std::error::Error>> {
+        let completion = self.inner.stream(system, messages, on_token).await?;
+        self.track(&completion);
+        Ok(completion)
+    }
+
+    async fn complete_with_tool(&self, system: &SystemPrompt, messages: &[Turn], tool: &ToolSpec) -> Result<Option<Completion>, Box<dyn std::error::Error>> {
+        let completion = self.inner.complete_with_tool(system, messages, tool).await?;
+        if let Some(completion) = &completion {
+            self.track(completion);
+        }
+        Ok(completion)
+    }
+}
+
+/// `llm-exec stats`: tokens and estimated cost per day and model for the last `days` days
+pub fn show(days: u32) {
+    let tally = load();
+    let since = (chrono::Local::now() - chrono::Duration::days(i64::from(days.saturating_sub(1))))
+        .format("%Y-%m-%d")
+        .to_string();
+
+    println!(
+        "{:<10}  {:<40}  {:>8}  {:>12}  {:>12}  {:>12}  {:>10}",
+        "Date", "Model", "Requests", "Input", "Cached", "Output", "Est. cost"
+    );
+
+    let mut total = Totals::default();
+    let mut total_cost = 0.0;
+    let mut unpriced = false;
+    for (day, models) in tally.range(since..) {
+        for (model, totals) in models {
+            let cost = cost(model, totals);
+            println!(
+                "{:<10}  {:<40}  {:>8}  {:>12}  {:>12}  {:>12}  {:>10}",
+                day,
+                model,
+                totals.requests,
+                totals.input_tokens + totals.cache_write_tokens,
+                totals.cache_read_tokens,
+                totals.output_tokens,
+                format_cost(cost)
+            );
+
+            total.add(totals);
+            match cost {
+                Some(cost) => total_cost += cost,
+                None => unpriced = true,
+            }
+        }
+    }
+
+    println!(
+        "{:<10}  {:<40}  {:>8}  {:>12}  {:>12}  {:>12}  {:>10}",
+        "Total",
+        format!("(last {} days)", days),
+        total.requests,
+        total.input_tokens + total.cache_write_tokens,
+        total.cache_read_tokens,
+        total.output_tokens,
+        format_cost(Some(total_cost))
+    );
+    if unpriced {
+        println!();
+        println!("Costs marked - are for models without a known price (such as local models) and aren't included in the total.");
+    }
+}
@@ -0,0 +1,26 @@
+use llm_exec_core::credentials;
+
+/// `llm-exec auth login`: read an API key without echoing it and store it in the system keychain
+pub fn login(provider: &str) -> Result<(), Box<dyn std::error::Error>> {
+    credentials::check_provider(provider)?;
+
+    let key = rpassword::prompt_password(format!("API key for {}: ", provider))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err("No API key entered".into());
+    }
+
+    credentials::store_key(provider, key)?;
+    println!("Stored the {} API key in your system keychain.", provider);
+    Ok(())
+}
+
+/// `llm-exec auth logout`: remove the stored API key for `provider`
+pub fn logout(provider: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if credentials::delete_key(provider)? {
+        println!("Removed the {} API key from your system keychain.", provider);
+    } else {
+        println!("No {} API key was stored.", provider);
+    }
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use base64::Engine;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Put `text` on the system clipboard, returning the mechanism that was used.
+///
+/// Tries the platform's clipboard tools first, then falls back to the OSC 52
+/// escape sequence, which most modern terminals (including over SSH) honor.
+pub fn copy(text: &str) -> Result<&'static str, Box<dyn std::error::Error>> {
+    for (program, args) in clipboard_commands() {
+        if pipe_to(program, args, text).is_ok() {
+            return Ok(program);
+        }
+    }
+
+    osc52(text)?;
+    Ok("OSC 52")
+}
+
+/// Clipboard tools to try, in order, for this platform and display server
+fn clipboard_commands() -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(windows) {
+        return vec![("clip", &[])];
+    }
+    if cfg!(target_os = "macos") {
+        return vec![("pbcopy", &[])];
+    }
+
+    let mut commands: Vec<(&'static str, &'static [&'static str])> = Vec::new();
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        commands.push(("wl-copy", &[]));
+    }
+    if std::env::var_os("DISPLAY").is_some() {
+        commands.push(("xclip", &["-selection", "clipboard"]));
+        commands.push(("xsel", &["--clipboard", "--input"]));
+    }
+    commands
+}
+
+fn pipe_to(program: &str, args: &[&str], text: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+
+    child.stdin.take().ok_or("no stdin")?.write_all(text.as_bytes())?;
+
+    if !child.wait()?.success() {
+        return Err(format!("{} exited with an error", program).into());
+    }
+    Ok(())
+}
+
+/// Ask the terminal itself to set the clipboard
+fn osc52(text: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+    let sequence = format!("\x1b]52;c;{}\x07", encoded);
+
+    // Write to the terminal directly so the sequence never ends up in redirected output
+    let path = if cfg!(windows) { "CONOUT$" } else { "/dev/tty" };
+    let mut tty = std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|e| format!("no clipboard tool found, and could not open the terminal: {}", e))?;
+    tty.write_all(sequence.as_bytes())?;
+    tty.flush()?;
+    Ok(())
+}
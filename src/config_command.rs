@@ -0,0 +1,191 @@
+use llm_exec_core::color;
+use llm_exec_core::config::{self, Config, KeyKind};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// `llm-exec config get [key]`: print the effective value of a key (or the whole config)
+/// after every layer has been applied.
+pub fn get(config: &Config, key: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    let value = toml::Value::try_from(config)?;
+
+    let Some(key) = key else {
+        print!("{}", toml::to_string_pretty(&value)?);
+        return Ok(());
+    };
+
+    if config::key_kind(key).is_none() {
+        return Err(format!("Unknown config key \"{}\"", key).into());
+    }
+
+    let mut current = &value;
+    for part in key.split('.') {
+        match current.get(part) {
+            Some(next) => current = next,
+            None => std::process::exit(1),
+        }
+    }
+
+    match current {
+        toml::Value::String(s) => println!("{}", s),
+        toml::Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(|item| item.as_str().map_or_else(|| item.to_string(), str::to_string)).collect();
+            println!("{}", items.join(","));
+        }
+        other => println!("{}", other),
+    }
+    Ok(())
+}
+
+/// `llm-exec config set <key> <value>`: update the user's config file, refusing
+/// unknown keys and values of the wrong type.
+pub fn set(key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let kind = config::key_kind(key).ok_or_else(|| format!("Unknown config key \"{}\"", key))?;
+    let path = config::user_config_file().ok_or("Could not determine the config directory")?;
+    let content = std::fs::read_to_string(&path).unwrap_or_default();
+
+    let updated = if path.extension().is_some_and(|ext| ext == "json") {
+        set_json(&content, key, parse_json_value(kind, value)?)?
+    } else {
+        set_toml(&content, key, parse_toml_value(kind, value)?)?
+    };
+
+    // Make sure the result still loads before replacing the file
+    config::parse(&path, &updated).map_err(|e| format!("Refusing to write an invalid config: {}", e))?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, updated)?;
+    Ok(())
+}
+
+/// `llm-exec config edit`: open the user's config file in `$EDITOR`, and reopen it
+/// until it parses (or the user gives up).
+pub fn edit() -> Result<(), Box<dyn std::error::Error>> {
+    let path = config::user_config_file().ok_or("Could not determine the config directory")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    loop {
+        crate::open_in_editor(&path)?;
+
+        let Err(e) = check_file(&path) else {
+            return Ok(());
+        };
+        eprintln!("{} {} is invalid: {}", color::epaint(color::ERROR, "Error:"), path.display(), e);
+
+        print!("Edit it again? [Y/n]: ");
+        io::stdout().flush()?;
+        let again = llm_exec_core::terminal::read_line().map(|line| line.trim().to_lowercase());
+        if matches!(again.as_deref(), None | Some("n") | Some("no")) {
+            return Err("Config left invalid; llm-exec will ignore it until it's fixed".into());
+        }
+    }
+}
+
+fn check_file(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        // Closing the editor without saving a new file is fine
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    config::parse(path, &content)?;
+    Ok(())
+}
+
+fn parse_toml_value(kind: KeyKind, value: &str) -> Result<toml_edit::Value, Box<dyn std::error::Error>> {
+    Ok(match kind {
+        KeyKind::String => value.into(),
+        KeyKind::Integer => parse_integer(value)?.into(),
+        KeyKind::Number => parse_number(value)?.into(),
+        KeyKind::Bool => parse_bool(value)?.into(),
+        KeyKind::List => toml_edit::Value::Array(split_list(value).collect()),
+    })
+}
+
+fn parse_json_value(kind: KeyKind, value: &str) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    Ok(match kind {
+        KeyKind::String => value.into(),
+        KeyKind::Integer => parse_integer(value)?.into(),
+        KeyKind::Number => parse_number(value)?.into(),
+        KeyKind::Bool => parse_bool(value)?.into(),
+        KeyKind::List => split_list(value).collect::<Vec<_>>().into(),
+    })
+}
+
+fn parse_integer(value: &str) -> Result<i64, Box<dyn std::error::Error>> {
+    value
+        .parse::<u32>()
+        .map(i64::from)
+        .map_err(|_| format!("Expected a non-negative whole number, got \"{}\"", value).into())
+}
+
+fn parse_number(value: &str) -> Result<f64, Box<dyn std::error::Error>> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite() && *number >= 0.0)
+        .ok_or_else(|| format!("Expected a non-negative number, got \"{}\"", value).into())
+}
+
+fn parse_bool(value: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(format!("Expected true or false, got \"{}\"", value).into()),
+    }
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|item| !item.is_empty())
+}
+
+/// Set a dotted key in a TOML document, keeping its comments and formatting
+fn set_toml(content: &str, key: &str, value: toml_edit::Value) -> Result<String, Box<dyn std::error::Error>> {
+    let mut doc: toml_edit::DocumentMut = content.parse()?;
+    let parts: Vec<&str> = key.split('.').collect();
+    let (last, tables) = parts.split_last().ok_or("Empty config key")?;
+
+    let mut table = doc.as_table_mut();
+    for part in tables {
+        table = table
+            .entry(part)
+            .or_insert_with(|| {
+                // Only the innermost table needs its own [header]
+                let mut table = toml_edit::Table::new();
+                table.set_implicit(true);
+                toml_edit::Item::Table(table)
+            })
+            .as_table_mut()
+            .ok_or_else(|| format!("\"{}\" is not a table in the config file", part))?;
+    }
+    table.insert(last, toml_edit::value(value));
+
+    Ok(doc.to_string())
+}
+
+/// Set a dotted key in a JSON config
+fn set_json(content: &str, key: &str, value: serde_json::Value) -> Result<String, Box<dyn std::error::Error>> {
+    let mut root: serde_json::Value = if content.trim().is_empty() {
+        serde_json::json!({})
+    } else {
+        serde_json::from_str(content)?
+    };
+
+    let parts: Vec<&str> = key.split('.').collect();
+    let (last, objects) = parts.split_last().ok_or("Empty config key")?;
+
+    let mut object = root.as_object_mut().ok_or("The config file is not a JSON object")?;
+    for part in objects {
+        object = object
+            .entry(part.to_string())
+            .or_insert_with(|| serde_json::json!({}))
+            .as_object_mut()
+            .ok_or_else(|| format!("\"{}\" is not an object in the config file", part))?;
+    }
+    object.insert(last.to_string(), value);
+
+    Ok(serde_json::to_string_pretty(&root)? + "\n")
+}
@@ -0,0 +1,63 @@
+use llm_exec_core::color;
+
+/// Words shown in bold when highlighting a script
+const KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac", "in", "function", "return", "local", "export", "exit",
+];
+
+/// Color a script for the terminal: comments, quoted strings, variables, and shell keywords
+pub fn script(script: &str) -> String {
+    script.lines().map(line).collect::<Vec<_>>().join("\n")
+}
+
+fn line(line: &str) -> String {
+    let mut out = String::new();
+    let mut word = String::new();
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let end = match c {
+            '#' if word.is_empty() && line[..i].chars().last().is_none_or(char::is_whitespace) => {
+                out.push_str(&color::paint(color::DIM, &line[i..]));
+                return out;
+            }
+            '\'' | '"' => line[i + 1..].find(c).map_or(line.len(), |close| i + close + 2),
+            '$' => {
+                let rest = &line[i + 1..];
+                let len = if rest.starts_with('{') {
+                    rest.find('}').map_or(rest.len(), |close| close + 1)
+                } else {
+                    rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len())
+                };
+                i + 1 + len
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                word.push(c);
+                continue;
+            }
+            c => {
+                out.push_str(&keyword(&mut word));
+                out.push(c);
+                continue;
+            }
+        };
+
+        out.push_str(&keyword(&mut word));
+        let style = if c == '$' { color::CYAN } else { color::GREEN };
+        out.push_str(&color::paint(style, &line[i..end]));
+        while chars.next_if(|&(j, _)| j < end).is_some() {}
+    }
+
+    out.push_str(&keyword(&mut word));
+    out
+}
+
+/// The word, in bold if it's a keyword, leaving `word` empty
+fn keyword(word: &mut String) -> String {
+    let word = std::mem::take(word);
+    if KEYWORDS.contains(&word.as_str()) {
+        color::paint(color::BOLD, &word)
+    } else {
+        word
+    }
+}
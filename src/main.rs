@@ -1,491 +1,2345 @@
-use clap::Parser;
-use serde::{Deserialize, Serialize};
-use std::io::{self, Write};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-
-const DEFAULT_MODEL: &str = "claude-haiku-4-5-20251001";
-const DEFAULT_MAX_TOKENS: u32 = 1024;
-const DEFAULT_HISTORY_LINES: usize = 1000;
-const DEFAULT_CONTEXT_FILES: &[&str] = &["CLAUDE.md", "AGENTS.md"];
-const CONFIG_PATH: &str = ".config/llm-exec/config.json";
-const API_URL: &str = "https://api.anthropic.com/v1/messages";
-const API_VERSION: &str = "2023-06-01";
-
-const DEFAULT_SYSTEM_PROMPT: &str = r#"You are a command-line assistant that outputs ONLY shell commands.
-
-RULES:
-1. Output ONLY a single shell command - nothing else
-2. NO explanations, NO markdown, NO code blocks, NO backticks, NO formatting
-3. If you cannot help, output: echo "Error: <reason>"
-4. Never suggest running "{}" - the user is already running that to talk to you
-
-Your entire response must be a valid shell command that can be executed directly."#;
-
-#[derive(Deserialize, Default)]
-struct Config {
-    /// Model to use
-    model: Option<String>,
-    /// Max tokens for response
-    max_tokens: Option<u32>,
-    /// Number of history lines to include
-    history_lines: Option<usize>,
-    /// Additional instructions to append to the system prompt
-    system_prompt_suffix: Option<String>,
-    /// Complete override of the system prompt (replaces default)
-    system_prompt: Option<String>,
-    /// Context files to look for in cwd and parent directories (default: ["CLAUDE.md", "AGENTS.md"])
-    context_files: Option<Vec<String>>,
-}
-
-fn get_config_path() -> Option<PathBuf> {
-    let home = dirs::home_dir()?;
-    Some(home.join(CONFIG_PATH))
-}
-
-fn load_config() -> Config {
-    let Some(path) = get_config_path() else {
-        return Config::default();
-    };
-
-    if !path.exists() {
-        return Config::default();
-    }
-
-    match std::fs::read_to_string(&path) {
-        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
-            eprintln!("Warning: Could not parse config file: {}", e);
-            Config::default()
-        }),
-        Err(e) => {
-            eprintln!("Warning: Could not read config file: {}", e);
-            Config::default()
-        }
-    }
-}
+mod auth;
+mod clipboard;
+mod config_command;
+mod highlight;
+mod wizard;
+
+use clap::{CommandFactory, Parser};
+use llm_exec_core::color::ColorChoice;
+use llm_exec_core::config::{self, AgentConfig, Config, ContextConfig};
+use llm_exec_core::conversation::{Conversation, Image, Role, SystemPrompt};
+use llm_exec_core::execute::{self, Captured};
+use llm_exec_core::history::HistoryBackend;
+use llm_exec_core::prompt::{self, shell_name, split_undo, undo_line_start, ERROR_SIGIL, EXPLAIN_SYSTEM_PROMPT, SUMMARIZE_SYSTEM_PROMPT, UNDO_MARKER};
+use llm_exec_core::providers::{self, Completion, Provider};
+use llm_exec_core::risk::{self, Policy, Risk};
+use llm_exec_core::{audit, color, context, favorites, history, redact, response_cache, sandbox, sessions, shell_integration, suggest_structured, terminal, usage, StructuredSuggestion, SuggestContext};
+use serde::Serialize;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// Times a failed command can be sent back to the model to fix, unless `max_fix_attempts` is set
+const DEFAULT_MAX_FIX_ATTEMPTS: u32 = 3;
+/// Most commands `--agent` runs for one task, unless `agent.max_steps` or `--max-steps` is set
+const DEFAULT_AGENT_MAX_STEPS: u32 = 10;
+/// Most history matches `--offline` offers
+const OFFLINE_MATCHES: usize = 10;
+
+/// Exit statuses besides 0, so wrappers can tell what happened: the command (or llm-exec itself)
+/// failed, the user declined to run it, the model couldn't be reached, or a policy blocked it
+const EXIT_FAILED: i32 = 1;
+const EXIT_DECLINED: i32 = 2;
+const EXIT_API_ERROR: i32 = 3;
+const EXIT_BLOCKED: i32 = 4;
+
+/// Set by `--quiet`, which leaves out headings, notes, and the "Thinking..." indicator
+static QUIET: AtomicBool = AtomicBool::new(false);
 
 #[derive(Parser)]
 #[command(name = "llm-exec")]
 #[command(about = "Execute terminal commands based on LLM instructions")]
+// Prompts like "help me find large files" must not be taken as a subcommand
+#[command(disable_help_subcommand = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// The prompt describing what command you want to run
     prompt: Vec<String>,
 
+    /// Use the named prompt from `templates` in your config, filling its placeholders from
+    /// `name=value` arguments
+    #[arg(short = 't', long, value_name = "NAME")]
+    template: Option<String>,
+
+    /// Show the most recent suggestion again, from the audit log, and offer to run it without asking the model
+    #[arg(long, conflicts_with_all = ["prompt", "template", "print", "output", "candidates", "compare", "offline", "repl", "script", "plan", "agent"])]
+    last: bool,
+
+    /// Continue a saved conversation: the most recent one, or the one with this ID
+    #[arg(long, value_name = "ID", num_args = 0..=1, default_missing_value = "", conflicts_with_all = ["last", "print", "output", "script", "plan", "agent"])]
+    resume: Option<String>,
+
     /// Number of history lines to include
     #[arg(short = 'n', long)]
     history_lines: Option<usize>,
 
-    /// Skip confirmation and execute immediately
-    #[arg(short = 'y', long)]
+    /// Don't send any shell history, including the previous command
+    #[arg(long, conflicts_with = "history_lines")]
+    no_history: bool,
+
+    /// Skip confirmation and execute immediately, where the risk level's `confirm` policy allows it
+    #[arg(short = 'y', long, global = true)]
     yes: bool,
 
     /// Show what would be sent to the API without making a request
     #[arg(long)]
     dry_run: bool,
+
+    /// Ask for N alternative commands and pick one from a menu
+    #[arg(long, value_name = "N")]
+    candidates: Option<usize>,
+
+    /// Ask several models at once, as `model` or `provider:model`, and pick one of their suggestions
+    #[arg(long, value_name = "MODELS", value_delimiter = ',', conflicts_with_all = ["candidates", "repl", "print", "output", "yes", "script", "plan", "agent"])]
+    compare: Vec<String>,
+
+    /// Don't ask the model; pick from the commands in your shell history that match the prompt
+    #[arg(long, conflicts_with_all = ["candidates", "compare", "print", "output", "script", "plan", "agent", "explain", "summarize", "dry_run"])]
+    offline: bool,
+
+    /// Explain what the suggested command does before asking to run it
+    #[arg(long)]
+    explain: bool,
+
+    /// After running the command, have the model summarize its output and suggest a next step
+    #[arg(long, conflicts_with_all = ["print", "output", "copy", "sandbox"])]
+    summarize: bool,
+
+    /// Let the model write a multi-line script, which you review in $EDITOR before it runs
+    #[arg(long, conflicts_with_all = ["candidates", "repl", "copy", "output", "sandbox", "summarize"])]
+    script: bool,
+
+    /// Ask the model for a plan of steps first, then suggest and confirm a command for each step in turn
+    #[arg(long, conflicts_with_all = ["repl", "print", "output", "copy", "script"])]
+    plan: bool,
+
+    /// Let the model carry out the task itself, running one confirmed command at a time and seeing its output
+    #[arg(long, conflicts_with_all = ["yes", "repl", "print", "output", "copy", "script", "plan", "candidates", "summarize"])]
+    agent: bool,
+
+    /// Most commands `--agent` runs before stopping (default: 10)
+    #[arg(long, value_name = "N", requires = "agent")]
+    max_steps: Option<u32>,
+
+    /// Start an interactive session that keeps the conversation across commands
+    #[arg(long)]
+    repl: bool,
+
+    /// Copy the suggested command to the clipboard instead of executing it
+    #[arg(long, conflicts_with_all = ["yes", "print", "output"])]
+    copy: bool,
+
+    /// Print only the suggested command to stdout, without confirming or executing it
+    #[arg(short = 'p', long, conflicts_with_all = ["yes", "repl"])]
+    print: bool,
+
+    /// Include a listing of the current directory as context
+    #[arg(long)]
+    context_cwd: bool,
+
+    /// Include git branch, remotes, status, and recent commits as context
+    #[arg(long)]
+    context_git: bool,
+
+    /// Use the named profile from the config file (default: $LLM_EXEC_PROFILE)
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Model to use, or an alias such as `sonnet` or one from `model_aliases`
+    #[arg(short = 'm', long)]
+    model: Option<String>,
+
+    /// Most tokens the model may generate in a response, overriding `max_tokens`
+    #[arg(long, value_name = "N")]
+    max_tokens: Option<u32>,
+
+    /// Sampling temperature, overriding `temperature` (0 for the most repeatable suggestions)
+    #[arg(long, value_name = "N")]
+    temperature: Option<f64>,
+
+    /// Nucleus sampling probability, overriding `top_p`
+    #[arg(long, value_name = "P")]
+    top_p: Option<f64>,
+
+    /// Sample from only this many of the most likely tokens, overriding `top_k`
+    #[arg(long, value_name = "N")]
+    top_k: Option<u32>,
+
+    /// Let Anthropic and Bedrock models think before answering, for up to BUDGET tokens (default: 4096)
+    #[arg(long, value_name = "BUDGET", num_args = 0..=1, require_equals = true, default_missing_value = "4096")]
+    think: Option<u32>,
+
+    /// Extra instructions to add to the system prompt for this run
+    #[arg(long, value_name = "TEXT")]
+    system_suffix: Option<String>,
+
+    /// Add the contents of a file to the system prompt for this run
+    #[arg(long, value_name = "PATH")]
+    system_file: Option<std::path::PathBuf>,
+
+    /// Refuse to run if a config file has unknown keys or can't be parsed
+    #[arg(long)]
+    strict_config: bool,
+
+    /// Include the current tmux pane's recent scrollback as context
+    #[arg(long)]
+    context_tmux: bool,
+
+    /// Run a command and include its output as context (can be given more than once)
+    #[arg(long, value_name = "COMMAND")]
+    context_cmd: Vec<String>,
+
+    /// Include the contents of a file as context (can be given more than once)
+    #[arg(long, value_name = "PATH")]
+    file: Vec<String>,
+
+    /// Attach an image, such as a screenshot of an error, for models that accept images (can be given more than once)
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["repl", "plan"])]
+    image: Vec<std::path::PathBuf>,
+
+    /// Run the command even if it matches a `blocklist` pattern
+    #[arg(long)]
+    force: bool,
+
+    /// Leave out headings, notes, and the "Thinking..." indicator
+    #[arg(short = 'q', long, global = true)]
+    quiet: bool,
+
+    /// Print token usage and estimated cost for each request, and trace requests to stderr
+    /// (-vv adds request headers, with credentials redacted)
+    #[arg(short = 'v', long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Run the command in a throwaway Docker/Podman container instead of on this machine
+    #[arg(long, conflicts_with_all = ["print", "output", "copy"])]
+    sandbox: bool,
+
+    /// Give up on a request after this many seconds (0 waits indefinitely)
+    #[arg(long, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Always ask the model, instead of reusing the response to an identical recent request
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Output format; `json` prints a single JSON object for tools and editor plugins
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text, conflicts_with_all = ["yes", "repl", "print"])]
+    output: OutputFormat,
+
+    /// When to color output; `auto` colors it for a terminal, unless NO_COLOR is set
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto, global = true)]
+    color: ColorChoice,
 }
 
-#[derive(Serialize)]
-struct Message {
-    role: String,
-    content: String,
+#[derive(clap::Subcommand)]
+enum Commands {
+    /// Suggest a corrected version of the last command you ran
+    Fix {
+        /// Optional hint about what went wrong or what you meant
+        hint: Vec<String>,
+    },
+    /// Answer a question in prose instead of suggesting a command; nothing is run
+    Ask {
+        /// The question, e.g. "what does exit code 137 mean"
+        question: Vec<String>,
+    },
+    /// Suggest a command for each task in a file, one per line, in turn (running them with --yes),
+    /// then summarize what happened to each
+    Batch {
+        /// File of tasks; blank lines and lines starting with # are skipped
+        file: std::path::PathBuf,
+    },
+    /// Explain what a command does, with a breakdown of each flag and argument
+    Explain {
+        /// The command to explain (quote it if it contains pipes or other shell syntax)
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Print shell integration (hooks and key bindings) to eval in your shell's rc file
+    ShellInit {
+        #[arg(value_parser = clap::builder::PossibleValuesParser::new(shell_integration::SHELLS))]
+        shell: String,
+    },
+    /// Print a completion script for your shell
+    Completions {
+        shell: clap_complete::Shell,
+    },
+    /// Store or remove API keys in the OS keychain
+    Auth {
+        #[command(subcommand)]
+        action: AuthCommand,
+    },
+    /// Manage the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// List the models the configured provider offers, with their context sizes where it says
+    Models,
+    /// Show token usage and estimated cost per day and model
+    Stats {
+        /// Number of days to show, including today
+        #[arg(long, default_value_t = 30)]
+        days: u32,
+    },
+    /// Show the prompts you've asked and the commands suggested for them
+    History {
+        /// Only show prompts or commands containing this text
+        search: Option<String>,
+        /// Number of most recent prompts to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// List the commands saved as favorites from the confirmation menu
+    Favorites {
+        /// Only show favorites whose prompt or command contains this text
+        search: Option<String>,
+    },
+    /// Show the audit log of suggestions and what happened to them
+    Log {
+        /// Only show entries whose prompt or command contains this text
+        search: Option<String>,
+        /// Number of most recent entries to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+        /// Only show entries with this outcome
+        #[arg(long, value_enum)]
+        action: Option<audit::Action>,
+        /// Only show commands that exited with a non-zero status
+        #[arg(long)]
+        failed: bool,
+        /// Print entries as JSON lines
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum AuthCommand {
+    /// Store an API key in the OS keychain, used when the provider's environment variable isn't set
+    Login {
+        /// Provider the key is for (default: the configured provider)
+        #[arg(long)]
+        provider: Option<String>,
+    },
+    /// Remove a stored API key
+    Logout {
+        /// Provider the key is for (default: the configured provider)
+        #[arg(long)]
+        provider: Option<String>,
+    },
+}
+
+#[derive(clap::Subcommand)]
+enum ConfigCommand {
+    /// Interactively create a config file and check that the provider works
+    Init,
+    /// Print the effective value of a key (e.g. `context.git`), or the whole config
+    Get { key: Option<String> },
+    /// Set a key in your config file
+    Set { key: String, value: String },
+    /// Open your config file in $EDITOR and check it afterwards
+    Edit,
 }
 
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    /// Interactive, human-readable output
+    Text,
+    /// A single JSON object describing the suggestion, without executing it
+    Json,
+}
+
+/// The object printed by `--output json`
 #[derive(Serialize)]
-struct AnthropicRequest {
+struct JsonSuggestion {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
     model: String,
-    max_tokens: u32,
-    system: String,
-    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explanation: Option<String>,
+    /// How to reverse a destructive command, as suggested by the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    undo: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    risk: Option<Risk>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    risk_reasons: Vec<&'static str>,
+    /// The blocklist pattern the command matches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blocked_by: Option<String>,
+    /// Whether the command is in the allowlist, when one is configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed: Option<bool>,
+    /// Whether the suggestion came from the response cache rather than the model
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    cached: bool,
+    tokens_in: u64,
+    tokens_out: u64,
 }
 
-#[derive(Deserialize)]
-struct ContentBlock {
-    text: Option<String>,
+/// Show "Thinking..." while waiting for the model, unless `--quiet` was given
+fn show_thinking() {
+    if !QUIET.load(Ordering::Relaxed) {
+        eprint!("Thinking...");
+        io::stderr().flush().unwrap();
+    }
 }
 
-#[derive(Deserialize)]
-struct AnthropicResponse {
-    content: Vec<ContentBlock>,
+fn clear_thinking() {
+    if !QUIET.load(Ordering::Relaxed) {
+        eprint!("\r           \r");
+    }
 }
 
-/// Load context files from current directory and parent directories.
-/// Files closer to the current directory take precedence (loaded last).
-fn load_context_files(filenames: &[String]) -> String {
-    if filenames.is_empty() {
-        return String::new();
+/// Print a section heading, unless `--quiet` was given
+fn heading(text: &str) {
+    if !QUIET.load(Ordering::Relaxed) {
+        println!("{}", color::paint(color::HEADING, text));
     }
+}
 
-    let mut context_parts: Vec<String> = Vec::new();
+/// Print a dim note about what's going on, unless `--quiet` was given
+fn note(text: impl std::fmt::Display) {
+    if !QUIET.load(Ordering::Relaxed) {
+        println!("{}", color::paint(color::DIM, text));
+    }
+}
 
-    let Ok(cwd) = std::env::current_dir() else {
-        return String::new();
-    };
+/// Like `note`, for stderr
+fn enote(text: impl std::fmt::Display) {
+    if !QUIET.load(Ordering::Relaxed) {
+        eprintln!("{}", color::epaint(color::DIM, text));
+    }
+}
 
-    // Collect all parent directories from root to cwd
-    let mut dirs: Vec<PathBuf> = Vec::new();
-    let mut current = Some(cwd.as_path());
-    while let Some(dir) = current {
-        dirs.push(dir.to_path_buf());
-        current = dir.parent();
-    }
-
-    // Process from root to cwd (so closer files are appended last)
-    dirs.reverse();
-
-    for dir in dirs {
-        for filename in filenames {
-            let path = dir.join(filename);
-            if path.exists() {
-                if let Ok(content) = std::fs::read_to_string(&path) {
-                    let content = content.trim();
-                    if !content.is_empty() {
-                        context_parts.push(format!("# {}\n\n{}", path.display(), content));
-                    }
-                }
+/// Prints the suggested command as it streams in, replacing the "Thinking..." indicator.
+/// Output is held back while it could still turn out to be the model's error sigil.
+#[derive(Default)]
+struct CommandStream {
+    text: String,
+    printed: usize,
+    started: bool,
+}
+
+impl CommandStream {
+    fn push(&mut self, token: &str) {
+        self.text.push_str(token);
+
+        let trimmed = self.text.trim_start();
+        if ERROR_SIGIL.starts_with(trimmed) || trimmed.starts_with(ERROR_SIGIL) {
+            return;
+        }
+
+        if !self.started {
+            clear_thinking();
+            heading("Suggested command:");
+            print!("{}  ", color::start(color::COMMAND));
+            self.printed = self.text.len() - trimmed.len();
+            self.started = true;
+        }
+
+        // Hold back trailing whitespace until we know more text follows it, and the
+        // undo line (or a line that could still turn out to be it), which is shown separately
+        let mut end = self.text.trim_end().len();
+        if let Some(start) = undo_line_start(&self.text) {
+            end = end.min(self.text[..start].trim_end().len());
+        } else if let Some(newline) = self.text.rfind('\n') {
+            if UNDO_MARKER.starts_with(self.text[newline + 1..].trim_start()) {
+                end = end.min(self.text[..newline].trim_end().len());
             }
         }
+        if end > self.printed {
+            print!("{}", self.text[self.printed..end].replace('\n', "\n  "));
+            self.printed = end;
+            io::stdout().flush().unwrap();
+        }
     }
 
-    context_parts.join("\n\n")
+    /// Finish the display, returning whether the command was shown
+    fn finish(self) -> bool {
+        if self.started {
+            println!("{}", color::reset());
+            println!();
+        } else {
+            clear_thinking();
+        }
+        self.started
+    }
 }
 
-fn get_history_file() -> Option<std::path::PathBuf> {
-    let home = dirs::home_dir()?;
-    let history_files = [
-        home.join(".zsh_history"),
-        home.join(".bash_history"),
-        home.join(".history"),
-    ];
+/// Query the model, streaming the suggested command to the terminal as it arrives.
+/// Returns the full response and whether it was already displayed.
+async fn suggest_command(provider: &dyn Provider, system_prompt: &SystemPrompt, conversation: &Conversation) -> Result<(String, bool), Box<dyn std::error::Error>> {
+    show_thinking();
+
+    let mut display = CommandStream::default();
+    let result = provider.stream(system_prompt, conversation.turns(), &mut |token| display.push(token)).await;
+    let shown = display.finish();
 
-    history_files.into_iter().find(|f| f.exists())
+    Ok((result?.text, shown))
 }
 
-fn get_shell_history(lines: usize) -> Result<String, Box<dyn std::error::Error>> {
-    let history_file = get_history_file().ok_or("Could not find shell history file")?;
+/// Ask the model for `count` alternative commands and let the user pick one.
+/// Returns `None` if the user cancels.
+async fn choose_candidate(provider: &dyn Provider, system_prompt: &SystemPrompt, conversation: &Conversation, count: usize) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let mut system_prompt = system_prompt.clone();
+    system_prompt.push(
+        format!(
+            "Instead of a single command, output exactly {} alternative commands, one per line, each taking a different approach (e.g. different tools). Every line must be a complete command on its own.",
+            count
+        ),
+        false,
+    );
+
+    show_thinking();
+    let response = provider.complete(&system_prompt, conversation.turns()).await;
+    clear_thinking();
+
+    let response = response?.text;
+    let candidates: Vec<&str> = response
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with(UNDO_MARKER))
+        .take(count)
+        .collect();
 
-    let content = std::fs::read_to_string(&history_file)?;
-    let history_lines: Vec<&str> = content.lines().collect();
-    let start = history_lines.len().saturating_sub(lines);
+    if candidates.len() <= 1 {
+        return Ok(Some(response.trim().to_string()));
+    }
 
-    // Clean up zsh history format (removes timestamps like `: 1234567890:0;`)
-    let cleaned: Vec<String> = history_lines[start..]
-        .iter()
-        .map(|line| {
-            if line.starts_with(": ") && line.contains(";") {
-                line.split_once(";")
-                    .map(|(_, cmd)| cmd)
-                    .unwrap_or(line)
-                    .to_string()
-            } else {
-                line.to_string()
-            }
-        })
-        .collect();
+    heading("Candidate commands:");
+    for (i, candidate) in candidates.iter().enumerate() {
+        println!("  {}) {}", i + 1, color::paint(color::COMMAND, candidate));
+    }
+    println!();
 
-    Ok(cleaned.join("\n"))
+    Ok(select_number(candidates.len()).map(|n| candidates[n - 1].to_string()))
 }
 
-fn append_to_history(command: &str) -> Result<(), Box<dyn std::error::Error>> {
-    use std::fs::OpenOptions;
+/// Ask for a number from 1 to `count` until one is given, or `None` if the user cancels
+fn select_number(count: usize) -> Option<usize> {
+    loop {
+        print!("Select a command [1-{}, q to cancel]: ", count);
+        io::stdout().flush().unwrap();
 
-    let history_file = get_history_file().ok_or("Could not find shell history file")?;
-    let mut file = OpenOptions::new().append(true).open(&history_file)?;
+        let input = terminal::read_line().unwrap_or_default();
+        let input = input.trim();
 
-    // Format depends on shell type
-    let entry = if history_file.to_string_lossy().contains("zsh_history") {
-        // zsh extended history format: `: timestamp:0;command`
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .map(|d| d.as_secs())
-            .unwrap_or(0);
-        format!(": {}:0;{}\n", timestamp, command)
-    } else {
-        // bash format: just the command
-        format!("{}\n", command)
-    };
+        if input.is_empty() || input.eq_ignore_ascii_case("q") {
+            return None;
+        }
 
-    file.write_all(entry.as_bytes())?;
-    Ok(())
+        match input.parse::<usize>() {
+            Ok(n) if (1..=count).contains(&n) => {
+                println!();
+                return Some(n);
+            }
+            _ => println!("Please enter a number between 1 and {}.", count),
+        }
+    }
 }
 
-fn build_system_prompt(history: &str, context_files: &str, config: &Config, argv0: &str) -> String {
-    let base_prompt = config
-        .system_prompt
-        .as_deref()
-        .unwrap_or(DEFAULT_SYSTEM_PROMPT)
-        .replace("{}", argv0);
-
-    let mut system_prompt = base_prompt;
+/// Commands from the shell history that match the prompt, for `--offline` or when the provider
+/// can't be reached, for the user to pick one of. Returns `None` if they cancel.
+fn search_history(session: &Session<'_>, prompt: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let history = session.history.recent(usize::MAX).map_err(|e| format!("Could not search shell history: {}", e))?.join("\n");
+    let mut matches = history::search(&history, prompt, OFFLINE_MATCHES);
+    if matches.is_empty() {
+        return Err("No commands in your shell history match the prompt".into());
+    }
 
-    if !context_files.is_empty() {
-        system_prompt.push_str("\n\n");
-        system_prompt.push_str(context_files);
+    heading("Matching commands from your history:");
+    for (i, command) in matches.iter().enumerate() {
+        println!("  {}) {}", i + 1, color::paint(color::COMMAND, command));
     }
+    println!();
+
+    Ok(select_number(matches.len()).map(|n| matches.swap_remove(n - 1)))
+}
 
-    system_prompt.push_str("\n\nThe user's recent shell history:\n");
-    system_prompt.push_str(history);
+/// `--compare`: ask every compared model for a suggestion at once, show them with how long
+/// each took and what it cost, and have the user pick one. Returns `None` if they cancel.
+async fn compare_models(session: &Session<'_>, conversation: &Conversation) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    show_thinking();
+    let requests = session.compared.iter().map(|(_, provider)| async move {
+        let start = Instant::now();
+        let completion = provider.complete(&session.system_prompt, conversation.turns()).await;
+        (completion, start.elapsed())
+    });
+    let results = futures_util::future::join_all(requests).await;
+    clear_thinking();
+
+    let mut suggestions = Vec::new();
+    heading("Suggestions:");
+    for ((name, provider), (completion, elapsed)) in session.compared.iter().zip(results) {
+        let completion = match completion {
+            Ok(completion) => completion,
+            Err(e) => {
+                println!("  {} {}", color::paint(color::BOLD, name), color::paint(color::ERROR, format!("failed: {}", e)));
+                continue;
+            }
+        };
+        let mut details = format!("{:.1}s", elapsed.as_secs_f64());
+        if let Some(cost) = completion.usage.and_then(|usage| usage::request_cost(provider.model(), usage)) {
+            details.push_str(&format!(", ~${:.4}", cost));
+        }
+        let text = prompt::normalize_response(&completion.text);
+        let (command, _) = split_undo(&text);
+        let shown = match prompt::refusal(&command) {
+            Some(error) => color::paint(color::DIM, format!("(no command: {})", error)),
+            None => {
+                suggestions.push(text.clone());
+                format!("{}) {}", suggestions.len(), color::paint(color::COMMAND, command.replace('\n', "\n     ")))
+            }
+        };
+        println!("  {} {}", color::paint(color::BOLD, name), color::paint(color::DIM, format!("({})", details)));
+        println!("     {}", shown);
+        provider.report();
+    }
+    println!();
 
-    if let Some(suffix) = &config.system_prompt_suffix {
-        system_prompt.push_str("\n\n");
-        system_prompt.push_str(suffix);
+    if suggestions.is_empty() {
+        return Err("None of the compared models suggested a command".into());
     }
 
-    system_prompt
+    Ok(select_number(suggestions.len()).map(|n| suggestions.swap_remove(n - 1)))
+}
+
+/// Ask the model to explain a command, streaming the explanation to the terminal.
+async fn explain_command(provider: &dyn Provider, command: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conversation = Conversation::new();
+    conversation.push(Role::User, command);
+
+    heading("Explanation:");
+    provider
+        .stream(&EXPLAIN_SYSTEM_PROMPT.into(), conversation.turns(), &mut |token| {
+            print!("{}", token);
+            io::stdout().flush().unwrap();
+        })
+        .await?;
+    println!();
+    println!();
+
+    Ok(())
 }
 
-async fn call_claude(prompt: &str, history: &str, context_files: &str, config: &Config, argv0: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let messages = vec![("user".to_string(), prompt.to_string())];
-    call_claude_with_messages(&messages, history, context_files, config, argv0).await
+/// Print the models the provider offers, marking the one in use, for `llm-exec models`
+async fn list_models(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let models = providers::list_models(config).await?;
+    let provider = config.provider.as_deref().unwrap_or(providers::DEFAULT_PROVIDER);
+    let current = config.model.as_deref().or(providers::default_model(provider));
+    let width = models.iter().map(|model| model.id.len()).max().unwrap_or(0);
+
+    for model in &models {
+        let marker = if Some(model.id.as_str()) == current { "*" } else { " " };
+        let context = match model.context_tokens {
+            Some(tokens) if tokens >= 1_000_000 => format!("{:.1}M tokens", tokens as f64 / 1_000_000.0),
+            Some(tokens) => format!("{}k tokens", tokens / 1000),
+            None => String::new(),
+        };
+        let line = format!("{} {:width$}  {}", marker, model.id, context, width = width);
+        println!("{}", line.trim_end());
+    }
+    if models.is_empty() {
+        println!("No models found.");
+    }
+    Ok(())
 }
 
-async fn call_claude_with_messages(messages: &[(String, String)], history: &str, context_files: &str, config: &Config, argv0: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let api_key = std::env::var("ANTHROPIC_API_KEY")
-        .map_err(|_| "ANTHROPIC_API_KEY environment variable not set")?;
+/// Stream the model's answer to a question to the terminal, for `llm-exec ask`
+async fn answer_question(provider: &dyn Provider, system_prompt: &SystemPrompt, question: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conversation = Conversation::new();
+    conversation.push(Role::User, question);
+
+    provider
+        .stream(system_prompt, conversation.turns(), &mut |token| {
+            print!("{}", token);
+            io::stdout().flush().unwrap();
+        })
+        .await?;
+    println!();
 
-    let system_prompt = build_system_prompt(history, context_files, config, argv0);
+    Ok(())
+}
 
-    let model = config.model.as_deref().unwrap_or(DEFAULT_MODEL);
-    let max_tokens = config.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS);
+/// A command's exit status and the end of its output, for the model
+fn describe_output(status: ExitStatus, output: &Captured) -> String {
+    let status = status.code().map_or_else(|| "killed by a signal".to_string(), |code| code.to_string());
+    let mut description = format!("Exit status: {}\n", status);
+    for (name, text) in [("Output", &output.stdout), ("Error output", &output.stderr)] {
+        if !text.trim().is_empty() {
+            description.push_str(&format!("\n{} (the end, if it was long):\n{}\n", name, text.trim_end()));
+        }
+    }
+    description
+}
 
-    let api_messages: Vec<Message> = messages
-        .iter()
-        .map(|(role, content)| Message {
-            role: role.clone(),
-            content: content.clone(),
+/// Ask the model to summarize what a command printed, streaming the summary to the terminal.
+async fn summarize_output(session: &Session<'_>, prompt: &str, command: &str, status: ExitStatus, output: &Captured) -> Result<(), Box<dyn std::error::Error>> {
+    let transcript = format!("What I wanted: {}\nCommand: {}\n{}", prompt, command, describe_output(status, output));
+    let mut conversation = Conversation::new();
+    conversation.push(Role::User, transcript);
+
+    println!();
+    heading("Summary:");
+    session
+        .provider
+        .stream(&SUMMARIZE_SYSTEM_PROMPT.into(), conversation.turns(), &mut |token| {
+            print!("{}", token);
+            io::stdout().flush().unwrap();
         })
-        .collect();
+        .await?;
+    println!();
+    session.provider.report();
+
+    Ok(())
+}
+
+/// Print a prompt and read a trimmed line of input
+fn read_input(prompt: &str) -> String {
+    print!("{}", prompt);
+    io::stdout().flush().unwrap();
+
+    terminal::read_line().unwrap_or_default().trim().to_string()
+}
 
-    let request = AnthropicRequest {
-        model: model.to_string(),
-        max_tokens,
-        system: system_prompt,
-        messages: api_messages,
+/// Warn about a MEDIUM or HIGH risk command and why it was rated that way
+fn print_risk(assessment: &risk::Assessment) {
+    let color = match assessment.risk {
+        Risk::Low => return,
+        Risk::Medium => color::WARNING,
+        Risk::High => color::ERROR,
     };
+    eprintln!("{} - this command {}", color::epaint(color, format!("Risk: {}", assessment.risk.label())), assessment.reasons.join(", "));
+    eprintln!();
+}
 
-    let client = reqwest::Client::new();
-    let response = client
-        .post(API_URL)
-        .header("x-api-key", api_key)
-        .header("anthropic-version", API_VERSION)
-        .header("content-type", "application/json")
-        .json(&request)
-        .send()
-        .await?;
+/// Whether the allowlist, if set, permits the command
+fn is_allowed(session: &Session<'_>, command: &str) -> bool {
+    session.allowlist.as_ref().is_none_or(|allowlist| allowlist.permits(command))
+}
+
+/// Whether the command isn't in the allowlist, or matches the blocklist and `--force` wasn't given, saying why if so
+fn is_blocked(session: &Session<'_>, command: &str) -> bool {
+    if !is_allowed(session, command) {
+        eprintln!("{} Not running this command, only commands in the allowlist can be run", color::epaint(color::ERROR, "Error:"));
+        return true;
+    }
+
+    let Some(rule) = session.blocklist.matching(command).filter(|_| !session.args.force) else {
+        return false;
+    };
+    eprintln!(
+        "{} Not running this command, it matches the blocklist pattern '{}' (use --force to run it anyway)",
+        color::epaint(color::ERROR, "Error:"),
+        rule
+    );
+    true
+}
 
-    if !response.status().is_success() {
-        let status = response.status();
-        let body = response.text().await?;
-        return Err(format!("API error ({}): {}", status, body).into());
+/// Commands whose risk level has the `type-to-confirm` policy (HIGH, by default) must be confirmed
+/// by typing them out exactly (or "yes"), even with `--yes`, unless they'll run in a sandbox that
+/// can't change anything.
+fn confirm_typed(session: &Session<'_>, command: &str) -> bool {
+    let assessment = risk::assess(command);
+    if session.policies.for_risk(assessment.risk) != Policy::TypeToConfirm || session.sandbox.as_ref().is_some_and(|sandbox| !sandbox.writable()) {
+        return true;
     }
 
-    let result: AnthropicResponse = response.json().await?;
+    let reasons = if assessment.reasons.is_empty() { String::new() } else { format!(" ({})", assessment.reasons.join(", ")) };
+    println!(
+        "{}{}. Type the command exactly, or \"yes\", to run it.",
+        color::paint(color::ERROR, format!("This command is {} risk", assessment.risk.label().to_lowercase())),
+        reasons
+    );
+    let input = read_input("> ");
+    let confirmed = input == "yes" || input == command.trim();
+    if !confirmed {
+        println!("Not running it.");
+    }
+    confirmed
+}
 
-    result
-        .content
-        .first()
-        .and_then(|block| block.text.clone())
-        .ok_or_else(|| "No response from Claude".into())
+/// Whether `--yes` lets the command run without the confirmation menu, which it doesn't
+/// when the command's risk level has the `confirm` policy
+fn skips_confirmation(session: &Session<'_>, command: &str) -> bool {
+    if !session.args.yes {
+        return false;
+    }
+    let risk = risk::assess(command).risk;
+    if session.policies.for_risk(risk) == Policy::Confirm {
+        note(format!("(asking anyway, since confirm.{} is \"confirm\")", risk.label().to_lowercase()));
+        return false;
+    }
+    true
 }
 
 enum PromptResponse {
     Yes,
     No,
-    Edit(String),
+    Edit,
+    Copy,
+    /// Re-query the model, optionally noting what was wrong with the last suggestion
+    Regenerate(Option<String>),
+    Explain,
+    /// Save the command to the favorites
+    Favorite,
 }
 
-fn prompt_yes_no_edit(prompt: &str) -> PromptResponse {
-    print!("{} [y/N/e]: ", prompt);
-    io::stdout().flush().unwrap();
+/// The confirmation menu's keys and how each is shown
+const MENU: &[(char, &str)] = &[
+    ('y', "[y]es"),
+    ('n', "[n]o"),
+    ('e', "[e]dit"),
+    ('r', "[r]egenerate"),
+    ('c', "[c]opy"),
+    ('x', "e[x]plain"),
+    ('s', "[s]ave as favorite"),
+];
+
+/// The menu key Enter picks, from `default_action`
+fn default_action_key(action: Option<&str>) -> Result<char, Box<dyn std::error::Error>> {
+    match action.unwrap_or("no") {
+        "no" => Ok('n'),
+        "yes" => Ok('y'),
+        "edit" => Ok('e'),
+        "copy" => Ok('c'),
+        "explain" => Ok('x'),
+        other => Err(format!("Unknown default_action '{}' (expected no, yes, edit, copy, or explain)", other).into()),
+    }
+}
 
-    let mut input = String::new();
-    io::stdin().read_line(&mut input).unwrap();
+/// Show the confirmation menu and act on a single key press, with Enter choosing `default`
+/// (shown in capitals). Other keys are ignored; end of input means no.
+fn prompt_action(prompt: &str, default: char) -> PromptResponse {
+    let choices: Vec<String> = MENU
+        .iter()
+        .map(|(key, label)| if *key == default { label.replace(&format!("[{}]", key), &format!("[{}]", key.to_ascii_uppercase())) } else { label.to_string() })
+        .collect();
+    print!("{} {}: ", prompt, choices.join(", "));
+    io::stdout().flush().unwrap();
 
-    match input.trim().to_lowercase().as_str() {
-        "y" | "yes" => PromptResponse::Yes,
-        "e" | "edit" => {
-            print!("Edit instructions: ");
-            io::stdout().flush().unwrap();
-            let mut edit_input = String::new();
-            io::stdin().read_line(&mut edit_input).unwrap();
-            PromptResponse::Edit(edit_input.trim().to_string())
+    let key = loop {
+        let key = match terminal::read_key() {
+            Some('\n') => default,
+            Some(key) => key.to_ascii_lowercase(),
+            None => 'n',
+        };
+        if MENU.iter().any(|(choice, _)| *choice == key) {
+            break key;
+        }
+    };
+    println!("{}", key);
+
+    match key {
+        'y' => PromptResponse::Yes,
+        'e' => PromptResponse::Edit,
+        'c' => PromptResponse::Copy,
+        'x' => PromptResponse::Explain,
+        's' => PromptResponse::Favorite,
+        'r' => {
+            let note = read_input("What was wrong? (optional, Enter to just regenerate): ");
+            PromptResponse::Regenerate((!note.is_empty()).then_some(note))
         }
         _ => PromptResponse::No,
     }
 }
 
-fn execute_command(command: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let shell = std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string());
+/// Open the command in `$VISUAL`/`$EDITOR` (default: vi, or notepad on Windows) and return the edited version.
+fn edit_in_editor(command: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let extension = if cfg!(windows) { "ps1" } else { "sh" };
+    let path = std::env::temp_dir().join(format!("llm-exec-{}.{}", std::process::id(), extension));
+    std::fs::write(&path, format!("{}\n", command))?;
+
+    let status = open_in_editor(&path);
+    let edited = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+
+    status?;
+    Ok(edited?.trim().to_string())
+}
 
-    let status = Command::new(&shell)
-        .arg("-i")
-        .arg("-c")
-        .arg(command)
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .status()?;
+/// Open a file in `$VISUAL`/`$EDITOR` (default: vi, or notepad on Windows) and wait for it to close.
+fn open_in_editor(path: &std::path::Path) -> Result<(), Box<dyn std::error::Error>> {
+    let default_editor = if cfg!(windows) { "notepad" } else { "vi" };
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| default_editor.to_string());
+
+    // Run through the system shell so editors configured with arguments (e.g. "code --wait") work
+    let status = if cfg!(windows) {
+        Command::new("cmd")
+            .arg("/C")
+            .arg(format!("{} \"{}\"", editor, path.display()))
+            .status()?
+    } else {
+        Command::new("/bin/sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", editor))
+            .arg("sh")
+            .arg(path)
+            .status()?
+    };
 
     if !status.success() {
-        if let Some(code) = status.code() {
-            std::process::exit(code);
-        }
+        return Err(format!("{} exited with an error", editor).into());
     }
-
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
-    let prompt = if args.prompt.is_empty() {
-        eprint!("What do you want to do? ");
-        io::stdout().flush().unwrap();
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
-        input.trim().to_string()
+/// Add the command to shell history, then execute it.
+/// Returns its exit status and the end of its output, if it's needed to fix or summarize the command.
+fn run_command(session: &Session<'_>, command: &str) -> Result<(ExitStatus, Captured), Box<dyn std::error::Error>> {
+    if let Some(sandbox) = &session.sandbox {
+        note(format!("(running in a throwaway {} container)", sandbox.image()));
+        return Ok((sandbox.run(command, terminal_stdin())?, Captured::default()));
+    }
+
+    // Add to shell history before execution so it's available even if command fails
+    let entry = session.history.append(command).map_err(|e| eprintln!("Warning: Could not add to history: {}", e)).ok().flatten();
+    let capture_stdout = session.args.summarize || session.args.agent;
+    let (status, output) = if can_fix(session) || capture_stdout {
+        let (status, mut output) = execute::execute_capturing(command, &session.shell, terminal_stdin, capture_stdout)?;
+        // The output is only captured to send to the model
+        if let Some(redactor) = &session.redactor {
+            output.stdout = redactor.redact(&output.stdout);
+            output.stderr = redactor.redact(&output.stderr);
+        }
+        (status, output)
     } else {
-        args.prompt.join(" ")
+        (execute::execute(command, &session.shell, terminal_stdin)?, Captured::default())
     };
+    if let Some(Err(e)) = entry.map(|id| session.history.finish(&id, status.code())) {
+        eprintln!("Warning: Could not record the command's exit status in history: {}", e);
+    }
+    Ok((status, output))
+}
 
-    if prompt.is_empty() {
-        eprintln!("Error: No prompt provided");
-        std::process::exit(1);
+/// Whether a failed command can be sent back to the model to fix, which needs someone to ask
+fn can_fix(session: &Session<'_>) -> bool {
+    session.max_fix_attempts > 0 && !session.args.yes
+}
+
+/// Ask whether to have the model fix a command that failed, returning the request to send it if so
+fn offer_fix(command: &str, status: ExitStatus, stderr: &str) -> Option<String> {
+    let code = status.code().filter(|_| !status.success())?;
+    println!();
+    let answer = read_input(&format!("{} [f]ix it? [f/N]: ", color::paint(color::ERROR, format!("Command failed (exit status {}).", code))));
+    if !matches!(answer.to_lowercase().as_str(), "f" | "fix") {
+        return None;
     }
 
-    // Load config
-    let config = load_config();
+    let mut request = format!("The command failed with exit status {}:\n\n{}\n\n", code, command);
+    if !stderr.trim().is_empty() {
+        request.push_str(&format!("Its error output was:\n\n{}\n\n", stderr.trim_end()));
+    }
+    request.push_str("Suggest a corrected command that does what I intended.");
+    Some(request)
+}
 
-    // CLI overrides config, config overrides defaults
-    let history_lines = args
-        .history_lines
-        .or(config.history_lines)
-        .unwrap_or(DEFAULT_HISTORY_LINES);
+/// Stdin for executed commands: the terminal, since piped stdin has already been read as context
+fn terminal_stdin() -> Stdio {
+    if io::stdin().is_terminal() {
+        return Stdio::inherit();
+    }
+    terminal::open().map(Stdio::from).unwrap_or_else(|_| Stdio::inherit())
+}
 
-    // Get shell history
-    let history = match get_shell_history(history_lines) {
-        Ok(h) => h,
-        Err(e) => {
-            eprintln!("Warning: Could not read shell history: {}", e);
-            String::new()
-        }
-    };
+fn copy_command(command: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let method = clipboard::copy(command)?;
+    println!("Copied to clipboard (via {}).", method);
+    Ok(())
+}
 
-    // Load context files from cwd and parent directories
-    let context_filenames: Vec<String> = config
-        .context_files
-        .clone()
-        .unwrap_or_else(|| DEFAULT_CONTEXT_FILES.iter().map(|s| s.to_string()).collect());
-    let context_files = load_context_files(&context_filenames);
+/// What happened to a suggestion once it was presented
+enum Outcome {
+    /// The user ran this command (possibly after editing it), with the end of its output if captured
+    Executed(String, ExitStatus, Captured),
+    /// The user copied this command to the clipboard instead of running it
+    Copied(String),
+    /// The user declined to run anything
+    Cancelled,
+    /// The model could not produce a command
+    Refused,
+    /// The command matches the blocklist or isn't in the allowlist, so it wasn't run
+    Blocked,
+}
 
-    // Get argv[0] (the command name used to invoke this program)
-    let argv0 = std::env::args()
-        .next()
-        .and_then(|p| {
-            std::path::Path::new(&p)
-                .file_name()
-                .map(|s| s.to_string_lossy().into_owned())
-        })
-        .unwrap_or_else(|| "llm-exec".to_string());
+/// Everything needed to turn prompts into executed commands
+struct Session<'a> {
+    provider: usage::Metered,
+    /// Where commands are added to the shell history
+    history: Box<dyn HistoryBackend>,
+    /// The models `--compare` asks, each with the name it was given as
+    compared: Vec<(String, usage::Metered)>,
+    system_prompt: SystemPrompt,
+    args: &'a Args,
+    /// Shell that commands are generated for and run with
+    shell: String,
+    blocklist: risk::Blocklist,
+    /// Set when execution is limited to allowlisted commands
+    allowlist: Option<risk::Allowlist>,
+    /// Set when `--sandbox` runs commands in a container
+    sandbox: Option<sandbox::Sandbox>,
+    /// Whether to record suggestions in the audit log
+    audit_log: bool,
+    /// Unset with `--no-cache` or `cache_ttl_secs = 0`
+    response_cache: Option<response_cache::ResponseCache>,
+    /// Times a failed command can be sent back to the model to fix, per prompt
+    max_fix_attempts: u32,
+    /// The confirmation menu key Enter picks
+    default_action: char,
+    /// How commands of each risk level are confirmed
+    policies: risk::Policies,
+    /// Redacts secrets in command output sent to the model, unless `redact` is off
+    redactor: Option<redact::Redactor>,
+    /// Whether to ask for suggestions through the `suggest_command` tool
+    structured_output: bool,
+}
 
-    // Dry run mode - show what would be sent
-    if args.dry_run {
-        let model = config.model.as_deref().unwrap_or(DEFAULT_MODEL);
-        let base_prompt = config.system_prompt.as_deref().unwrap_or(DEFAULT_SYSTEM_PROMPT).replace("{}", &argv0);
+/// A recent response to the same conversation, if there is one
+fn cached_response(session: &Session<'_>, conversation: &Conversation) -> Option<String> {
+    let text = session.response_cache.as_ref()?.get(conversation.turns())?;
+    enote("(reusing the response to the same request; use --no-cache to ask again)");
+    Some(text)
+}
 
-        let mut system_prompt = base_prompt;
+/// Cache the response to the conversation, unless the model couldn't suggest anything
+fn cache_response(session: &Session<'_>, conversation: &Conversation, text: &str) {
+    if let Some(cache) = session.response_cache.as_ref().filter(|_| !text.trim().starts_with(ERROR_SIGIL)) {
+        cache.put(conversation.turns(), text);
+    }
+}
 
-        if !context_files.is_empty() {
-            system_prompt.push_str("\n\n");
-            system_prompt.push_str(&context_files);
+/// Like `suggest_command`, but reusing the response to an identical recent request unless `refresh`
+async fn suggest_command_cached(session: &Session<'_>, conversation: &Conversation, refresh: bool) -> Result<(String, bool), Box<dyn std::error::Error>> {
+    if let Some(text) = (!refresh).then(|| cached_response(session, conversation)).flatten() {
+        return Ok((prompt::normalize_response(&text), false));
+    }
+    if session.structured_output {
+        show_thinking();
+        let structured = suggest_structured(&session.provider, &session.system_prompt, conversation.turns()).await;
+        clear_thinking();
+        if let Some(structured) = structured? {
+            let text = structured.text();
+            if let Some(fixed) = fix_syntax(session, conversation, &text).await? {
+                cache_response(session, conversation, &fixed);
+                return Ok((fixed, false));
+            }
+            let shown = show_structured(&structured);
+            cache_response(session, conversation, &text);
+            return Ok((text, shown));
+        }
+    }
+    let (text, shown) = suggest_command(&session.provider, &session.system_prompt, conversation).await?;
+    let normalized = prompt::normalize_response(&text);
+    // Show the command again if what was streamed had to be cleaned up or fixed
+    let mut shown = shown && split_undo(&normalized).0 == split_undo(&text).0;
+    let text = match fix_syntax(session, conversation, &normalized).await? {
+        Some(fixed) => {
+            shown = false;
+            fixed
         }
+        None => normalized,
+    };
+    cache_response(session, conversation, &text);
+    Ok((text, shown))
+}
 
-        system_prompt.push_str("\n\nThe user's recent shell history:\n");
-        system_prompt.push_str(&history);
+/// If the suggested command isn't valid syntax for the shell, ask the model once to fix it.
+/// Returns the fixed suggestion, or `None` if there was nothing to fix or the fix isn't valid either.
+async fn fix_syntax(session: &Session<'_>, conversation: &Conversation, text: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let (command, _) = split_undo(text);
+    if prompt::refusal(&command).is_some() {
+        return Ok(None);
+    }
+    let Some(error) = execute::check_syntax(&command, &session.shell) else {
+        return Ok(None);
+    };
 
-        if let Some(suffix) = &config.system_prompt_suffix {
-            system_prompt.push_str("\n\n");
-            system_prompt.push_str(suffix);
-        }
+    let shell = shell_name(&session.shell);
+    enote(format!("(the suggestion isn't valid {} syntax, so the model is fixing it...)", shell));
+    let mut fixing = Conversation::from_turns(conversation.turns().to_vec());
+    fixing.push(Role::Assistant, text);
+    fixing.push(Role::User, format!("That isn't valid {} syntax: {}\nReply with the corrected command only.", shell, error));
+    let fixed = session.provider.complete(&session.system_prompt, fixing.turns()).await?;
+
+    let fixed = prompt::normalize_response(&fixed.text);
+    let (fixed_command, _) = split_undo(&fixed);
+    if prompt::refusal(&fixed_command).is_some() || execute::check_syntax(&fixed_command, &session.shell).is_some() {
+        return Ok(None);
+    }
+    Ok(Some(fixed))
+}
 
-        println!("\x1b[1;36mModel:\x1b[0m {}", model);
-        println!();
-        println!("\x1b[1;36mSystem prompt:\x1b[0m");
-        println!("{}", system_prompt);
-        println!();
-        println!("\x1b[1;36mUser prompt:\x1b[0m {}", prompt);
-        return Ok(());
+/// Present a suggestion made through the `suggest_command` tool with the model's explanation,
+/// and whether it has placeholders to fill in or the model rates it riskier than it looks.
+/// Returns whether the command was shown (it isn't if the model couldn't help).
+fn show_structured(structured: &StructuredSuggestion) -> bool {
+    let command = structured.command.trim();
+    if command.is_empty() {
+        return false;
     }
 
-    // Call Claude
-    eprint!("Thinking...");
-    let mut suggested_command = call_claude(&prompt, &history, &context_files, &config, &argv0).await?;
-    eprintln!("\r           \r"); // Clear "Thinking..."
+    heading("Suggested command:");
+    println!("  {}", color::paint(color::COMMAND, command.replace('\n', "\n  ")));
+    if !structured.explanation.trim().is_empty() {
+        println!("  {}", color::paint(color::DIM, structured.explanation.trim()));
+    }
+    println!();
 
-    // Track conversation for edits
-    let mut messages: Vec<(String, String)> = vec![
-        ("user".to_string(), prompt.clone()),
-        ("assistant".to_string(), suggested_command.clone()),
-    ];
+    if structured.needs_placeholder {
+        eprintln!("{} This command has placeholders to fill in; edit it before running it", color::epaint(color::WARNING, "Note:"));
+        eprintln!();
+    }
+    if let Some(rated) = structured.risk.filter(|&rated| rated > risk::assess(command).risk) {
+        eprintln!("{} The model rates this command as {} risk", color::epaint(color::WARNING, "Warning:"), rated.label());
+        eprintln!();
+    }
+    true
+}
 
-    loop {
-        let suggested_command_trimmed = suggested_command.trim();
+/// Get the suggestion without streaming, from the cache if possible, showing "Thinking..."
+/// while waiting for the model if `progress`. Returns whether it was cached.
+async fn complete_suggestion(session: &Session<'_>, conversation: &Conversation, progress: bool) -> Result<(Completion, bool), Box<dyn std::error::Error>> {
+    if let Some(text) = cached_response(session, conversation) {
+        return Ok((Completion { text, ..Completion::default() }, true));
+    }
 
-        // Check if the response is an error sigil from the LLM
-        if let Some(error_msg) = suggested_command_trimmed
-            .strip_prefix("echo \"Error: ")
-            .and_then(|s| s.strip_suffix('"'))
-        {
-            eprintln!("\x1b[1;31mError:\x1b[0m {}", error_msg);
-            std::process::exit(1);
-        }
+    if progress {
+        show_thinking();
+    }
+    let completion = complete_structured_or_text(session, conversation).await;
+    if progress {
+        clear_thinking();
+    }
 
-        // Present the command
-        println!("\x1b[1;36mSuggested command:\x1b[0m");
-        println!("\x1b[1;33m  {}\x1b[0m", suggested_command_trimmed);
-        println!();
+    let completion = completion?;
+    cache_response(session, conversation, &completion.text);
+    Ok((completion, false))
+}
 
-        // Execute (with or without confirmation)
-        if args.yes {
-            // Add to shell history before execution so it's available even if command fails
-            if let Err(e) = append_to_history(suggested_command_trimmed) {
-                eprintln!("Warning: Could not add to history: {}", e);
-            }
-            execute_command(suggested_command_trimmed)?;
-            break;
+/// The suggestion through the `suggest_command` tool with `structured_output`, as the text the model
+/// would otherwise have replied with, or else the model's reply as usual
+async fn complete_structured_or_text(session: &Session<'_>, conversation: &Conversation) -> Result<Completion, Box<dyn std::error::Error>> {
+    if session.structured_output {
+        if let Some(structured) = suggest_structured(&session.provider, &session.system_prompt, conversation.turns()).await? {
+            return Ok(Completion {
+                text: structured.text(),
+                usage: structured.usage,
+                thinking: None,
+            });
         }
+    }
+    session.provider.complete(&session.system_prompt, conversation.turns()).await
+}
 
-        match prompt_yes_no_edit("Execute this command?") {
-            PromptResponse::Yes => {
-                // Add to shell history before execution so it's available even if command fails
-                if let Err(e) = append_to_history(suggested_command_trimmed) {
-                    eprintln!("Warning: Could not add to history: {}", e);
-                }
-                println!();
-                execute_command(suggested_command_trimmed)?;
-                break;
-            }
-            PromptResponse::No => {
-                println!("Cancelled.");
-                break;
-            }
-            PromptResponse::Edit(edit_instructions) => {
-                if edit_instructions.is_empty() {
-                    println!("No edit instructions provided.");
-                    continue;
-                }
+/// Record what happened to a prompt's final suggestion in the audit log
+fn audit_outcome(session: &Session<'_>, prompt: &str, conversation: &Conversation, outcome: &Outcome) {
+    let suggestion = match conversation.turns().last() {
+        Some(turn) if turn.role == Role::Assistant => split_undo(&turn.content).0,
+        _ => String::new(),
+    };
+
+    let (action, command, status) = match outcome {
+        Outcome::Executed(command, status, _) if *command == suggestion => (audit::Action::Executed, None, Some(status)),
+        Outcome::Executed(command, status, _) => (audit::Action::Edited, Some(command.clone()), Some(status)),
+        Outcome::Copied(_) => (audit::Action::Copied, None, None),
+        Outcome::Cancelled => (audit::Action::Cancelled, None, None),
+        Outcome::Refused => (audit::Action::Refused, None, None),
+        Outcome::Blocked => (audit::Action::Blocked, None, None),
+    };
+
+    let mut entry = audit::Entry::new(prompt, session.provider.model(), &suggestion, action);
+    entry.command = command;
+    entry.exit_code = status.and_then(|status| status.code());
+    record_audit(session, &entry);
+}
+
+fn record_audit(session: &Session<'_>, entry: &audit::Entry) {
+    if session.audit_log {
+        audit::append(entry);
+    }
+}
+
+/// The prompt a single-shot conversation started with
+fn first_prompt(conversation: &Conversation) -> &str {
+    conversation.turns().first().map_or("", |turn| turn.content.as_str())
+}
+
+/// Get a suggestion for the conversation so far, present it, and run it once confirmed.
+/// Suggestions are appended as assistant turns. If the conversation already ends with one
+/// (recalled with `--last`), it's presented without asking the model.
+async fn handle_turn(session: &Session<'_>, prompt: &str, conversation: &mut Conversation) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let provider: &dyn Provider = &session.provider;
+    let system_prompt = &session.system_prompt;
+    let args = session.args;
+    let shell = session.shell.as_str();
+
+    let (mut suggested_command, mut shown) = match args.candidates {
+        _ if conversation.last_role() == Some(Role::Assistant) => {
+            let recalled = conversation.pop().map(|turn| turn.content).unwrap_or_default();
+            (recalled, false)
+        }
+        Some(count) if count > 1 => match choose_candidate(provider, system_prompt, conversation, count).await? {
+            Some(command) => (command, false),
+            None => {
+                println!("Cancelled.");
+                // Nothing was suggested, so drop the unanswered prompt
+                conversation.pop();
+                return Ok(Outcome::Cancelled);
+            }
+        },
+        _ if !session.compared.is_empty() => match compare_models(session, conversation).await? {
+            Some(command) => (command, false),
+            None => {
+                println!("Cancelled.");
+                conversation.pop();
+                return Ok(Outcome::Cancelled);
+            }
+        },
+        _ if args.offline => match search_history(session, prompt)? {
+            Some(command) => (command, false),
+            None => {
+                println!("Cancelled.");
+                conversation.pop();
+                return Ok(Outcome::Cancelled);
+            }
+        },
+        _ => match suggest_command_cached(session, conversation, false).await {
+            Ok(suggestion) => suggestion,
+            Err(e) if providers::is_unreachable(e.as_ref()) && io::stdin().is_terminal() => {
+                eprintln!("{} {}", color::epaint(color::ERROR, "Error:"), e);
+                enote("(searching your shell history instead)");
+                match search_history(session, prompt)? {
+                    Some(command) => (command, false),
+                    None => {
+                        println!("Cancelled.");
+                        conversation.pop();
+                        return Ok(Outcome::Cancelled);
+                    }
+                }
+            }
+            Err(e) => return Err(e),
+        },
+    };
+    conversation.push(Role::Assistant, suggested_command.clone());
+
+    loop {
+        // Set when regenerating, which must not get the same response back from the cache
+        let mut refresh = false;
+        let (command, undo) = split_undo(&suggested_command);
+        let suggested_command_trimmed = command.as_str();
+
+        // Check if the response is an error sigil from the LLM
+        if let Some(error_msg) = prompt::refusal(suggested_command_trimmed) {
+            eprintln!("{} {}", color::epaint(color::ERROR, "Error:"), error_msg);
+            return Ok(Outcome::Refused);
+        }
+
+        // Present the command, unless it was just streamed to the terminal
+        if !shown {
+            heading("Suggested command:");
+            println!("  {}", color::paint(color::COMMAND, suggested_command_trimmed));
+            println!();
+        }
+        shown = false;
+
+        if let Some(undo) = &undo {
+            println!("{}", color::paint(color::HEADING, "To undo:"));
+            println!("  {}", color::paint(color::DIM, undo));
+            println!();
+        }
+        session.provider.report();
+
+        if let Some(error) = execute::check_syntax(suggested_command_trimmed, shell) {
+            eprintln!("{} This may not be valid {} syntax: {}", color::epaint(color::WARNING, "Warning:"), shell_name(shell), error);
+            eprintln!();
+        }
+
+        print_risk(&risk::assess(suggested_command_trimmed));
+        if let Some(rule) = session.blocklist.matching(suggested_command_trimmed) {
+            eprintln!("{} This command matches the blocklist pattern '{}'", color::epaint(color::ERROR, "Blocked:"), rule);
+            eprintln!();
+        }
+        if !is_allowed(session, suggested_command_trimmed) {
+            eprintln!("{} This command isn't in the allowlist, so it can be copied but not run", color::epaint(color::WARNING, "Not allowed:"));
+            eprintln!();
+        }
+
+        if args.explain {
+            if let Err(e) = explain_command(provider, suggested_command_trimmed).await {
+                eprintln!("Warning: Could not explain command: {}", e);
+            }
+            session.provider.report();
+        }
+
+        if args.copy {
+            copy_command(suggested_command_trimmed)?;
+            return Ok(Outcome::Copied(suggested_command_trimmed.to_string()));
+        }
+
+        // Execute (with or without confirmation)
+        if skips_confirmation(session, suggested_command_trimmed) {
+            if is_blocked(session, suggested_command_trimmed) {
+                return Ok(Outcome::Blocked);
+            }
+            if !confirm_typed(session, suggested_command_trimmed) {
+                return Ok(Outcome::Cancelled);
+            }
+            let (status, output) = run_command(session, suggested_command_trimmed)?;
+            return Ok(Outcome::Executed(suggested_command_trimmed.to_string(), status, output));
+        }
+
+        let response = loop {
+            match prompt_action("Execute this command?", session.default_action) {
+                PromptResponse::Explain => {
+                    if let Err(e) = explain_command(provider, suggested_command_trimmed).await {
+                        eprintln!("Warning: Could not explain command: {}", e);
+                    }
+                    session.provider.report();
+                }
+                PromptResponse::Favorite => {
+                    let favorite = favorites::Favorite {
+                        prompt: prompt.to_string(),
+                        command: suggested_command_trimmed.to_string(),
+                    };
+                    match favorites::add(&favorite) {
+                        Ok(()) => println!("Saved to favorites (see llm-exec favorites)."),
+                        Err(e) => eprintln!("Error: Could not save to favorites: {}", e),
+                    }
+                }
+                response => break response,
+            }
+        };
+
+        match response {
+            PromptResponse::Yes => {
+                if is_blocked(session, suggested_command_trimmed) || !confirm_typed(session, suggested_command_trimmed) {
+                    continue;
+                }
+                println!();
+                let (status, output) = run_command(session, suggested_command_trimmed)?;
+                return Ok(Outcome::Executed(suggested_command_trimmed.to_string(), status, output));
+            }
+            PromptResponse::No => {
+                let feedback = read_input("What should be different? (Enter to cancel): ");
+                if feedback.is_empty() {
+                    println!("Cancelled.");
+                    return Ok(Outcome::Cancelled);
+                }
+
+                // Send the feedback back so the model can refine its rejected suggestion
+                conversation.push(Role::User, feedback);
+            }
+            PromptResponse::Edit => {
+                let edited = match edit_in_editor(suggested_command_trimmed) {
+                    Ok(edited) => edited,
+                    Err(e) => {
+                        eprintln!("Error: Could not edit command: {}", e);
+                        continue;
+                    }
+                };
+
+                if edited.is_empty() {
+                    println!("Cancelled.");
+                    return Ok(Outcome::Cancelled);
+                }
+
+                if is_blocked(session, &edited) {
+                    return Ok(Outcome::Blocked);
+                }
+                if !confirm_typed(session, &edited) {
+                    return Ok(Outcome::Cancelled);
+                }
+                println!();
+                let (status, output) = run_command(session, &edited)?;
+                return Ok(Outcome::Executed(edited, status, output));
+            }
+            PromptResponse::Copy => match copy_command(suggested_command_trimmed) {
+                Ok(()) => return Ok(Outcome::Copied(suggested_command_trimmed.to_string())),
+                Err(e) => {
+                    eprintln!("Error: Could not copy command: {}", e);
+                    continue;
+                }
+            },
+            PromptResponse::Regenerate(note) => match note {
+                // Explain what was wrong with the previous suggestion
+                Some(note) => conversation.push(Role::User, note),
+                // Drop the previous suggestion and ask again for a fresh one
+                None => {
+                    conversation.pop();
+                    refresh = true;
+                }
+            },
+            PromptResponse::Explain | PromptResponse::Favorite => unreachable!("handled above"),
+        }
+
+        // Call the model with full conversation
+        (suggested_command, shown) = suggest_command_cached(session, conversation, refresh).await?;
+
+        // Add response to conversation
+        conversation.push(Role::Assistant, suggested_command.clone());
+    }
+}
+
+/// Handle a turn and record it in the audit log. If the command fails, offer to send it back
+/// to the model to fix, up to `max_fix_attempts` times.
+async fn handle_turn_with_fixes(session: &Session<'_>, prompt: &str, conversation: &mut Conversation) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut attempts = 0;
+    loop {
+        let outcome = handle_turn(session, prompt, conversation).await?;
+        audit_outcome(session, prompt, conversation, &outcome);
+
+        if let Outcome::Executed(command, status, output) = &outcome {
+            if session.args.summarize {
+                if let Err(e) = summarize_output(session, prompt, command, *status, output).await {
+                    eprintln!("Warning: Could not summarize the output: {}", e);
+                }
+            }
+            if can_fix(session) && attempts < session.max_fix_attempts {
+                if let Some(request) = offer_fix(command, *status, &output.stderr) {
+                    attempts += 1;
+                    conversation.push(Role::User, request);
+                    continue;
+                }
+            }
+        }
+        return Ok(outcome);
+    }
+}
+
+/// `--script`: get a script from the model, let the user review it in their editor, then run it as a file
+async fn run_script(session: &Session<'_>, conversation: &mut Conversation) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let text = complete_suggestion(session, conversation, true).await?.0.text;
+    let suggested = prompt::strip_code_fence(&text).to_string();
+    conversation.push(Role::Assistant, suggested.as_str());
+    session.provider.report();
+
+    let outcome = script_outcome(session, &suggested)?;
+    audit_outcome(session, first_prompt(conversation), conversation, &outcome);
+    Ok(outcome)
+}
+
+fn script_outcome(session: &Session<'_>, suggested: &str) -> Result<Outcome, Box<dyn std::error::Error>> {
+    if let Some(error_msg) = prompt::refusal(suggested) {
+        eprintln!("{} {}", color::epaint(color::ERROR, "Error:"), error_msg);
+        return Ok(Outcome::Refused);
+    }
+
+    heading("Suggested script:");
+    println!("{}", highlight::script(suggested));
+    println!();
+    print_risk(&risk::assess(suggested));
+
+    let extension = if cfg!(windows) { "ps1" } else { "sh" };
+    let path = std::env::temp_dir().join(format!("llm-exec-script-{}.{}", std::process::id(), extension));
+    std::fs::write(&path, format!("{}\n", suggested))?;
+    let outcome = review_and_run_script(session, suggested, &path);
+    let _ = std::fs::remove_file(&path);
+    outcome
+}
+
+/// Open the script in the editor unless `--yes` was given, then run it once confirmed
+fn review_and_run_script(session: &Session<'_>, suggested: &str, path: &std::path::Path) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut script = suggested.to_string();
+    if !session.args.yes {
+        note("(opening the script in your editor for review)");
+        open_in_editor(path)?;
+        script = std::fs::read_to_string(path)?.trim().to_string();
+        if script.is_empty() {
+            println!("Cancelled.");
+            return Ok(Outcome::Cancelled);
+        }
+        if script != suggested {
+            heading("Edited script:");
+            println!("{}", highlight::script(&script));
+            println!();
+        }
+    }
+
+    if is_blocked(session, &script) {
+        return Ok(Outcome::Blocked);
+    }
+    if !confirm_typed(session, &script) {
+        return Ok(Outcome::Cancelled);
+    }
+    if !skips_confirmation(session, &script) && !matches!(read_input("Run this script? [y/N]: ").to_lowercase().as_str(), "y" | "yes") {
+        println!("Cancelled.");
+        return Ok(Outcome::Cancelled);
+    }
+
+    println!();
+    let status = execute::execute_script(path, &session.shell, terminal_stdin)?;
+    Ok(Outcome::Executed(script, status, Captured::default()))
+}
+
+/// Scripting mode: write just the raw command to stdout (all other output goes to stderr)
+async fn print_suggestion(session: &Session<'_>, conversation: &Conversation) -> Result<(), Box<dyn std::error::Error>> {
+    let response = prompt::normalize_response(&complete_suggestion(session, conversation, true).await?.0.text);
+    let response = fix_syntax(session, conversation, &response).await?.unwrap_or(response);
+    let (command, undo) = split_undo(&response);
+    let command = command.as_str();
+
+    session.provider.report();
+    let model = session.provider.model();
+    if let Some(error_msg) = prompt::refusal(command) {
+        record_audit(session, &audit::Entry::new(first_prompt(conversation), model, command, audit::Action::Refused));
+        eprintln!("Error: {}", error_msg);
+        std::process::exit(EXIT_FAILED);
+    }
+
+    if let Some(rule) = session.blocklist.matching(command).filter(|_| !session.args.force) {
+        eprintln!("Error: The suggested command matches the blocklist pattern '{}' (use --force to print it anyway)", rule);
+        std::process::exit(EXIT_BLOCKED);
+    }
+
+    print_risk(&risk::assess(command));
+    if let Some(undo) = undo {
+        eprintln!("To undo: {}", undo);
+    }
+    println!("{}", command);
+    record_audit(session, &audit::Entry::new(first_prompt(conversation), model, command, audit::Action::Printed));
+    Ok(())
+}
+
+/// JSON mode: print the suggestion, optional explanation, and token usage as one object
+async fn print_json_suggestion(session: &Session<'_>, conversation: &Conversation) -> Result<(), Box<dyn std::error::Error>> {
+    let (completion, cached) = complete_suggestion(session, conversation, false).await?;
+    let mut usage = completion.usage.unwrap_or_default();
+    let text = prompt::normalize_response(&completion.text);
+    let text = fix_syntax(session, conversation, &text).await?.unwrap_or(text);
+    let (command, undo) = split_undo(&text);
+
+    let mut output = JsonSuggestion {
+        command: None,
+        error: None,
+        model: session.provider.model().to_string(),
+        explanation: None,
+        undo: None,
+        risk: None,
+        risk_reasons: Vec::new(),
+        blocked_by: None,
+        allowed: None,
+        cached,
+        tokens_in: 0,
+        tokens_out: 0,
+    };
+
+    if let Some(error_msg) = prompt::refusal(&command) {
+        output.error = Some(error_msg.to_string());
+    } else {
+        if session.args.explain {
+            let mut explain_conversation = Conversation::new();
+            explain_conversation.push(Role::User, command.as_str());
+            let explanation = session.provider.complete(&EXPLAIN_SYSTEM_PROMPT.into(), explain_conversation.turns()).await?;
+            usage += explanation.usage.unwrap_or_default();
+            output.explanation = Some(explanation.text.trim().to_string());
+        }
+        let assessment = risk::assess(&command);
+        output.risk = Some(assessment.risk);
+        output.risk_reasons = assessment.reasons;
+        output.blocked_by = session.blocklist.matching(&command).map(str::to_string);
+        output.allowed = session.allowlist.as_ref().map(|allowlist| allowlist.permits(&command));
+        output.undo = undo;
+        output.command = Some(command);
+    }
+
+    output.tokens_in = usage.input_tokens;
+    output.tokens_out = usage.output_tokens;
+    println!("{}", serde_json::to_string(&output)?);
+    session.provider.report();
+
+    let action = if output.error.is_some() { audit::Action::Refused } else { audit::Action::Printed };
+    let suggestion = output.command.as_deref().unwrap_or(completion.text.trim());
+    record_audit(session, &audit::Entry::new(first_prompt(conversation), &output.model, suggestion, action));
+
+    if output.error.is_some() {
+        std::process::exit(EXIT_FAILED);
+    }
+    Ok(())
+}
 
-                // Add edit instructions to conversation
-                messages.push(("user".to_string(), edit_instructions));
+/// What happened to the model's last suggestion, to tell it along with the next request
+fn outcome_note(outcome: &Outcome, conversation: &Conversation) -> Option<String> {
+    match outcome {
+        Outcome::Executed(command, status, output) => {
+            let description = status.code().map_or_else(|| "was terminated by a signal".to_string(), |code| format!("exited with status {}", code));
+            let mut note = format!("(I ran `{}` and it {}.", command, description);
+            if !status.success() && !output.stderr.trim().is_empty() {
+                note.push_str(&format!(" Its error output ended with:\n{}\n", output.stderr.trim_end()));
+            }
+            note.push(')');
+            Some(note)
+        }
+        Outcome::Copied(command) => Some(format!("(I copied `{}` to the clipboard instead of running it.)", command)),
+        Outcome::Cancelled if conversation.last_role() == Some(Role::Assistant) => Some("(I decided not to run that command.)".to_string()),
+        Outcome::Blocked => Some("(That command isn't allowed here, so I couldn't run it.)".to_string()),
+        Outcome::Cancelled | Outcome::Refused => None,
+    }
+}
 
-                // Call Claude with full conversation
-                eprint!("Thinking...");
-                suggested_command = call_claude_with_messages(&messages, &history, &context_files, &config, &argv0).await?;
-                eprintln!("\r           \r"); // Clear "Thinking..."
+/// `--plan`: ask the model for the steps of the task, then suggest and run a command for each in turn,
+/// telling the model how each step went. Returns what happened to the last step attempted.
+async fn run_plan(session: &Session<'_>, plan_system_prompt: &SystemPrompt, task: &str) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut planning = Conversation::new();
+    planning.push(Role::User, task);
+    eprint!("Planning...");
+    io::stderr().flush().unwrap();
+    let plan = session.provider.complete(plan_system_prompt, planning.turns()).await;
+    eprint!("\r           \r"); // Clear "Planning..."
+    session.provider.report();
+
+    let steps = prompt::plan_steps(&plan?.text);
+    if steps.is_empty() {
+        return Err("The model didn't suggest any steps".into());
+    }
+    let plan: Vec<String> = steps.iter().enumerate().map(|(i, step)| format!("{}. {}", i + 1, step)).collect();
+    heading("Plan:");
+    for step in &plan {
+        println!("  {}", step);
+    }
+    println!();
+    if !session.args.yes && read_input("Go ahead? [Y/n]: ").to_lowercase().starts_with('n') {
+        println!("Cancelled.");
+        return Ok(Outcome::Cancelled);
+    }
 
-                // Add response to conversation
-                messages.push(("assistant".to_string(), suggested_command.clone()));
+    let mut conversation = Conversation::new();
+    let mut previous: Option<String> = None;
+    let mut outcome = Outcome::Cancelled;
+    for (i, step) in steps.iter().enumerate() {
+        println!();
+        println!("{} {}", color::paint(color::HEADING, format!("Step {}/{}:", i + 1, steps.len())), step);
+        let request = match previous.take() {
+            _ if i == 0 => format!("{}\n\nWe'll do this in steps:\n{}\n\nSuggest the command for step 1: {}", task, plan.join("\n"), step),
+            Some(note) => format!("{}\n\nNow suggest the command for step {}: {}", note, i + 1, step),
+            None => format!("Now suggest the command for step {}: {}", i + 1, step),
+        };
+        conversation.push(Role::User, request);
+
+        outcome = handle_turn_with_fixes(session, &format!("{} (step {}: {})", task, i + 1, step), &mut conversation).await?;
+        let succeeded = matches!(&outcome, Outcome::Executed(_, status, _) if status.success());
+        if matches!(outcome, Outcome::Refused) || i + 1 == steps.len() {
+            break;
+        }
+        if !succeeded && (session.args.yes || !matches!(read_input("Continue with the next step? [y/N]: ").to_lowercase().as_str(), "y" | "yes")) {
+            break;
+        }
+        previous = outcome_note(&outcome, &conversation);
+    }
+
+    Ok(outcome)
+}
+
+/// `--agent`: let the model run commands one at a time, showing it each one's output, until it says
+/// the task is done or `agent.max_steps` commands have run. Each command is confirmed unless it's
+/// LOW risk and `agent.auto_approve_low_risk` is set. Returns what happened to the last command.
+async fn run_agent(session: &Session<'_>, agent: &AgentConfig, conversation: &mut Conversation) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let max_steps = agent.max_steps.unwrap_or(DEFAULT_AGENT_MAX_STEPS);
+    let task = first_prompt(conversation).to_string();
+    let mut outcome = Outcome::Cancelled;
+
+    for step in 1..=max_steps {
+        show_thinking();
+        let completion = session.provider.complete(&session.system_prompt, conversation.turns()).await;
+        clear_thinking();
+        let completion = completion?;
+        session.provider.report();
+
+        // The summary is prose, so it's checked for before the reply is cleaned up as a command
+        if let Some(summary) = prompt::strip_code_fence(&completion.text).strip_prefix(prompt::DONE_MARKER) {
+            conversation.push(Role::Assistant, completion.text.as_str());
+            println!("{} {}", color::paint(color::HEADING, "Done:"), summary.trim());
+            return Ok(outcome);
+        }
+        let text = prompt::normalize_response(&completion.text);
+        conversation.push(Role::Assistant, text.as_str());
+        let (command, undo) = split_undo(&text);
+        if let Some(error_msg) = prompt::refusal(&command) {
+            eprintln!("{} {}", color::epaint(color::ERROR, "Error:"), error_msg);
+            outcome = Outcome::Refused;
+            audit_outcome(session, &task, conversation, &outcome);
+            return Ok(outcome);
+        }
+
+        println!("{}", color::paint(color::HEADING, format!("Step {}/{}:", step, max_steps)));
+        println!("  {}", color::paint(color::COMMAND, &command));
+        if let Some(undo) = &undo {
+            println!("  {}", color::paint(color::DIM, format!("To undo: {}", undo)));
+        }
+        println!();
+        let assessment = risk::assess(&command);
+        print_risk(&assessment);
+
+        let allowed = is_allowed(session, &command) && session.blocklist.matching(&command).is_none();
+        if agent.auto_approve_low_risk.unwrap_or(false) && assessment.risk == Risk::Low && allowed {
+            note("(running it without asking, since it's LOW risk)");
+        } else {
+            let answer = read_input("Run it? [y/N, or say what to do instead]: ");
+            match answer.to_lowercase().as_str() {
+                "y" | "yes" => {}
+                "" | "n" | "no" => {
+                    println!("Stopped.");
+                    outcome = Outcome::Cancelled;
+                    audit_outcome(session, &task, conversation, &outcome);
+                    return Ok(outcome);
+                }
+                _ => {
+                    conversation.push(Role::User, answer);
+                    continue;
+                }
             }
         }
+
+        if is_blocked(session, &command) {
+            conversation.push(Role::User, "(That command isn't allowed here, so it wasn't run. Find another way, or finish.)");
+            continue;
+        }
+        if !confirm_typed(session, &command) {
+            outcome = Outcome::Cancelled;
+            audit_outcome(session, &task, conversation, &outcome);
+            return Ok(outcome);
+        }
+
+        println!();
+        let (status, output) = run_command(session, &command)?;
+        println!();
+        let observation = describe_output(status, &output);
+        outcome = Outcome::Executed(command, status, output);
+        audit_outcome(session, &task, conversation, &outcome);
+        conversation.push(Role::User, observation);
+    }
+
+    println!("Stopped at the limit of {} steps without finishing (raise it with --max-steps).", max_steps);
+    Ok(outcome)
+}
+
+/// The context for `prompt`, with the history summarized if `history_summary_model` is set,
+/// noting what's included unless it's a dry run or offline
+async fn gather_context(config: Config, prompt: &str, piped_input: Option<context::Section>, args: &Args) -> Result<SuggestContext, Box<dyn std::error::Error>> {
+    let mut context = SuggestContext::gather(config, prompt, piped_input)?;
+    if !args.dry_run && !args.offline && context.config.history_summary_model.is_some() {
+        show_thinking();
+        let summarized = context.summarize_history().await;
+        clear_thinking();
+        if let Err(e) = summarized {
+            eprintln!("Warning: Could not summarize shell history: {}", e);
+        }
+    }
+    if !args.dry_run && !args.offline {
+        let included = context.included();
+        let included = if included.is_empty() { "none".to_string() } else { included.join(", ") };
+        enote(format!("(context sent: {})", included));
+    }
+    Ok(context)
+}
+
+/// Add the instructions for `--script` and `--agent` to `system_prompt`
+fn push_mode_instructions(system_prompt: &mut SystemPrompt, args: &Args) {
+    if args.script {
+        system_prompt.push(prompt::SCRIPT_INSTRUCTIONS, false);
+    }
+    if args.agent {
+        system_prompt.push(prompt::AGENT_INSTRUCTIONS, false);
+    }
+}
+
+/// The tasks in a `llm-exec batch` file, skipping blank lines and comments
+fn read_tasks(path: &std::path::Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let tasks: Vec<String> = text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).map(str::to_string).collect();
+    if tasks.is_empty() {
+        return Err(format!("There are no tasks in {}", path.display()).into());
+    }
+    Ok(tasks)
+}
+
+/// `llm-exec batch`: handle each task as its own prompt, stopping if a command fails, then print
+/// a table of what happened to each. Returns whether every task's command ran successfully.
+async fn run_batch(session: &mut Session<'_>, tasks: &[String], config: &Config, piped_input: Option<context::Section>, argv0: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut results: Vec<(String, String)> = Vec::new();
+    for (i, task) in tasks.iter().enumerate() {
+        println!("{} {}", color::paint(color::HEADING, format!("Task {}/{}:", i + 1, tasks.len())), task);
+        let context = gather_context(config.clone(), task, piped_input.clone(), session.args).await?;
+        session.system_prompt = context.system_prompt(argv0);
+        push_mode_instructions(&mut session.system_prompt, session.args);
+        let mut conversation = Conversation::new();
+        conversation.push(Role::User, task.as_str());
+        let outcome = handle_turn_with_fixes(session, task, &mut conversation).await?;
+        println!();
+
+        let suggestion = match conversation.turns().last() {
+            Some(turn) if turn.role == Role::Assistant => split_undo(&turn.content).0,
+            _ => String::new(),
+        };
+        let (result, command) = match outcome {
+            Outcome::Executed(command, status, _) if status.success() => ("ok".to_string(), command),
+            Outcome::Executed(command, status, _) => (status.code().map_or_else(|| "killed".to_string(), |code| format!("exit {}", code)), command),
+            Outcome::Copied(command) => ("copied".to_string(), command),
+            Outcome::Cancelled => ("declined".to_string(), suggestion),
+            Outcome::Refused => ("no command".to_string(), String::new()),
+            Outcome::Blocked => ("blocked".to_string(), suggestion),
+        };
+        let failed = result.starts_with("exit") || result == "killed";
+        results.push((result, command));
+        if failed {
+            break;
+        }
+    }
+
+    let rows: Vec<[String; 4]> = tasks
+        .iter()
+        .enumerate()
+        .map(|(i, task)| {
+            let (result, command) = results.get(i).cloned().unwrap_or_else(|| ("skipped".to_string(), String::new()));
+            [(i + 1).to_string(), task.clone(), result, command.replace('\n', "; ")]
+        })
+        .collect();
+    let header = ["#", "Task", "Result", "Command"].map(str::to_string);
+    let widths: Vec<usize> = (0..3).map(|column| rows.iter().chain([&header]).map(|row| row[column].chars().count()).max().unwrap_or(0)).collect();
+    heading("Summary:");
+    for (i, row) in [&header].into_iter().chain(&rows).enumerate() {
+        let line = format!("{:<w0$}  {:<w1$}  {:<w2$}  {}", row[0], row[1], row[2], row[3], w0 = widths[0], w1 = widths[1], w2 = widths[2]);
+        println!("{}", if i == 0 { color::paint(color::BOLD, line.trim_end()) } else { line.trim_end().to_string() });
+    }
+
+    Ok(results.len() == tasks.len() && results.iter().all(|(result, _)| result == "ok" || result == "copied"))
+}
+
+/// Interactive session: each prompt continues the same conversation, and the
+/// model is told what happened to its previous suggestion. The conversation is saved
+/// after each prompt so it can be resumed.
+async fn run_repl(session: &Session<'_>, initial_prompt: Option<String>, mut saved: sessions::SavedSession) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Describe what you want to do. Type \"exit\" or press Ctrl-D to quit.");
+    println!();
+
+    let mut conversation = Conversation::from_turns(std::mem::take(&mut saved.turns));
+    let mut next_prompt = initial_prompt;
+    // What happened to the previous suggestion, reported along with the next prompt
+    let mut last_outcome = saved.last_outcome.take();
+
+    loop {
+        let prompt = match next_prompt.take() {
+            Some(prompt) => prompt,
+            None => {
+                print!("{} ", color::paint(color::SUCCESS, ">"));
+                io::stdout().flush().unwrap();
+                let Some(input) = terminal::read_line() else {
+                    println!();
+                    break;
+                };
+                input.trim().to_string()
+            }
+        };
+
+        if prompt.is_empty() {
+            continue;
+        }
+        if prompt == "exit" || prompt == "quit" {
+            break;
+        }
+
+        let content = match last_outcome.take() {
+            Some(outcome) => format!("{}\n\n{}", outcome, prompt),
+            None => prompt.clone(),
+        };
+        conversation.push(Role::User, content);
+
+        match handle_turn_with_fixes(session, &prompt, &mut conversation).await {
+            Ok(outcome) => last_outcome = outcome_note(&outcome, &conversation),
+            Err(e) => {
+                if e.is::<providers::Interrupted>() {
+                    println!("Cancelled.");
+                } else {
+                    eprintln!("{} {}", color::epaint(color::ERROR, "Error:"), e);
+                }
+                // Drop the unanswered prompt so the conversation stays well-formed
+                if conversation.last_role() == Some(Role::User) {
+                    conversation.pop();
+                }
+            }
+        }
+
+        saved.turns = conversation.turns().to_vec();
+        saved.last_outcome = last_outcome.clone();
+        if !saved.turns.is_empty() {
+            saved.save();
+        }
+        println!();
+    }
+
+    if !saved.turns.is_empty() {
+        note(format!("(continue this session later with --resume {})", saved.id));
+    }
+    Ok(())
+}
+
+/// Whether a history entry is a previous invocation of llm-exec itself
+fn is_own_invocation(command: &str, argv0: &str) -> bool {
+    let program = command.split_whitespace().next().unwrap_or_default();
+    let program = program.rsplit(['/', '\\']).next().unwrap_or(program);
+    program == argv0 || program == "llm-exec"
+}
+
+/// The command for `llm-exec fix` to correct, with its exit status if the shell integration recorded it.
+/// Falls back to the most recent shell history entry.
+fn last_command_to_fix(argv0: &str, history: &dyn HistoryBackend) -> Option<(String, Option<i32>)> {
+    if let Some((status, command)) = shell_integration::last_command().filter(|(_, c)| !is_own_invocation(c, argv0)) {
+        return Some((command, Some(status)));
+    }
+
+    let history = history.recent(10).ok()?;
+    history
+        .iter()
+        .rev()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !is_own_invocation(line, argv0))
+        .map(|line| (line.to_string(), None))
+}
+
+/// The prompt sent by `llm-exec fix`
+fn fix_prompt(command: &str, status: Option<i32>, hint: &str) -> String {
+    let outcome = match status {
+        Some(0) => "ran successfully but did not do what I wanted".to_string(),
+        Some(code) => format!("failed with exit status {}", code),
+        None => "did not do what I wanted".to_string(),
+    };
+
+    let mut prompt = format!(
+        "The command I just ran {}:\n\n{}\n\nSuggest a corrected command that does what I intended. Use any error output in the context to work out what went wrong.",
+        outcome, command
+    );
+    if !hint.is_empty() {
+        prompt.push_str(&format!("\n\nHint: {}", hint));
+    }
+    prompt
+}
+
+/// Config keys set by command-line flags, the highest-precedence config layer
+fn cli_overrides(args: &Args) -> Config {
+    Config {
+        model: args.model.clone(),
+        max_tokens: args.max_tokens,
+        temperature: args.temperature,
+        top_p: args.top_p,
+        top_k: args.top_k,
+        thinking_budget: args.think,
+        history_lines: args.history_lines,
+        timeout_secs: args.timeout,
+        context: ContextConfig {
+            history: args.no_history.then_some(false),
+            last_command: args.no_history.then_some(false),
+            cwd: args.context_cwd.then_some(true),
+            git: args.context_git.then_some(true),
+            tmux: args.context_tmux.then_some(true),
+            files: (!args.file.is_empty()).then(|| args.file.clone()),
+            ..ContextConfig::default()
+        },
+        agent: AgentConfig {
+            max_steps: args.max_steps,
+            ..AgentConfig::default()
+        },
+        ..Config::default()
+    }
+}
+
+/// Add `--system-file` and `--system-suffix` to the end of `system_prompt_suffix`, after any from the config
+fn add_system_suffix(config: &mut Config, args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let mut parts: Vec<String> = config.system_prompt_suffix.take().into_iter().collect();
+    if let Some(path) = &args.system_file {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        parts.push(text.trim().to_string());
+    }
+    parts.extend(args.system_suffix.clone());
+    parts.retain(|part| !part.trim().is_empty());
+    config.system_prompt_suffix = (!parts.is_empty()).then(|| parts.join("\n\n"));
+    Ok(())
+}
+
+/// Add each `--context-cmd` to `context.commands`, named after the command itself
+fn add_context_commands(config: &mut Config, args: &Args) {
+    if args.context_cmd.is_empty() {
+        return;
+    }
+    let commands = config.context.commands.get_or_insert_default();
+    commands.extend(args.context_cmd.iter().map(|command| (command.clone(), command.clone())));
+}
+
+/// Send tracing events to stderr as requested with `-v`/`-vv`, and at the most detailed
+/// level to the `log_file` if one is configured.
+fn init_tracing(verbosity: u8, log_file: Option<&std::path::Path>) {
+    use tracing_subscriber::filter::{LevelFilter, Targets};
+    use tracing_subscriber::prelude::*;
+
+    let level = match verbosity {
+        0 => LevelFilter::OFF,
+        1 => LevelFilter::INFO,
+        _ => LevelFilter::DEBUG,
+    };
+    let terminal = io::stderr().is_terminal();
+    let stderr = tracing_subscriber::fmt::layer()
+        .with_writer(move || {
+            if terminal {
+                eprint!("\r\x1b[K"); // Clear "Thinking..."
+            }
+            io::stderr()
+        })
+        .with_ansi(terminal)
+        .without_time()
+        .with_target(false)
+        // Targets match by prefix, so this covers llm_exec_core too
+        .with_filter(Targets::new().with_target("llm_exec", level));
+
+    let file = log_file.and_then(|path| {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| eprintln!("Warning: Could not open log file {}: {}", path.display(), e))
+            .ok()?;
+        Some(
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::sync::Mutex::new(file))
+                .with_ansi(false)
+                .with_filter(Targets::new().with_target("llm_exec", LevelFilter::DEBUG)),
+        )
+    });
+
+    tracing_subscriber::registry().with(stderr).with(file).init();
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    match run().await {
+        Err(e) if e.is::<providers::Interrupted>() => {
+            eprintln!("Cancelled.");
+            std::process::exit(130);
+        }
+        Err(e) if e.is::<providers::TimedOut>() || e.is::<providers::ApiError>() => {
+            eprintln!("Error: {}", e);
+            std::process::exit(EXIT_API_ERROR);
+        }
+        result => result,
+    }
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    color::init(args.color);
+    QUIET.store(args.quiet, Ordering::Relaxed);
+
+    match &args.command {
+        Some(Commands::ShellInit { shell }) => {
+            print!("{}", shell_integration::script(shell).unwrap_or_default());
+            return Ok(());
+        }
+        Some(Commands::Completions { shell }) => {
+            clap_complete::generate(*shell, &mut Args::command(), "llm-exec", &mut io::stdout());
+            return Ok(());
+        }
+        Some(Commands::Auth { action }) => {
+            let (AuthCommand::Login { provider } | AuthCommand::Logout { provider }) = action;
+            let provider = match provider {
+                Some(provider) => provider.clone(),
+                None => config::load(args.profile.as_deref(), Config::default(), args.strict_config)?
+                    .provider
+                    .unwrap_or_else(|| providers::DEFAULT_PROVIDER.to_string()),
+            };
+            return match action {
+                AuthCommand::Login { .. } => auth::login(&provider),
+                AuthCommand::Logout { .. } => auth::logout(&provider),
+            };
+        }
+        Some(Commands::Config { action }) => {
+            return match action {
+                ConfigCommand::Init => wizard::init().await,
+                ConfigCommand::Get { key } => {
+                    let config = config::load(args.profile.as_deref(), Config::default(), args.strict_config)?;
+                    config_command::get(&config, key.as_deref())
+                }
+                ConfigCommand::Set { key, value } => config_command::set(key, value),
+                ConfigCommand::Edit => config_command::edit(),
+            };
+        }
+        Some(Commands::Models) => {
+            let config = config::load(args.profile.as_deref(), cli_overrides(&args), args.strict_config)?;
+            init_tracing(args.verbose, config.log_file.as_deref());
+            return list_models(&config).await;
+        }
+        Some(Commands::Stats { days }) => {
+            usage::show(*days);
+            return Ok(());
+        }
+        Some(Commands::History { search, limit }) => {
+            return audit::show_history(search.as_deref(), *limit);
+        }
+        Some(Commands::Favorites { search }) => {
+            return favorites::show(search.as_deref());
+        }
+        Some(Commands::Log {
+            search,
+            limit,
+            action,
+            failed,
+            json,
+        }) => {
+            let filter = audit::Filter {
+                search: search.clone(),
+                action: *action,
+                failed: *failed,
+                limit: *limit,
+            };
+            return audit::show(&filter, *json);
+        }
+        Some(Commands::Explain { command }) => {
+            let command = match command.join(" ") {
+                command if command.is_empty() => {
+                    eprint!("Command to explain: ");
+                    io::stderr().flush().unwrap();
+                    terminal::read_line().unwrap_or_default().trim().to_string()
+                }
+                command => command,
+            };
+            if command.is_empty() {
+                eprintln!("Error: No command provided");
+                std::process::exit(EXIT_FAILED);
+            }
+
+            let config = config::load(args.profile.as_deref(), cli_overrides(&args), args.strict_config)?;
+            init_tracing(args.verbose, config.log_file.as_deref());
+            let provider = usage::Metered::new(providers::from_config(&config)?, args.verbose > 0);
+            explain_command(&provider, &command).await?;
+            provider.report();
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    // Piped stdin is the prompt if there isn't one (e.g. `echo "rotate these logs daily" | llm-exec`),
+    // and otherwise context (e.g. `make 2>&1 | llm-exec "fix this"`)
+    let prompt_from_stdin = args.command.is_none() && args.prompt.is_empty() && args.template.is_none() && !args.last && !args.repl;
+    let (piped_prompt, piped_input) = if io::stdin().is_terminal() {
+        (None, None)
+    } else if prompt_from_stdin {
+        (context::read_stdin(), None)
+    } else {
+        (None, context::piped_input())
+    };
+
+    // Get argv[0] (the command name used to invoke this program)
+    let argv0 = std::env::args()
+        .next()
+        .and_then(|p| {
+            std::path::Path::new(&p)
+                .file_name()
+                .map(|s| s.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| "llm-exec".to_string());
+
+    let mut config = config::load(args.profile.as_deref(), cli_overrides(&args), args.strict_config)?;
+    init_tracing(args.verbose, config.log_file.as_deref());
+    add_system_suffix(&mut config, &args)?;
+    add_context_commands(&mut config, &args);
+
+    let resumed = match args.resume.as_deref() {
+        Some(id) => Some(sessions::load((!id.is_empty()).then_some(id))?),
+        None => None,
+    };
+
+    let last = if args.last {
+        Some(audit::last_suggestion()?.ok_or("There's no previous suggestion in the audit log")?)
+    } else {
+        None
+    };
+
+    let tasks = match &args.command {
+        Some(Commands::Batch { file }) => read_tasks(file)?,
+        _ => Vec::new(),
+    };
+
+    let prompt = match &args.command {
+        Some(Commands::Batch { .. }) => tasks.join("\n"),
+        Some(Commands::Fix { hint }) => {
+            let (command, status) = last_command_to_fix(&argv0, history::from_config(&config)?.as_ref()).ok_or("Could not find a previous command to fix")?;
+            fix_prompt(&command, status, &hint.join(" "))
+        }
+        Some(Commands::Ask { question }) if question.is_empty() => {
+            eprint!("What do you want to know? ");
+            io::stderr().flush().unwrap();
+            terminal::read_line().unwrap_or_default().trim().to_string()
+        }
+        Some(Commands::Ask { question }) => question.join(" "),
+        Some(
+            Commands::ShellInit { .. } | Commands::Completions { .. } | Commands::Auth { .. } | Commands::Config { .. } | Commands::Models | Commands::Stats { .. } | Commands::History { .. } | Commands::Favorites { .. } | Commands::Log { .. } | Commands::Explain { .. },
+        ) => {
+            unreachable!("handled above")
+        }
+        None if last.is_some() => last.as_ref().map(|entry| entry.prompt.clone()).unwrap_or_default(),
+        None if args.template.is_some() => {
+            let name = args.template.as_deref().unwrap_or_default();
+            let template = config
+                .templates
+                .as_ref()
+                .and_then(|templates| templates.get(name))
+                .ok_or_else(|| format!("There is no template named '{}' (define it as templates.{} in your config)", name, name))?;
+            prompt::expand_template(template, &args.prompt)?
+        }
+        None if args.prompt.is_empty() && !args.repl => match piped_prompt {
+            Some(prompt) => prompt,
+            None => {
+                eprint!("What do you want to do? ");
+                io::stderr().flush().unwrap();
+                terminal::read_line().unwrap_or_default().trim().to_string()
+            }
+        },
+        None => args.prompt.join(" "),
+    };
+
+    if prompt.is_empty() && !args.repl {
+        eprintln!("Error: No prompt provided");
+        std::process::exit(EXIT_FAILED);
+    }
+
+    // Each batch task gathers its own context, since the relevant history and tool docs depend on it
+    let batch = (!tasks.is_empty()).then(|| (config.clone(), piped_input.clone()));
+    let context = if batch.is_some() {
+        SuggestContext::gather(config, &prompt, piped_input)?
+    } else {
+        gather_context(config, &prompt, piped_input, &args).await?
+    };
+    let asking = matches!(args.command, Some(Commands::Ask { .. }));
+    let mut system_prompt = if asking { context.ask_system_prompt() } else { context.system_prompt(&argv0) };
+    push_mode_instructions(&mut system_prompt, &args);
+    let plan_system_prompt = args.plan.then(|| {
+        let mut plan_system_prompt = context.ask_system_prompt();
+        plan_system_prompt.push(prompt::PLAN_INSTRUCTIONS, false);
+        plan_system_prompt
+    });
+    let SuggestContext {
+        config,
+        shell,
+        context_files,
+        sections,
+        redactor,
+        ..
+    } = context;
+    let provider = usage::Metered::new(providers::from_config(&config)?, args.verbose > 0);
+    let mut compared = Vec::new();
+    for spec in &args.compare {
+        let provider = providers::from_config(&providers::with_model(&config, spec))?;
+        compared.push((spec.clone(), usage::Metered::new(provider, args.verbose > 0)));
+    }
+    let blocklist = risk::Blocklist::new(config.blocklist.as_deref().unwrap_or_default())?;
+    let allowlist = config.allowlist.as_deref().map(risk::Allowlist::new);
+    // Shell history changes with every command (including this one), so it's left out of the cache key
+    let mut cache_context = prompt::build_system_prompt("", &context_files, &sections, &config, &argv0, &shell).text();
+    if args.script {
+        cache_context.push_str(prompt::SCRIPT_INSTRUCTIONS);
+    }
+    // Different sampling and thinking settings are asked for to get different answers
+    cache_context.push_str(&format!("{:?} {:?} {:?} {:?}", config.temperature, config.top_p, config.top_k, config.thinking_budget));
+    let response_cache = if args.no_cache {
+        None
+    } else {
+        let ttl = config.cache_ttl_secs.unwrap_or(response_cache::DEFAULT_TTL_SECS);
+        response_cache::ResponseCache::new(ttl, provider.model(), &cache_context)
+    };
+    let sandbox = args.sandbox.then(|| sandbox::Sandbox::new(&config.sandbox)).transpose()?;
+
+    let images = args.image.iter().map(|path| Image::load(path)).collect::<Result<Vec<_>, _>>()?;
+
+    // Dry run mode - show what would be sent
+    if args.dry_run {
+        let model = provider.model();
+        println!("{} {}", color::paint(color::HEADING, "Model:"), model);
+        println!();
+        println!("{}", color::paint(color::HEADING, "System prompt:"));
+        println!("{}", system_prompt.text());
+        println!();
+        println!("{} {}", color::paint(color::HEADING, "User prompt:"), prompt);
+        if !args.image.is_empty() {
+            let paths: Vec<String> = args.image.iter().map(|path| path.display().to_string()).collect();
+            println!("{} {}", color::paint(color::HEADING, "Images:"), paths.join(", "));
+        }
+        return Ok(());
+    }
+
+    if asking {
+        answer_question(&provider, &system_prompt, &prompt).await?;
+        provider.report();
+        return Ok(());
+    }
+
+    let mut session = Session {
+        provider,
+        history: history::from_config(&config)?,
+        compared,
+        system_prompt,
+        args: &args,
+        shell,
+        blocklist,
+        allowlist,
+        sandbox,
+        audit_log: config.audit_log.unwrap_or(true),
+        response_cache,
+        max_fix_attempts: config.max_fix_attempts.unwrap_or(DEFAULT_MAX_FIX_ATTEMPTS),
+        default_action: default_action_key(config.default_action.as_deref())?,
+        policies: risk::Policies::new(&config.confirm)?,
+        redactor,
+        structured_output: config.structured_output.unwrap_or(false),
+    };
+
+    let mut saved = resumed.unwrap_or_default();
+    if !saved.turns.is_empty() {
+        let updated = saved.updated.get(..16).unwrap_or(&saved.updated).replace('T', " ");
+        note(format!("(continuing session {} from {}, in {})", saved.id, updated, saved.cwd));
+    }
+
+    if args.repl {
+        let initial_prompt = (!prompt.is_empty()).then_some(prompt);
+        return run_repl(&session, initial_prompt, saved).await;
+    }
+    if let Some((config, piped_input)) = batch {
+        if !run_batch(&mut session, &tasks, &config, piped_input, &argv0).await? {
+            std::process::exit(EXIT_FAILED);
+        }
+        return Ok(());
+    }
+
+    let mut conversation = Conversation::from_turns(std::mem::take(&mut saved.turns));
+    let content = match saved.last_outcome.take() {
+        Some(outcome) => format!("{}\n\n{}", outcome, prompt),
+        None => prompt.clone(),
+    };
+    conversation.push_with_images(Role::User, content, images);
+    if let Some(entry) = last {
+        note(format!("(suggested for \"{}\" on {}, in {})", entry.prompt, entry.timestamp.get(..10).unwrap_or(&entry.timestamp), entry.cwd));
+        conversation.push(Role::Assistant, entry.command.unwrap_or(entry.suggestion));
+    }
+
+    if args.print {
+        return print_suggestion(&session, &conversation).await;
+    }
+    if args.output == OutputFormat::Json {
+        return print_json_suggestion(&session, &conversation).await;
+    }
+
+    let outcome = if args.script {
+        run_script(&session, &mut conversation).await?
+    } else if let Some(plan_system_prompt) = &plan_system_prompt {
+        run_plan(&session, plan_system_prompt, &prompt).await?
+    } else if args.agent {
+        run_agent(&session, &config.agent, &mut conversation).await?
+    } else {
+        let outcome = handle_turn_with_fixes(&session, &prompt, &mut conversation).await?;
+        saved.last_outcome = outcome_note(&outcome, &conversation);
+        saved.turns = conversation.turns().to_vec();
+        saved.save();
+        outcome
+    };
+    match outcome {
+        Outcome::Executed(_, status, _) if !status.success() => std::process::exit(EXIT_FAILED),
+        Outcome::Executed(..) | Outcome::Copied(_) => {}
+        Outcome::Cancelled => std::process::exit(EXIT_DECLINED),
+        Outcome::Refused => std::process::exit(EXIT_FAILED),
+        Outcome::Blocked => std::process::exit(EXIT_BLOCKED),
     }
 
     Ok(())
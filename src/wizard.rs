@@ -0,0 +1,169 @@
+use llm_exec_core::config::{self, Config};
+use llm_exec_core::conversation::{Conversation, Role};
+use llm_exec_core::{color, providers, terminal};
+use std::io::{self, Write};
+
+/// `llm-exec config init`: ask a few questions, write a commented config.toml,
+/// and check that the chosen provider answers.
+pub async fn init() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = config::config_dir().ok_or("Could not determine the config directory")?;
+    let path = dir.join("config.toml");
+
+    if path.exists() && !ask_yes_no(&format!("{} already exists. Overwrite it?", path.display()), false)? {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    println!("Providers: {}", providers::PROVIDERS.join(", "));
+    let provider = loop {
+        let answer = ask("Provider", Some("anthropic"))?;
+        if providers::PROVIDERS.contains(&answer.as_str()) {
+            break answer;
+        }
+        println!("Please choose one of: {}", providers::PROVIDERS.join(", "));
+    };
+
+    let mut config = Config {
+        provider: Some(provider.clone()),
+        ..Config::default()
+    };
+
+    match provider.as_str() {
+        "bedrock" => {
+            config.aws_profile = non_empty(ask("AWS profile (Enter for AWS_PROFILE or \"default\")", None)?);
+            config.aws_region = non_empty(ask("AWS region (Enter for AWS_REGION or ~/.aws/config)", None)?);
+        }
+        "ollama" => {
+            config.base_url = non_empty(ask("Ollama URL (Enter for http://localhost:11434)", None)?);
+        }
+        "custom" => {
+            config.base_url = Some(ask_required("Base URL of the OpenAI-compatible API")?);
+            config.api_key_env = non_empty(ask("Environment variable holding the API key (Enter for none)", None)?);
+        }
+        _ => {
+            let default_env = providers::default_api_key_env(&provider).unwrap_or_default();
+            let env = ask("Environment variable holding your API key", Some(default_env))?;
+            if std::env::var_os(&env).is_none() {
+                println!("  {} isn't set yet; add `export {}=...` to your shell profile.", env, env);
+            }
+            config.api_key_env = (env != default_env).then_some(env);
+        }
+    }
+
+    config.model = match providers::default_model(&provider) {
+        Some(default) => non_empty(ask(&format!("Model (Enter for {})", default), None)?),
+        None => Some(ask_required("Model")?),
+    };
+
+    println!();
+    println!("Extra context sent with each request:");
+    config.context.os = Some(ask_yes_no("  Your OS, distribution, and architecture?", true)?);
+    config.context.git = Some(ask_yes_no("  Git branch and status when inside a repository?", false)?);
+    config.context.cwd = Some(ask_yes_no("  A listing of the current directory?", false)?);
+
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(&path, render(&config))?;
+    println!();
+    println!("Wrote {}", path.display());
+
+    print!("Checking that the provider responds... ");
+    io::stdout().flush()?;
+    match check_connectivity(&config).await {
+        Ok(()) => {
+            println!("{}", color::paint(color::SUCCESS, "ok"));
+            println!();
+            println!("You're all set. Try: llm-exec list the largest files here");
+        }
+        Err(e) => println!("{} {}", color::paint(color::ERROR, "failed:"), e),
+    }
+
+    Ok(())
+}
+
+/// Send a tiny request to make sure the provider is reachable and the credentials work
+async fn check_connectivity(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let provider = providers::from_config(config)?;
+    let mut conversation = Conversation::new();
+    conversation.push(Role::User, "ping");
+    provider
+        .complete(&"Reply with the single word: pong".into(), conversation.turns())
+        .await?;
+    Ok(())
+}
+
+/// A commented config.toml with the wizard's answers
+fn render(config: &Config) -> String {
+    let mut out = String::from("# llm-exec configuration. See the README for every available key.\n\n");
+
+    out.push_str("# anthropic, openai, ollama, gemini, bedrock, or custom\n");
+    push_key(&mut out, "provider", config.provider.as_deref());
+    push_key(&mut out, "base_url", config.base_url.as_deref());
+    push_key(&mut out, "api_key_env", config.api_key_env.as_deref());
+    push_key(&mut out, "aws_profile", config.aws_profile.as_deref());
+    push_key(&mut out, "aws_region", config.aws_region.as_deref());
+    match (&config.model, config.provider.as_deref().and_then(providers::default_model)) {
+        (None, Some(default)) => out.push_str(&format!("# model = {}\n", toml::Value::String(default.to_string()))),
+        (model, _) => push_key(&mut out, "model", model.as_deref()),
+    }
+
+    out.push_str("\n# Extra instructions for the model, e.g. \"Prefer ripgrep over grep.\"\n");
+    out.push_str("# system_prompt_suffix = \"\"\n");
+    out.push_str("\n# Number of shell history lines to include\n");
+    out.push_str("# history_lines = 1000\n");
+
+    out.push_str("\n[context]\n");
+    for (key, value) in [("os", config.context.os), ("git", config.context.git), ("cwd", config.context.cwd)] {
+        out.push_str(&format!("{} = {}\n", key, value.unwrap_or(false)));
+    }
+
+    out
+}
+
+/// Append `key = "value"`, or nothing if the value is unset
+fn push_key(out: &mut String, key: &str, value: Option<&str>) {
+    if let Some(value) = value {
+        out.push_str(&format!("{} = {}\n", key, toml::Value::String(value.to_string())));
+    }
+}
+
+/// Print a prompt and read a trimmed answer, failing at end of input so the wizard can't spin
+fn read_answer(prompt: &str) -> Result<String, Box<dyn std::error::Error>> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let line = terminal::read_line().ok_or("No input; run `llm-exec config init` from a terminal")?;
+    Ok(line.trim().to_string())
+}
+
+fn ask(question: &str, default: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    let answer = match default {
+        Some(default) => read_answer(&format!("{} [{}]: ", question, default))?,
+        None => read_answer(&format!("{}: ", question))?,
+    };
+    if answer.is_empty() {
+        Ok(default.unwrap_or_default().to_string())
+    } else {
+        Ok(answer)
+    }
+}
+
+fn ask_required(question: &str) -> Result<String, Box<dyn std::error::Error>> {
+    loop {
+        let answer = ask(question, None)?;
+        if !answer.is_empty() {
+            return Ok(answer);
+        }
+    }
+}
+
+fn ask_yes_no(question: &str, default: bool) -> Result<bool, Box<dyn std::error::Error>> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    Ok(match read_answer(&format!("{} [{}]: ", question, hint))?.to_lowercase().as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => default,
+    })
+}
+
+fn non_empty(value: String) -> Option<String> {
+    (!value.is_empty()).then_some(value)
+}